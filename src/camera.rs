@@ -0,0 +1,979 @@
+use crate::color::D65_WHITE;
+
+/// Per-model color data, from the Adobe DNG converter matrices as collected
+/// by rawloader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Camera {
+    /// Make as written in IFD0.
+    pub make: &'static str,
+    /// Model as written in IFD0.
+    pub model: &'static str,
+    /// XYZ (D65) to camera matrix, row by row, scaled by 10000.
+    pub xyz_to_cam: [i16; 9],
+    /// Black and white levels of each raw bit depth the body writes.
+    pub levels: &'static [Levels],
+    /// Pixels to crop from the top, right, bottom and left edges.
+    pub crop: [u16; 4],
+}
+
+/// The black and white level of raw data with `bits` bits per sample.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Levels {
+    pub bits: u16,
+    pub black: u16,
+    pub white: u16,
+}
+
+impl Camera {
+    /// Finds the entry for the Make and Model strings of IFD0.
+    pub fn lookup(make: &str, model: &str) -> Option<&'static Camera> {
+        CAMERAS.iter().find(|camera| {
+            camera.make.eq_ignore_ascii_case(make.trim())
+                && camera.model.eq_ignore_ascii_case(model.trim())
+        })
+    }
+
+    /// The levels for raw data with `bps` bits per sample.
+    pub fn levels(&self, bps: u16) -> Option<Levels> {
+        self.levels
+            .iter()
+            .copied()
+            .find(|levels| levels.bits == bps)
+    }
+
+    /// The XYZ to camera matrix as floats.
+    pub fn xyz_to_cam(&self) -> [[f32; 3]; 3] {
+        let mut matrix = [[0.0; 3]; 3];
+        for (i, row) in matrix.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.xyz_to_cam[i * 3 + j] as f32 / 10000.0;
+            }
+        }
+        matrix
+    }
+
+    /// R, G, B multipliers that make a D65 white neutral, normalized to
+    /// green. Each row of the color matrix gives the camera response to white.
+    pub fn daylight_multipliers(&self) -> [f32; 3] {
+        let multipliers = self.xyz_to_cam().map(|row| {
+            let white: f32 = row.iter().zip(D65_WHITE).map(|(a, b)| a * b).sum();
+            1.0 / white
+        });
+        let green = multipliers[1];
+        multipliers.map(|multiplier| multiplier / green)
+    }
+}
+
+/// Stands in for bodies missing from [`CAMERAS`] in color conversion: the
+/// D7500, whose response is close to that of other current Nikon sensors.
+/// It lists no levels, so those still come from the file.
+pub static FALLBACK: Camera = Camera {
+    make: "NIKON CORPORATION",
+    model: "NIKON D7500",
+    xyz_to_cam: [8813, -3210, -1036, -4703, 12868, 2021, -1054, 1940, 6129],
+    levels: &[],
+    crop: [0, 0, 0, 0],
+};
+
+pub static CAMERAS: &[Camera] = &[
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX B700",
+        xyz_to_cam: [14387, -6014, -1299, -1357, 9975, 1616, 467, 1047, 4744],
+        levels: &[Levels {
+            bits: 12,
+            black: 200,
+            white: 4000,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P330",
+        xyz_to_cam: [10321, -3920, -931, -2750, 11146, 1824, -442, 1545, 5539],
+        levels: &[Levels {
+            bits: 14,
+            black: 3210,
+            white: 65000,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P340",
+        xyz_to_cam: [10321, -3920, -931, -2750, 11146, 1824, -442, 1545, 5539],
+        levels: &[Levels {
+            bits: 12,
+            black: 200,
+            white: 3800,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P6000",
+        xyz_to_cam: [9698, -3367, -914, -4706, 12584, 2368, -837, 968, 5801],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P7000",
+        xyz_to_cam: [11432, -3679, -1111, -3169, 11239, 2202, -791, 1380, 4455],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P7100",
+        xyz_to_cam: [11053, -4269, -1024, -1976, 10182, 2088, -526, 1263, 4469],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3800,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "COOLPIX P7800",
+        xyz_to_cam: [10321, -3920, -931, -2750, 11146, 1824, -442, 1545, 5539],
+        levels: &[Levels {
+            bits: 16,
+            black: 3195,
+            white: 65000,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "E5400",
+        xyz_to_cam: [9349, -2987, -1001, -7919, 15766, 2266, -2098, 2680, 6839],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON",
+        model: "E8400",
+        xyz_to_cam: [7842, -2320, -992, -8154, 15718, 2599, -1098, 1342, 7560],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "COOLPIX A",
+        xyz_to_cam: [8198, -2239, -724, -4871, 12389, 2798, -1043, 2050, 7181],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15892,
+        }],
+        crop: [0, 44, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 AW1",
+        xyz_to_cam: [6588, -1305, -693, -3277, 10987, 2634, -355, 2016, 5106],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3300,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 J1",
+        xyz_to_cam: [8994, -2667, -865, -4594, 12324, 2552, -699, 1786, 6260],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3300,
+        }],
+        crop: [0, 0, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 J2",
+        xyz_to_cam: [8994, -2667, -865, -4594, 12324, 2552, -699, 1786, 6260],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3300,
+        }],
+        crop: [0, 0, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 J3",
+        xyz_to_cam: [6588, -1305, -693, -3277, 10987, 2634, -355, 2016, 5106],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3300,
+        }],
+        crop: [0, 0, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 J5",
+        xyz_to_cam: [7520, -2518, -645, -3844, 12102, 1945, -913, 2249, 6835],
+        levels: &[Levels {
+            bits: 12,
+            black: 200,
+            white: 3800,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 S2",
+        xyz_to_cam: [6612, -1342, -618, -3338, 11055, 2623, -174, 1792, 5075],
+        levels: &[Levels {
+            bits: 12,
+            black: 200,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 V1",
+        xyz_to_cam: [8994, -2667, -865, -4594, 12324, 2552, -699, 1786, 6260],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 V2",
+        xyz_to_cam: [6588, -1305, -693, -3277, 10987, 2634, -355, 2016, 5106],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON 1 V3",
+        xyz_to_cam: [5958, -1559, -571, -4021, 11453, 2939, -634, 1548, 5087],
+        levels: &[Levels {
+            bits: 12,
+            black: 200,
+            white: 4000,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D1",
+        xyz_to_cam: [7559, -2130, -965, -7611, 15713, 1972, -2478, 3042, 8290],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 12, 12, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D100",
+        xyz_to_cam: [5902, -933, -782, -8983, 16719, 2354, -1402, 1455, 6464],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D1X",
+        xyz_to_cam: [7702, -2245, -975, -9114, 17242, 1875, -2679, 3055, 8521],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D200",
+        xyz_to_cam: [8367, -2248, -763, -8758, 16447, 2422, -1527, 1550, 8053],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 0, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D2X",
+        xyz_to_cam: [10231, -2769, -1255, -8301, 15900, 2552, -797, 680, 7148],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 32, 8, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3",
+        xyz_to_cam: [8139, -2171, -663, -8747, 16541, 2295, -1925, 2008, 8093],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15892,
+        }],
+        crop: [0, 0, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D300",
+        xyz_to_cam: [9030, -1992, -715, -8465, 16302, 2255, -2689, 3217, 8069],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3808,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15236,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3000",
+        xyz_to_cam: [8736, -2458, -935, -9075, 16894, 2251, -1354, 1242, 8263],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 2, 3, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D300S",
+        xyz_to_cam: [9030, -1992, -716, -8465, 16302, 2256, -2689, 3217, 8069],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15236,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3100",
+        xyz_to_cam: [7911, -2167, -813, -5327, 13150, 2408, -1288, 2483, 7968],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [2, 24, 2, 8],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3200",
+        xyz_to_cam: [7013, -1408, -635, -5268, 12902, 2640, -1470, 2801, 7379],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 48, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3300",
+        xyz_to_cam: [6988, -1384, -714, -5631, 13410, 2447, -1485, 2204, 7318],
+        levels: &[Levels {
+            bits: 12,
+            black: 150,
+            white: 3880,
+        }],
+        crop: [8, 8, 8, 8],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3400",
+        xyz_to_cam: [6988, -1384, -714, -5631, 13410, 2447, -1485, 2204, 7318],
+        levels: &[Levels {
+            bits: 12,
+            black: 150,
+            white: 3880,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D3X",
+        xyz_to_cam: [7171, -1986, -648, -8085, 15555, 2718, -2170, 2512, 7457],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15892,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D4",
+        xyz_to_cam: [8598, -2848, -857, -5618, 13606, 2195, -1002, 1773, 7137],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 50, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D40",
+        xyz_to_cam: [6992, -1668, -806, -8138, 15748, 2543, -874, 850, 7897],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 2, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D40X",
+        xyz_to_cam: [8819, -2543, -911, -9025, 16928, 2151, -1329, 1213, 8449],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 0, 0, 4],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D4S",
+        xyz_to_cam: [8598, -2848, -857, -5618, 13606, 2195, -1002, 1773, 7137],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 192,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 768,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5",
+        xyz_to_cam: [9200, -3522, -992, -5755, 13803, 2117, -753, 1486, 6338],
+        levels: &[Levels {
+            bits: 14,
+            black: 400,
+            white: 15520,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D50",
+        xyz_to_cam: [7732, -2422, -789, -8238, 15884, 2498, -859, 783, 7330],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 2, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D500",
+        xyz_to_cam: [8813, -3210, -1036, -4703, 12868, 2021, -1054, 1940, 6129],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 100,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 400,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5000",
+        xyz_to_cam: [7309, -1403, -519, -8474, 16008, 2622, -2433, 2826, 8064],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3767,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5100",
+        xyz_to_cam: [8198, -2239, -724, -4871, 12389, 2798, -1043, 2050, 7181],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15892,
+        }],
+        crop: [0, 46, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5200",
+        xyz_to_cam: [8322, -3112, -1047, -6367, 14342, 2179, -988, 1638, 6394],
+        levels: &[Levels {
+            bits: 14,
+            black: 0,
+            white: 15892,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5300",
+        xyz_to_cam: [6988, -1384, -714, -5631, 13410, 2447, -1485, 2204, 7318],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5500",
+        xyz_to_cam: [8821, -2938, -785, -4178, 12142, 2287, -824, 1651, 6860],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D5600",
+        xyz_to_cam: [8821, -2938, -785, -4178, 12142, 2287, -824, 1651, 6860],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D60",
+        xyz_to_cam: [8736, -2458, -935, -9075, 16894, 2251, -1354, 1242, 8263],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D600",
+        xyz_to_cam: [8178, -2245, -609, -4857, 12394, 2776, -1207, 2086, 7298],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 50, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D610",
+        xyz_to_cam: [8178, -2245, -609, -4857, 12394, 2776, -1207, 2086, 7298],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 50, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D70",
+        xyz_to_cam: [7732, -2422, -789, -8238, 15884, 2498, -859, 783, 7330],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 2, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D700",
+        xyz_to_cam: [8139, -2171, -663, -8747, 16541, 2295, -1925, 2008, 8093],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D7000",
+        xyz_to_cam: [8198, -2239, -724, -4871, 12389, 2798, -1043, 2050, 7181],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15892,
+            },
+        ],
+        crop: [0, 46, 2, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D70s",
+        xyz_to_cam: [7732, -2422, -789, -8238, 15884, 2498, -859, 783, 7330],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 4095,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D7100",
+        xyz_to_cam: [8322, -3112, -1047, -6367, 14342, 2179, -988, 1638, 6394],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D7200",
+        xyz_to_cam: [8322, -3112, -1047, -6367, 14342, 2179, -988, 1638, 6394],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3972,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15892,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D750",
+        xyz_to_cam: [9020, -2890, -715, -4535, 12436, 2348, -934, 1919, 7086],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D7500",
+        xyz_to_cam: [8813, -3210, -1036, -4703, 12868, 2021, -1054, 1940, 6129],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 100,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 400,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D80",
+        xyz_to_cam: [8629, -2410, -883, -9055, 16940, 2171, -1490, 1363, 8520],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3880,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D800",
+        xyz_to_cam: [7866, -2108, -555, -4869, 12483, 2681, -1176, 2069, 7501],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 48, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D800E",
+        xyz_to_cam: [7866, -2108, -555, -4869, 12483, 2681, -1176, 2069, 7501],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 48, 0, 2],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D810",
+        xyz_to_cam: [9369, -3195, -791, -4488, 12430, 2301, -893, 1796, 6872],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 150,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 600,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D850",
+        xyz_to_cam: [10405, -3755, -1270, -5461, 13787, 1793, -1040, 2015, 6785],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 100,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 400,
+                white: 15520,
+            },
+        ],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON D90",
+        xyz_to_cam: [7309, -1403, -519, -8474, 16008, 2622, -2434, 2826, 8064],
+        levels: &[Levels {
+            bits: 12,
+            black: 0,
+            white: 3767,
+        }],
+        crop: [0, 0, 0, 0],
+    },
+    Camera {
+        make: "NIKON CORPORATION",
+        model: "NIKON Df",
+        xyz_to_cam: [8598, -2848, -857, -5618, 13606, 2195, -1002, 1773, 7137],
+        levels: &[
+            Levels {
+                bits: 12,
+                black: 0,
+                white: 3880,
+            },
+            Levels {
+                bits: 14,
+                black: 0,
+                white: 15520,
+            },
+        ],
+        crop: [0, 50, 0, 2],
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::white_balance::DAYLIGHT;
+
+    #[test]
+    fn looks_up_make_and_model_loosely() {
+        let camera = Camera::lookup("nikon corporation", " NIKON D7500 ").unwrap();
+        assert_eq!(camera.model, "NIKON D7500");
+        assert_eq!(Camera::lookup("NIKON CORPORATION", "NIKON Z 99"), None);
+        assert_eq!(Camera::lookup("Canon", "NIKON D7500"), None);
+    }
+
+    #[test]
+    fn picks_the_levels_of_the_bit_depth() {
+        let camera = Camera::lookup("NIKON CORPORATION", "NIKON D7500").unwrap();
+        let levels = camera.levels(14).unwrap();
+        assert_eq!((levels.black, levels.white), (400, 15520));
+        assert_eq!(camera.levels(12).unwrap().white, 3880);
+        assert_eq!(camera.levels(16), None);
+    }
+
+    #[test]
+    fn derives_daylight_multipliers_from_the_matrix() {
+        let camera = Camera::lookup("NIKON CORPORATION", "NIKON D7500").unwrap();
+        let multipliers = camera.daylight_multipliers();
+        assert_eq!(multipliers[1], 1.0);
+        for (value, expected) in multipliers.iter().zip(DAYLIGHT) {
+            assert!((value - expected).abs() < 0.01, "{multipliers:?}");
+        }
+    }
+
+    #[test]
+    fn lists_each_body_once() {
+        // A repeated model would be shadowed by its first entry
+        for camera in CAMERAS {
+            let first = Camera::lookup(camera.make, camera.model);
+            assert_eq!(first, Some(camera), "{} listed twice", camera.model);
+        }
+    }
+
+    #[test]
+    fn every_entry_is_usable() {
+        for camera in CAMERAS {
+            let name = camera.model;
+            assert!(
+                camera.daylight_multipliers().iter().all(|m| *m > 0.0),
+                "{name}"
+            );
+            for levels in camera.levels {
+                assert!(levels.black < levels.white, "{name}");
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+/// The color spaces the conversion stage can output. All are linear; gamma
+/// encoding is left to the caller.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    AdobeRgb,
+    /// Adapted from D65 to its D50 white point with the Bradford transform.
+    ProPhotoRgb,
+    /// CIE XYZ relative to a D65 white.
+    Xyz,
+}
+
+/// The D65 white point in XYZ, normalized to Y = 1.
+pub const D65_WHITE: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+impl ColorSpace {
+    /// The matrix from XYZ (D65) to this space.
+    pub fn from_xyz(self) -> [[f32; 3]; 3] {
+        match self {
+            ColorSpace::Srgb => [
+                [3.240454, -1.537138, -0.498531],
+                [-0.969266, 1.876011, 0.041556],
+                [0.055643, -0.204026, 1.057225],
+            ],
+            ColorSpace::AdobeRgb => [
+                [2.041369, -0.564946, -0.344694],
+                [-0.969266, 1.876011, 0.041556],
+                [0.013447, -0.118390, 1.01541],
+            ],
+            ColorSpace::ProPhotoRgb => [
+                [1.403215, -0.223140, -0.101553],
+                [-0.526272, 1.481661, 0.017031],
+                [-0.011191, 0.018230, 0.911443],
+            ],
+            ColorSpace::Xyz => [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+}
+
+/// The matrix from white balanced camera RGB to `space`, given the camera's
+/// XYZ to camera matrix. Each row of `xyz_to_cam` is first scaled so that a
+/// D65 white gives equal camera values, which white balance produces for a
+/// neutral subject; camera white then maps to the white of `space`.
+///
+/// `None` if the matrix cannot be inverted.
+pub fn camera_to_space(xyz_to_cam: [[f32; 3]; 3], space: ColorSpace) -> Option<[[f32; 3]; 3]> {
+    let mut cam_from_xyz = xyz_to_cam;
+    for row in cam_from_xyz.iter_mut() {
+        let white: f32 = row.iter().zip(D65_WHITE).map(|(a, b)| a * b).sum();
+        if white.abs() < f32::EPSILON {
+            return None;
+        }
+        for value in row.iter_mut() {
+            *value /= white;
+        }
+    }
+    Some(multiply(space.from_xyz(), invert(cam_from_xyz)?))
+}
+
+/// Applies `matrix` to interleaved R, G, B values. Out of gamut colors are
+/// clipped at zero; values above 1.0 are kept for highlight handling.
+pub fn convert_colors(rgb: &mut [f32], matrix: [[f32; 3]; 3]) {
+    for pixel in rgb.chunks_exact_mut(3) {
+        let input = [pixel[0], pixel[1], pixel[2]];
+        for (value, row) in pixel.iter_mut().zip(matrix) {
+            *value = row
+                .iter()
+                .zip(input)
+                .map(|(a, b)| a * b)
+                .sum::<f32>()
+                .max(0.0);
+        }
+    }
+}
+
+fn multiply(a: [[f32; 3]; 3], b: [[f32; 3]; 3]) -> [[f32; 3]; 3] {
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| a[i][k] * b[k][j]).sum();
+        }
+    }
+    out
+}
+
+fn invert(m: [[f32; 3]; 3]) -> Option<[[f32; 3]; 3]> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant: f32 = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum();
+    if determinant.abs() < f32::EPSILON {
+        return None;
+    }
+    let mut out = [[0.0; 3]; 3];
+    for (i, row) in out.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            // The inverse is the transposed cofactor matrix over the determinant
+            *value = cofactor(j, i) / determinant;
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Camera;
+
+    fn apply(matrix: [[f32; 3]; 3], rgb: [f32; 3]) -> [f32; 3] {
+        matrix.map(|row| row.iter().zip(rgb).map(|(a, b)| a * b).sum())
+    }
+
+    #[test]
+    fn camera_white_maps_to_the_white_of_each_space() {
+        let camera = Camera::lookup("NIKON CORPORATION", "NIKON D7500").unwrap();
+        for space in [
+            ColorSpace::Srgb,
+            ColorSpace::AdobeRgb,
+            ColorSpace::ProPhotoRgb,
+            ColorSpace::Xyz,
+        ] {
+            let matrix = camera_to_space(camera.xyz_to_cam(), space).unwrap();
+            let white = apply(matrix, [1.0; 3]);
+            let expected = if space == ColorSpace::Xyz {
+                D65_WHITE
+            } else {
+                [1.0; 3]
+            };
+            for (value, expected) in white.iter().zip(expected) {
+                assert!((value - expected).abs() < 1e-3, "{space:?}: {white:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn singular_matrices_have_no_conversion() {
+        let singular = [[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [0.0, 0.0, 1.0]];
+        assert_eq!(camera_to_space(singular, ColorSpace::Srgb), None);
+    }
+
+    #[test]
+    fn converts_and_clips_at_zero() {
+        let matrix = [[1.0, 0.0, 0.0], [0.5, 0.5, 0.0], [-1.0, 0.0, 0.0]];
+        let mut rgb = [2.0, 0.5, 1.0, 0.2, 0.4, 0.6];
+        convert_colors(&mut rgb, matrix);
+        assert_eq!(rgb, [2.0, 1.25, 0.0, 0.2, 0.3, 0.0]);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::error::NefError;
+use crate::utils::Endian;
+
+// Substitution tables of Nikon's MakerNote cipher, indexed by the low byte of
+// the serial number and by the XOR of the shutter count bytes respectively.
+const SERIAL_MAP: [u8; 256] = [
+    0xc1, 0xbf, 0x6d, 0x0d, 0x59, 0xc5, 0x13, 0x9d, 0x83, 0x61, 0x6b, 0x4f, 0xc7, 0x7f, 0x3d, 0x3d,
+    0x53, 0x59, 0xe3, 0xc7, 0xe9, 0x2f, 0x95, 0xa7, 0x95, 0x1f, 0xdf, 0x7f, 0x2b, 0x29, 0xc7, 0x0d,
+    0xdf, 0x07, 0xef, 0x71, 0x89, 0x3d, 0x13, 0x3d, 0x3b, 0x13, 0xfb, 0x0d, 0x89, 0xc1, 0x65, 0x1f,
+    0xb3, 0x0d, 0x6b, 0x29, 0xe3, 0xfb, 0xef, 0xa3, 0x6b, 0x47, 0x7f, 0x95, 0x35, 0xa7, 0x47, 0x4f,
+    0xc7, 0xf1, 0x59, 0x95, 0x35, 0x11, 0x29, 0x61, 0xf1, 0x3d, 0xb3, 0x2b, 0x0d, 0x43, 0x89, 0xc1,
+    0x9d, 0x9d, 0x89, 0x65, 0xf1, 0xe9, 0xdf, 0xbf, 0x3d, 0x7f, 0x53, 0x97, 0xe5, 0xe9, 0x95, 0x17,
+    0x1d, 0x3d, 0x8b, 0xfb, 0xc7, 0xe3, 0x67, 0xa7, 0x07, 0xf1, 0x71, 0xa7, 0x53, 0xb5, 0x29, 0x89,
+    0xe5, 0x2b, 0xa7, 0x17, 0x29, 0xe9, 0x4f, 0xc5, 0x65, 0x6d, 0x6b, 0xef, 0x0d, 0x89, 0x49, 0x2f,
+    0xb3, 0x43, 0x53, 0x65, 0x1d, 0x49, 0xa3, 0x13, 0x89, 0x59, 0xef, 0x6b, 0xef, 0x65, 0x1d, 0x0b,
+    0x59, 0x13, 0xe3, 0x4f, 0x9d, 0xb3, 0x29, 0x43, 0x2b, 0x07, 0x1d, 0x95, 0x59, 0x59, 0x47, 0xfb,
+    0xe5, 0xe9, 0x61, 0x47, 0x2f, 0x35, 0x7f, 0x17, 0x7f, 0xef, 0x7f, 0x95, 0x95, 0x71, 0xd3, 0xa3,
+    0x0b, 0x71, 0xa3, 0xad, 0x0b, 0x3b, 0xb5, 0xfb, 0xa3, 0xbf, 0x4f, 0x83, 0x1d, 0xad, 0xe9, 0x2f,
+    0x71, 0x65, 0xa3, 0xe5, 0x07, 0x35, 0x3d, 0x0d, 0xb5, 0xe9, 0xe5, 0x47, 0x3b, 0x9d, 0xef, 0x35,
+    0xa3, 0xbf, 0xb3, 0xdf, 0x53, 0xd3, 0x97, 0x53, 0x49, 0x71, 0x07, 0x35, 0x61, 0x71, 0x2f, 0x43,
+    0x2f, 0x11, 0xdf, 0x17, 0x97, 0xfb, 0x95, 0x3b, 0x7f, 0x6b, 0xd3, 0x25, 0xbf, 0xad, 0xc7, 0xc5,
+    0xc5, 0xb5, 0x8b, 0xef, 0x2f, 0xd3, 0x07, 0x6b, 0x25, 0x49, 0x95, 0x25, 0x49, 0x6d, 0x71, 0xc7,
+];
+
+const KEY_MAP: [u8; 256] = [
+    0xa7, 0xbc, 0xc9, 0xad, 0x91, 0xdf, 0x85, 0xe5, 0xd4, 0x78, 0xd5, 0x17, 0x46, 0x7c, 0x29, 0x4c,
+    0x4d, 0x03, 0xe9, 0x25, 0x68, 0x11, 0x86, 0xb3, 0xbd, 0xf7, 0x6f, 0x61, 0x22, 0xa2, 0x26, 0x34,
+    0x2a, 0xbe, 0x1e, 0x46, 0x14, 0x68, 0x9d, 0x44, 0x18, 0xc2, 0x40, 0xf4, 0x7e, 0x5f, 0x1b, 0xad,
+    0x0b, 0x94, 0xb6, 0x67, 0xb4, 0x0b, 0xe1, 0xea, 0x95, 0x9c, 0x66, 0xdc, 0xe7, 0x5d, 0x6c, 0x05,
+    0xda, 0xd5, 0xdf, 0x7a, 0xef, 0xf6, 0xdb, 0x1f, 0x82, 0x4c, 0xc0, 0x68, 0x47, 0xa1, 0xbd, 0xee,
+    0x39, 0x50, 0x56, 0x4a, 0xdd, 0xdf, 0xa5, 0xf8, 0xc6, 0xda, 0xca, 0x90, 0xca, 0x01, 0x42, 0x9d,
+    0x8b, 0x0c, 0x73, 0x43, 0x75, 0x05, 0x94, 0xde, 0x24, 0xb3, 0x80, 0x34, 0xe5, 0x2c, 0xdc, 0x9b,
+    0x3f, 0xca, 0x33, 0x45, 0xd0, 0xdb, 0x5f, 0xf5, 0x52, 0xc3, 0x21, 0xda, 0xe2, 0x22, 0x72, 0x6b,
+    0x3e, 0xd0, 0x5b, 0xa8, 0x87, 0x8c, 0x06, 0x5d, 0x0f, 0xdd, 0x09, 0x19, 0x93, 0xd0, 0xb9, 0xfc,
+    0x8b, 0x0f, 0x84, 0x60, 0x33, 0x1c, 0x9b, 0x45, 0xf1, 0xf0, 0xa3, 0x94, 0x3a, 0x12, 0x77, 0x33,
+    0x4d, 0x44, 0x78, 0x28, 0x3c, 0x9e, 0xfd, 0x65, 0x57, 0x16, 0x94, 0x6b, 0xfb, 0x59, 0xd0, 0xc8,
+    0x22, 0x36, 0xdb, 0xd2, 0x63, 0x98, 0x43, 0xa1, 0x04, 0x87, 0x86, 0xf7, 0xa6, 0x26, 0xbb, 0xd6,
+    0x59, 0x4d, 0xbf, 0x6a, 0x2e, 0xaa, 0x2b, 0xef, 0xe6, 0x78, 0xb6, 0x4e, 0xe0, 0x2f, 0xdc, 0x7c,
+    0xbe, 0x57, 0x19, 0x32, 0x7e, 0x2a, 0xd0, 0xb8, 0xba, 0x29, 0x00, 0x3c, 0x52, 0x7d, 0xa8, 0x49,
+    0x3b, 0x2d, 0xeb, 0x25, 0x49, 0xfa, 0xa3, 0xaa, 0x39, 0xa7, 0xc5, 0xa7, 0x50, 0x11, 0x36, 0xfb,
+    0xc6, 0x67, 0x4a, 0xf5, 0xa5, 0x12, 0x65, 0x7e, 0xb0, 0xdf, 0xaf, 0x4e, 0xb3, 0x61, 0x7f, 0x2f,
+];
+
+/// Key of the XOR cipher Nikon applies to parts of the MakerNote, derived
+/// from SerialNumber (0x1D) and ShutterCount (0xA7).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NikonKey {
+    serial: u8,
+    key: u8,
+}
+
+impl NikonKey {
+    pub fn new(serial_number: &str, shutter_count: u32) -> NikonKey {
+        // Non-digit characters are folded in as their code modulo 10, so
+        // alphanumeric serials still give a key
+        let serial = serial_number.bytes().fold(0_u32, |serial, c| {
+            let digit = if c.is_ascii_digit() { c - b'0' } else { c % 10 };
+            serial.wrapping_mul(10).wrapping_add(digit as u32)
+        });
+        let key = shutter_count
+            .to_le_bytes()
+            .iter()
+            .fold(0, |key, byte| key ^ byte);
+        NikonKey {
+            serial: serial as u8,
+            key,
+        }
+    }
+
+    /// Decrypts (or encrypts, the cipher is symmetric) `data` in place. The
+    /// key stream restarts for every call, so pass the whole encrypted range.
+    pub fn decrypt(&self, data: &mut [u8]) {
+        let ci = SERIAL_MAP[self.serial as usize];
+        let mut cj = KEY_MAP[self.key as usize];
+        let mut ck: u8 = 0x60;
+        for byte in data {
+            cj = cj.wrapping_add(ci.wrapping_mul(ck));
+            ck = ck.wrapping_add(1);
+            *byte ^= cj;
+        }
+    }
+}
+
+/// Reads the four digit version every encrypted block starts with, e.g.
+/// `0204` as 204.
+fn block_version(block: &'static str, data: &[u8]) -> Result<(String, u32), NefError> {
+    let digits = data
+        .get(0..4)
+        .ok_or(NefError::OutOfBounds { offset: 0, len: 4 })?;
+    let text = String::from_utf8_lossy(digits).into_owned();
+    if !digits.iter().all(u8::is_ascii_digit) {
+        return Err(NefError::UnsupportedVersion {
+            block,
+            version: text,
+        });
+    }
+    let number = digits
+        .iter()
+        .fold(0, |number, digit| number * 10 + (digit - b'0') as u32);
+    Ok((text, number))
+}
+
+/// Copies `data` and decrypts everything from `start` on.
+fn decrypted(data: &[u8], start: usize, key: Option<&NikonKey>) -> Result<Vec<u8>, NefError> {
+    let key = key.ok_or(NefError::MissingDecryptionKey)?;
+    if data.len() < start {
+        return Err(NefError::OutOfBounds {
+            offset: start,
+            len: 0,
+        });
+    }
+    let mut data = data.to_vec();
+    key.decrypt(&mut data[start..]);
+    Ok(data)
+}
+
+/// As-shot white balance from ColorBalance (0x97).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorBalance {
+    pub version: String,
+    /// White balance multipliers in R, G, G, B order, 256 (or 1024 on some
+    /// bodies) being unity.
+    pub wb_rggb_levels: [u16; 4],
+}
+
+impl ColorBalance {
+    /// Decodes a ColorBalance block stored in `endian` byte order. Versions
+    /// from 0200 on are encrypted and need `key`.
+    pub fn parse(
+        data: &[u8],
+        endian: Endian,
+        key: Option<&NikonKey>,
+    ) -> Result<ColorBalance, NefError> {
+        let (version, number) = block_version("ColorBalance", data)?;
+        let unsupported = || NefError::UnsupportedVersion {
+            block: "ColorBalance",
+            version: version.clone(),
+        };
+
+        // Offset of the levels and the channel order they are stored in, as
+        // indices into RGGB
+        let (data, offset, order) = match number {
+            100 => (data.to_vec(), 72, [0, 3, 1, 2]),
+            102 => (data.to_vec(), 10, [0, 1, 2, 3]),
+            103 => (data.to_vec(), 20, [0, 1, 3, 2]),
+            200..=216 => {
+                // The D50 (0205) encrypts from the version on, the others
+                // leave 280 bytes in between
+                let start = if number == 205 { 4 } else { 284 };
+                let data = decrypted(data, start, key)?;
+                let shift = b"66666>666;6A;:;55"[(number - 200) as usize] - b'0';
+                let order = if shift & 1 == 1 {
+                    [1, 0, 3, 2]
+                } else {
+                    [0, 1, 2, 3]
+                };
+                (data, start + (shift & !1) as usize, order)
+            }
+            _ => return Err(unsupported()),
+        };
+
+        let levels = data.get(offset..offset + 8).ok_or_else(unsupported)?;
+        let mut wb_rggb_levels = [0; 4];
+        for (i, channel) in order.into_iter().enumerate() {
+            wb_rggb_levels[channel] = endian.u16(&levels[i * 2..]);
+        }
+        Ok(ColorBalance {
+            version,
+            wb_rggb_levels,
+        })
+    }
+}
+
+/// Lens information from LensData (0x98). Values are converted from Nikon's
+/// logarithmic encoding; fields missing from older versions are `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LensData {
+    pub version: String,
+    /// Distance of the exit pupil from the focal plane in millimeters.
+    pub exit_pupil_position: Option<f32>,
+    pub af_aperture: Option<f32>,
+    pub focus_position: Option<u8>,
+    /// Focus distance in meters.
+    pub focus_distance: Option<f32>,
+    /// Focal length in millimeters.
+    pub focal_length: Option<f32>,
+    pub lens_id_number: u8,
+    /// Number of f-stops the lens can stop down.
+    pub lens_f_stops: f32,
+    pub min_focal_length: f32,
+    pub max_focal_length: f32,
+    pub max_aperture_at_min_focal: f32,
+    pub max_aperture_at_max_focal: f32,
+    pub mcu_version: u8,
+    pub effective_max_aperture: Option<f32>,
+}
+
+impl LensData {
+    /// Decodes a LensData block. Versions from 0200 on are encrypted and
+    /// need `key`.
+    pub fn parse(data: &[u8], key: Option<&NikonKey>) -> Result<LensData, NefError> {
+        let (version, number) = block_version("LensData", data)?;
+        let data = match number {
+            100 | 101 => data.to_vec(),
+            201..=204 => decrypted(data, 4, key)?,
+            _ => {
+                return Err(NefError::UnsupportedVersion {
+                    block: "LensData",
+                    version,
+                });
+            }
+        };
+
+        let focal = |value: u8| 5.0 * 2_f32.powf(value as f32 / 24.0);
+        let aperture = |value: u8| 2_f32.powf(value as f32 / 24.0);
+        // The layouts only differ in where the lens identification starts
+        // and whether the focus and exit pupil fields precede it
+        let (lens_start, focus) = match number {
+            100 => (6, None),
+            204 => (12, Some(10)),
+            _ => (11, Some(9)),
+        };
+        let byte = |offset: usize| {
+            data.get(offset)
+                .copied()
+                .ok_or(NefError::OutOfBounds { offset, len: 1 })
+        };
+        let optional = |offset: usize| focus.and_then(|_| data.get(offset).copied());
+
+        Ok(LensData {
+            exit_pupil_position: optional(4)
+                .filter(|&value| value != 0)
+                .map(|value| 2048.0 / value as f32),
+            af_aperture: optional(5).map(aperture),
+            focus_position: optional(8),
+            focus_distance: focus
+                .and_then(|offset| data.get(offset).copied())
+                .map(|value| 0.01 * 10_f32.powf(value as f32 / 40.0)),
+            focal_length: focus
+                .and_then(|offset| data.get(offset + 1).copied())
+                .map(focal),
+            lens_id_number: byte(lens_start)?,
+            lens_f_stops: byte(lens_start + 1)? as f32 / 12.0,
+            min_focal_length: focal(byte(lens_start + 2)?),
+            max_focal_length: focal(byte(lens_start + 3)?),
+            max_aperture_at_min_focal: aperture(byte(lens_start + 4)?),
+            max_aperture_at_max_focal: aperture(byte(lens_start + 5)?),
+            mcu_version: byte(lens_start + 6)?,
+            effective_max_aperture: optional(lens_start + 7).map(aperture),
+            version,
+        })
+    }
+}
+
+/// Camera state from ShotInfo (0x91). The layout differs for almost every
+/// body, so besides the version only the firmware version is decoded and the
+/// decrypted block is kept for model specific lookups.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShotInfo {
+    pub version: String,
+    /// Camera firmware version, e.g. `1.01`, on bodies that record it.
+    pub firmware_version: Option<String>,
+    /// The whole block, decrypted.
+    pub data: Vec<u8>,
+}
+
+impl ShotInfo {
+    /// Decodes a ShotInfo block. Versions from 0200 on are encrypted and
+    /// need `key`.
+    pub fn parse(data: &[u8], key: Option<&NikonKey>) -> Result<ShotInfo, NefError> {
+        let (version, number) = block_version("ShotInfo", data)?;
+        let data = if number >= 200 {
+            decrypted(data, 4, key)?
+        } else {
+            data.to_vec()
+        };
+
+        // Bodies that record it store the firmware version as "d.dd" right
+        // after the block version
+        let firmware_version = data
+            .get(4..8)
+            .filter(|text| {
+                text[0].is_ascii_digit()
+                    && text[1] == b'.'
+                    && text[2].is_ascii_digit()
+                    && text[3].is_ascii_digit()
+            })
+            .map(|text| String::from_utf8_lossy(text).into_owned());
+
+        Ok(ShotInfo {
+            version,
+            firmware_version,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serial 1234567 (0x12D687) and shutter count 12345 (0x3039) select
+    /// SERIAL_MAP[0x87] = 0x13 and KEY_MAP[0x39 ^ 0x30] = 0x78. The stream
+    /// starts at 0x78 + 0x13 * 0x60 = 0x98, then 0x98 + 0x13 * 0x61 = 0xCB.
+    const KEY_STREAM: [u8; 6] = [0x98, 0xCB, 0x11, 0x6A, 0xD6, 0x55];
+
+    fn key() -> NikonKey {
+        NikonKey::new("1234567", 12345)
+    }
+
+    #[test]
+    fn generates_the_key_stream() {
+        let mut data = [0; 6];
+        key().decrypt(&mut data);
+        assert_eq!(data, KEY_STREAM);
+        key().decrypt(&mut data);
+        assert_eq!(data, [0; 6]);
+    }
+
+    #[test]
+    fn folds_letters_of_the_serial_number() {
+        // 'A' is 65, so it counts as the digit 5
+        assert_eq!(NikonKey::new("12A", 7), NikonKey::new("125", 7));
+    }
+
+    #[test]
+    fn reads_plain_color_balance() {
+        let mut data = b"0102".to_vec();
+        data.resize(10, 0);
+        data.extend_from_slice(&[0x01, 0xF0, 0x01, 0x00, 0x01, 0x00, 0x01, 0x80]);
+        let color_balance = ColorBalance::parse(&data, Endian::Big, None).unwrap();
+        assert_eq!(color_balance.version, "0102");
+        assert_eq!(color_balance.wb_rggb_levels, [0x1F0, 0x100, 0x100, 0x180]);
+    }
+
+    #[test]
+    fn decrypts_color_balance() {
+        // Version 0204 encrypts from byte 284 on and keeps the levels six
+        // bytes later. Little-endian R G G B levels 0x1F0, 0x100, 0x100 and
+        // 0x180, encrypted with the key stream.
+        let mut data = b"0204".to_vec();
+        data.resize(284, 0);
+        data.extend_from_slice(&KEY_STREAM);
+        data.extend_from_slice(&[0x17, 0x8D, 0x44, 0x0E, 0xED, 0xDF, 0x62, 0xF8]);
+        let color_balance = ColorBalance::parse(&data, Endian::Little, Some(&key())).unwrap();
+        assert_eq!(color_balance.wb_rggb_levels, [0x1F0, 0x100, 0x100, 0x180]);
+
+        assert!(matches!(
+            ColorBalance::parse(&data, Endian::Little, None),
+            Err(NefError::MissingDecryptionKey)
+        ));
+        assert!(matches!(
+            ColorBalance::parse(b"0999", Endian::Little, Some(&key())),
+            Err(NefError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[test]
+    fn reads_lens_data() {
+        let data = [b'0', b'1', b'0', b'0', 0, 0, 7, 72, 48, 96, 24, 48, 3];
+        let lens_data = LensData::parse(&data, None).unwrap();
+        assert_eq!(lens_data.lens_id_number, 7);
+        assert_eq!(lens_data.lens_f_stops, 6.0);
+        assert!((lens_data.min_focal_length - 20.0).abs() < 1e-3);
+        assert!((lens_data.max_focal_length - 80.0).abs() < 1e-3);
+        assert!((lens_data.max_aperture_at_min_focal - 2.0).abs() < 1e-3);
+        assert!((lens_data.max_aperture_at_max_focal - 4.0).abs() < 1e-3);
+        assert_eq!(lens_data.mcu_version, 3);
+        assert_eq!(lens_data.focus_distance, None);
+    }
+
+    #[test]
+    fn decrypts_shot_info() {
+        let data = [b'0', b'2', b'1', b'0', 0xA9, 0xE5, 0x21, 0x5B];
+        let shot_info = ShotInfo::parse(&data, Some(&key())).unwrap();
+        assert_eq!(shot_info.firmware_version.as_deref(), Some("1.01"));
+        assert_eq!(&shot_info.data[..4], b"0210");
+    }
+}
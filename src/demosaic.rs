@@ -0,0 +1,319 @@
+use crate::error::NefError;
+use crate::raw_image::RawImage;
+
+/// The interpolation used to fill in the two colors each CFA pixel lacks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DemosaicMethod {
+    /// Averages the nearest samples of each color. Fast, but soft and prone
+    /// to color fringes at edges.
+    Bilinear,
+    /// Bilinear corrected by the gradient of the sampled color, after Malvar,
+    /// He and Cutler (2004).
+    MalvarHeCutler,
+    /// Patterned Pixel Grouping: interpolates along the direction with the
+    /// smaller gradient, as in dcraw.
+    #[default]
+    Ppg,
+}
+
+/// Interpolates the CFA mosaic `mosaic` of `width` x `height` samples into
+/// interleaved R, G, B values, three per pixel. `cfa` gives the color (0
+/// red, 1 green, 2 blue) of each position of the 2x2 tile in row-major order,
+/// as returned by `NefFile::cfa_pattern_2x2`. Results are clipped at zero
+/// only, so values white balance pushed above 1.0 reach the highlight stage.
+///
+/// Any of the four Bayer phases works with every method. Other layouts, and
+/// images too small for the larger kernels, fall back to bilinear.
+pub fn demosaic(
+    mosaic: &[f32],
+    width: usize,
+    height: usize,
+    cfa: [u8; 4],
+    method: DemosaicMethod,
+) -> Result<Vec<f32>, NefError> {
+    let needed = width * height;
+    if mosaic.len() < needed {
+        return Err(NefError::OutOfBounds {
+            offset: 0,
+            len: needed,
+        });
+    }
+    let mosaic = Mosaic {
+        data: &mosaic[..needed],
+        width,
+        height,
+        cfa: cfa.map(|color| color.min(2)),
+    };
+
+    let mut pixels = bilinear(&mosaic);
+    if is_bayer(mosaic.cfa) && width >= 2 && height >= 2 {
+        match method {
+            DemosaicMethod::Bilinear => {}
+            DemosaicMethod::MalvarHeCutler => malvar_he_cutler(&mosaic, &mut pixels),
+            DemosaicMethod::Ppg => ppg(&mosaic, &mut pixels),
+        }
+    }
+    Ok(pixels.into_iter().flatten().collect())
+}
+
+/// Greens on one diagonal of the tile, red and blue on the other.
+fn is_bayer(cfa: [u8; 4]) -> bool {
+    let mut sorted = cfa;
+    sorted.sort_unstable();
+    sorted == [0, 1, 1, 2] && (cfa[0] == cfa[3] || cfa[1] == cfa[2])
+}
+
+struct Mosaic<'a> {
+    data: &'a [f32],
+    width: usize,
+    height: usize,
+    cfa: [u8; 4],
+}
+
+impl Mosaic<'_> {
+    fn color(&self, row: usize, col: usize) -> usize {
+        self.cfa[RawImage::cfa_index(row, col)] as usize
+    }
+
+    fn at(&self, row: usize, col: usize, dr: isize, dc: isize) -> f32 {
+        let row = row as isize + dr;
+        let col = col as isize + dc;
+        self.data[row as usize * self.width + col as usize]
+    }
+}
+
+/// Each missing color is the mean of the samples of that color among the
+/// eight neighbors. Only neighbors inside the image count, so borders need no
+/// special case.
+fn bilinear(mosaic: &Mosaic) -> Vec<[f32; 3]> {
+    let (width, height) = (mosaic.width, mosaic.height);
+    let mut pixels = Vec::with_capacity(width * height);
+    for row in 0..height {
+        for col in 0..width {
+            let mut sums = [0.0; 3];
+            let mut counts = [0_u32; 3];
+            for neighbor_row in row.saturating_sub(1)..(row + 2).min(height) {
+                for neighbor_col in col.saturating_sub(1)..(col + 2).min(width) {
+                    let color = mosaic.color(neighbor_row, neighbor_col);
+                    sums[color] += mosaic.data[neighbor_row * width + neighbor_col];
+                    counts[color] += 1;
+                }
+            }
+            let here = mosaic.color(row, col);
+            let mut pixel = [0.0; 3];
+            for (color, value) in pixel.iter_mut().enumerate() {
+                *value = if color == here {
+                    mosaic.data[row * width + col]
+                } else if counts[color] > 0 {
+                    sums[color] / counts[color] as f32
+                } else {
+                    0.0
+                };
+            }
+            pixels.push(pixel);
+        }
+    }
+    pixels
+}
+
+/// The 5x5 gradient-corrected kernels of Malvar, He and Cutler. The two
+/// pixels along each border keep the bilinear result.
+fn malvar_he_cutler(mosaic: &Mosaic, pixels: &mut [[f32; 3]]) {
+    for row in 2..mosaic.height.saturating_sub(2) {
+        for col in 2..mosaic.width.saturating_sub(2) {
+            let at = |dr, dc| mosaic.at(row, col, dr, dc);
+            let center = at(0, 0);
+            let horizontal_1 = at(0, -1) + at(0, 1);
+            let vertical_1 = at(-1, 0) + at(1, 0);
+            let horizontal_2 = at(0, -2) + at(0, 2);
+            let vertical_2 = at(-2, 0) + at(2, 0);
+            let diagonal = at(-1, -1) + at(-1, 1) + at(1, -1) + at(1, 1);
+
+            let here = mosaic.color(row, col);
+            let pixel = &mut pixels[row * mosaic.width + col];
+            if here == 1 {
+                // Red or blue sits left and right, the other one above and below
+                let row_color = mosaic.color(row, col + 1);
+                pixel[row_color] = (5.0 * center + 4.0 * horizontal_1 - horizontal_2 - diagonal
+                    + 0.5 * vertical_2)
+                    / 8.0;
+                pixel[2 - row_color] = (5.0 * center + 4.0 * vertical_1 - vertical_2 - diagonal
+                    + 0.5 * horizontal_2)
+                    / 8.0;
+            } else {
+                pixel[1] = (4.0 * center + 2.0 * (horizontal_1 + vertical_1)
+                    - (horizontal_2 + vertical_2))
+                    / 8.0;
+                pixel[2 - here] =
+                    (6.0 * center + 2.0 * diagonal - 1.5 * (horizontal_2 + vertical_2)) / 8.0;
+            }
+            pixel[here] = center;
+            for value in pixel.iter_mut() {
+                *value = value.max(0.0);
+            }
+        }
+    }
+}
+
+/// Patterned Pixel Grouping on top of the bilinear result, which is kept for
+/// the pixels too close to the border for the PPG neighborhoods.
+fn ppg(mosaic: &Mosaic, pixels: &mut [[f32; 3]]) {
+    let (width, height) = (mosaic.width, mosaic.height);
+    let index = |row: usize, col: usize, dr: isize, dc: isize| {
+        (row as isize + dr) as usize * width + (col as isize + dc) as usize
+    };
+    let directions = [(0, 1), (1, 0)];
+
+    // Green at red and blue pixels, along the direction of least change
+    for row in 3..height.saturating_sub(3) {
+        let start = 3 + (mosaic.color(row, 3) & 1);
+        for col in (start..width.saturating_sub(3)).step_by(2) {
+            let color = mosaic.color(row, col);
+            let green = |dr, dc| pixels[index(row, col, dr, dc)][1];
+            let same = |dr, dc| pixels[index(row, col, dr, dc)][color];
+            let center = same(0, 0);
+
+            let mut guess = [0.0; 2];
+            let mut diff = [0.0; 2];
+            for (i, (dr, dc)) in directions.into_iter().enumerate() {
+                guess[i] = (green(-dr, -dc) + center + green(dr, dc)) * 2.0
+                    - same(-2 * dr, -2 * dc)
+                    - same(2 * dr, 2 * dc);
+                diff[i] = ((same(-2 * dr, -2 * dc) - center).abs()
+                    + (same(2 * dr, 2 * dc) - center).abs()
+                    + (green(-dr, -dc) - green(dr, dc)).abs())
+                    * 3.0
+                    + ((green(3 * dr, 3 * dc) - green(dr, dc)).abs()
+                        + (green(-3 * dr, -3 * dc) - green(-dr, -dc)).abs())
+                        * 2.0;
+            }
+            let i = usize::from(diff[0] > diff[1]);
+            let (dr, dc) = directions[i];
+            let (low, high) = min_max(green(dr, dc), green(-dr, -dc));
+            pixels[row * width + col][1] = (guess[i] / 4.0).clamp(low, high);
+        }
+    }
+
+    // Red and blue at green pixels, from the color differences of the two
+    // neighbors of each color
+    for row in 1..height - 1 {
+        let start = 1 + (mosaic.color(row, 2) & 1);
+        for col in (start..width - 1).step_by(2) {
+            let row_color = mosaic.color(row, col + 1);
+            let center_green = pixels[row * width + col][1];
+            for (color, (dr, dc)) in [(row_color, (0, 1)), (2 - row_color, (1, 0))] {
+                let before = pixels[index(row, col, -dr, -dc)];
+                let after = pixels[index(row, col, dr, dc)];
+                let value =
+                    (before[color] + after[color] + 2.0 * center_green - before[1] - after[1])
+                        / 2.0;
+                pixels[row * width + col][color] = value.max(0.0);
+            }
+        }
+    }
+
+    // Blue at red pixels and red at blue pixels, along the diagonal of least
+    // change
+    for row in 1..height - 1 {
+        let start = 1 + (mosaic.color(row, 1) & 1);
+        for col in (start..width - 1).step_by(2) {
+            let color = 2 - mosaic.color(row, col);
+            let center_green = pixels[row * width + col][1];
+
+            let mut guess = [0.0; 2];
+            let mut diff = [0.0; 2];
+            for (i, (dr, dc)) in [(1, 1), (1, -1)].into_iter().enumerate() {
+                let before = pixels[index(row, col, -dr, -dc)];
+                let after = pixels[index(row, col, dr, dc)];
+                diff[i] = (before[color] - after[color]).abs()
+                    + (before[1] - center_green).abs()
+                    + (after[1] - center_green).abs();
+                guess[i] = before[color] + after[color] + 2.0 * center_green - before[1] - after[1];
+            }
+            let value = if diff[0] != diff[1] {
+                guess[usize::from(diff[0] > diff[1])] / 2.0
+            } else {
+                (guess[0] + guess[1]) / 4.0
+            };
+            pixels[row * width + col][color] = value.max(0.0);
+        }
+    }
+}
+
+fn min_max(a: f32, b: f32) -> (f32, f32) {
+    if a < b { (a, b) } else { (b, a) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RGGB: [u8; 4] = [0, 1, 1, 2];
+    const METHODS: [DemosaicMethod; 3] = [
+        DemosaicMethod::Bilinear,
+        DemosaicMethod::MalvarHeCutler,
+        DemosaicMethod::Ppg,
+    ];
+
+    /// A `width` x `height` mosaic of a flat `rgb` color.
+    fn flat(width: usize, height: usize, cfa: [u8; 4], rgb: [f32; 3]) -> Vec<f32> {
+        (0..width * height)
+            .map(|i| rgb[cfa[RawImage::cfa_index(i / width, i % width)] as usize])
+            .collect()
+    }
+
+    #[test]
+    fn bilinear_averages_the_neighbors() {
+        // R G R
+        // G B G
+        // R G R
+        let mosaic = [0.1, 0.2, 0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+        let rgb = demosaic(&mosaic, 3, 3, RGGB, DemosaicMethod::Bilinear).unwrap();
+        assert_eq!(rgb[..3], [0.1, 0.3, 0.5]);
+        // Center: the four reds on the corners, the four greens on the sides
+        let center = &rgb[12..15];
+        assert!((center[0] - 0.5).abs() < 1e-6 && (center[1] - 0.5).abs() < 1e-6);
+        assert_eq!(center[2], 0.5);
+        // Top center: two reds beside it, the blue below
+        assert_eq!(rgb[3..6], [0.2, 0.2, 0.5]);
+    }
+
+    #[test]
+    fn flat_colors_stay_flat() {
+        let rgb = [0.4, 0.6, 0.3];
+        for cfa in [RGGB, [1, 0, 2, 1], [1, 2, 0, 1], [2, 1, 1, 0]] {
+            let mosaic = flat(12, 10, cfa, rgb);
+            for method in METHODS {
+                let out = demosaic(&mosaic, 12, 10, cfa, method).unwrap();
+                assert_eq!(out.len(), 12 * 10 * 3);
+                for pixel in out.chunks_exact(3) {
+                    for (value, expected) in pixel.iter().zip(rgb) {
+                        assert!(
+                            (value - expected).abs() < 1e-5,
+                            "{method:?} {cfa:?}: {pixel:?}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_values_above_white() {
+        let mosaic = flat(12, 10, RGGB, [2.0, 1.0, 1.5]);
+        for method in METHODS {
+            let out = demosaic(&mosaic, 12, 10, RGGB, method).unwrap();
+            let pixel = &out[(5 * 12 + 5) * 3..][..3];
+            assert!((pixel[0] - 2.0).abs() < 1e-5, "{method:?}: {pixel:?}");
+            assert!((pixel[2] - 1.5).abs() < 1e-5, "{method:?}: {pixel:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_a_short_mosaic() {
+        assert!(matches!(
+            demosaic(&[0.0; 11], 4, 3, RGGB, DemosaicMethod::Ppg),
+            Err(NefError::OutOfBounds { .. })
+        ));
+    }
+}
@@ -0,0 +1,293 @@
+use crate::color::{ColorSpace, convert_colors};
+use crate::demosaic::{DemosaicMethod, demosaic};
+use crate::error::NefError;
+use crate::nef::NefFile;
+use crate::raw_image::{Area, Crop};
+use crate::white_balance::{WhiteBalance, apply_white_balance};
+use image::{DynamicImage, ImageBuffer, Pixel, Rgb};
+
+/// What happens to colors pushed above white by exposure or the color matrix.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Highlights {
+    /// Clip each channel at white. Bright saturated colors shift in hue.
+    #[default]
+    Clip,
+    /// Desaturate towards the pixel's luminance until it fits, keeping hue.
+    Blend,
+}
+
+/// A tone curve applied to linear values before gamma encoding.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum ToneCurve {
+    #[default]
+    Linear,
+    /// Input, output pairs in 0.0..=1.0 with increasing input, joined by
+    /// straight lines. Inputs outside the points keep the end values.
+    Points(Vec<(f32, f32)>),
+}
+
+/// The transfer function that encodes the linear output.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum Gamma {
+    /// No encoding, for further processing.
+    Linear,
+    /// The piecewise sRGB curve, also a good fit for Adobe RGB.
+    #[default]
+    Srgb,
+    /// A pure power law, e.g. 1.8 for ProPhoto RGB.
+    Power(f32),
+}
+
+/// Settings of the raw development pipeline: black and white level, white
+/// balance, demosaic, color matrix, exposure, highlights, tone curve, gamma,
+/// crop and orientation, in that order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Develop {
+    /// Files that record no as-shot multipliers get
+    /// [`WhiteBalance::Daylight`].
+    pub white_balance: WhiteBalance,
+    pub demosaic: DemosaicMethod,
+    pub color_space: ColorSpace,
+    /// Exposure compensation in EV.
+    pub exposure: f32,
+    pub highlights: Highlights,
+    pub tone_curve: ToneCurve,
+    pub gamma: Gamma,
+    /// The part of the sensor the output covers.
+    pub crop: Crop,
+    /// Rotate and mirror the result as the EXIF Orientation tag says.
+    pub orientation: bool,
+}
+
+impl Default for Develop {
+    /// As-shot white balance, PPG demosaic, sRGB with its gamma, default crop
+    /// and upright.
+    fn default() -> Develop {
+        Develop {
+            white_balance: WhiteBalance::default(),
+            demosaic: DemosaicMethod::default(),
+            color_space: ColorSpace::default(),
+            exposure: 0.0,
+            highlights: Highlights::default(),
+            tone_curve: ToneCurve::default(),
+            gamma: Gamma::default(),
+            crop: Crop::Default,
+            orientation: true,
+        }
+    }
+}
+
+impl Develop {
+    pub fn new() -> Develop {
+        Develop::default()
+    }
+
+    pub fn white_balance(mut self, white_balance: WhiteBalance) -> Develop {
+        self.white_balance = white_balance;
+        self
+    }
+
+    pub fn demosaic(mut self, demosaic: DemosaicMethod) -> Develop {
+        self.demosaic = demosaic;
+        self
+    }
+
+    pub fn color_space(mut self, color_space: ColorSpace) -> Develop {
+        self.color_space = color_space;
+        self
+    }
+
+    pub fn exposure(mut self, exposure: f32) -> Develop {
+        self.exposure = exposure;
+        self
+    }
+
+    pub fn highlights(mut self, highlights: Highlights) -> Develop {
+        self.highlights = highlights;
+        self
+    }
+
+    pub fn tone_curve(mut self, tone_curve: ToneCurve) -> Develop {
+        self.tone_curve = tone_curve;
+        self
+    }
+
+    pub fn gamma(mut self, gamma: Gamma) -> Develop {
+        self.gamma = gamma;
+        self
+    }
+
+    pub fn crop(mut self, crop: Crop) -> Develop {
+        self.crop = crop;
+        self
+    }
+
+    pub fn orientation(mut self, orientation: bool) -> Develop {
+        self.orientation = orientation;
+        self
+    }
+
+    /// Develops `nef_file` to 8 bits per channel.
+    pub fn develop_rgb8(
+        &self,
+        nef_file: &NefFile,
+    ) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>, NefError> {
+        let image = self.develop_to(nef_file, |value| (value * 255.0).round() as u8)?;
+        Ok(self
+            .orient(nef_file, DynamicImage::ImageRgb8(image))
+            .into_rgb8())
+    }
+
+    /// Develops `nef_file` to 16 bits per channel.
+    pub fn develop_rgb16(
+        &self,
+        nef_file: &NefFile,
+    ) -> Result<ImageBuffer<Rgb<u16>, Vec<u16>>, NefError> {
+        let image = self.develop_to(nef_file, |value| (value * 65535.0).round() as u16)?;
+        Ok(self
+            .orient(nef_file, DynamicImage::ImageRgb16(image))
+            .into_rgb16())
+    }
+
+    /// Runs the pipeline up to the crop and converts the encoded values with
+    /// `quantize`.
+    fn develop_to<T>(
+        &self,
+        nef_file: &NefFile,
+        quantize: impl Fn(f32) -> T,
+    ) -> Result<ImageBuffer<Rgb<T>, Vec<T>>, NefError>
+    where
+        Rgb<T>: Pixel<Subpixel = T>,
+    {
+        let (width, height, rgb) = self.develop(nef_file)?;
+        let data: Vec<T> = rgb.into_iter().map(quantize).collect();
+        ImageBuffer::from_raw(width as u32, height as u32, data).ok_or_else(|| {
+            NefError::InvalidMosaic(format!("developed image does not fill {width}x{height}"))
+        })
+    }
+
+    /// The developed image as interleaved R, G, B values in 0.0..=1.0, with
+    /// its width and height.
+    pub fn develop(&self, nef_file: &NefFile) -> Result<(usize, usize, Vec<f32>), NefError> {
+        let raw_image = nef_file.raw_image()?;
+        let (width, height) = (raw_image.width, raw_image.height);
+        let area = raw_image.crop_area(self.crop);
+
+        let mut samples = raw_image.subtract_black();
+        let multipliers = nef_file
+            .wb_multipliers(self.white_balance)
+            .or_else(|| nef_file.wb_multipliers(WhiteBalance::Daylight));
+        if let Some(multipliers) = multipliers {
+            apply_white_balance(&mut samples, width, raw_image.cfa, multipliers);
+        }
+        let mut rgb = demosaic(&samples, width, height, raw_image.cfa, self.demosaic)?;
+        drop(samples);
+
+        if let Some(matrix) = nef_file.color_matrix(self.color_space) {
+            convert_colors(&mut rgb, matrix);
+        }
+
+        let scale = self.exposure.exp2();
+        let curve = ToneCurveLut::new(&self.tone_curve);
+        for pixel in rgb.chunks_exact_mut(3) {
+            for value in pixel.iter_mut() {
+                *value *= scale;
+            }
+            self.recover_highlights(pixel);
+            for value in pixel.iter_mut() {
+                *value = self.encode(curve.apply(*value));
+            }
+        }
+
+        if area != Area::full(width, height) {
+            let mut cropped = Vec::with_capacity(area.width * area.height * 3);
+            for line in rgb.chunks_exact(width * 3).skip(area.top).take(area.height) {
+                cropped.extend_from_slice(&line[area.left * 3..(area.left + area.width) * 3]);
+            }
+            return Ok((area.width, area.height, cropped));
+        }
+        Ok((width, height, rgb))
+    }
+
+    fn recover_highlights(&self, pixel: &mut [f32]) {
+        let max = pixel.iter().copied().fold(0.0, f32::max);
+        if max <= 1.0 {
+            return;
+        }
+        match self.highlights {
+            Highlights::Clip => {}
+            Highlights::Blend => {
+                // Rec. 709 luminance; white itself cannot be kept below 1.0
+                let luminance =
+                    (0.2126 * pixel[0] + 0.7152 * pixel[1] + 0.0722 * pixel[2]).min(1.0);
+                let t = (1.0 - luminance) / (max - luminance).max(f32::EPSILON);
+                for value in pixel.iter_mut() {
+                    *value = luminance + (*value - luminance) * t;
+                }
+            }
+        }
+        for value in pixel.iter_mut() {
+            *value = value.clamp(0.0, 1.0);
+        }
+    }
+
+    fn encode(&self, value: f32) -> f32 {
+        let value = value.clamp(0.0, 1.0);
+        match self.gamma {
+            Gamma::Linear => value,
+            Gamma::Srgb if value <= 0.0031308 => value * 12.92,
+            Gamma::Srgb => 1.055 * value.powf(1.0 / 2.4) - 0.055,
+            Gamma::Power(gamma) => value.powf(1.0 / gamma),
+        }
+    }
+
+    fn orient(&self, nef_file: &NefFile, mut image: DynamicImage) -> DynamicImage {
+        if self.orientation {
+            image.apply_orientation(nef_file.orientation());
+        }
+        image
+    }
+}
+
+/// A tone curve sampled at 4096 points, so each value costs a lookup and an
+/// interpolation.
+struct ToneCurveLut(Option<Vec<f32>>);
+
+impl ToneCurveLut {
+    const SIZE: usize = 4096;
+
+    fn new(tone_curve: &ToneCurve) -> ToneCurveLut {
+        let ToneCurve::Points(points) = tone_curve else {
+            return ToneCurveLut(None);
+        };
+        if points.is_empty() {
+            return ToneCurveLut(None);
+        }
+        let table = (0..=Self::SIZE)
+            .map(|i| {
+                let x = i as f32 / Self::SIZE as f32;
+                let upper = points.iter().position(|&(input, _)| input >= x);
+                match upper {
+                    None => points[points.len() - 1].1,
+                    Some(0) => points[0].1,
+                    Some(upper) => {
+                        let (x0, y0) = points[upper - 1];
+                        let (x1, y1) = points[upper];
+                        y0 + (y1 - y0) * (x - x0) / (x1 - x0).max(f32::EPSILON)
+                    }
+                }
+            })
+            .collect();
+        ToneCurveLut(Some(table))
+    }
+
+    fn apply(&self, value: f32) -> f32 {
+        let Some(table) = &self.0 else {
+            return value;
+        };
+        let position = value.clamp(0.0, 1.0) * Self::SIZE as f32;
+        let index = (position as usize).min(Self::SIZE - 1);
+        let fraction = position - index as f32;
+        table[index] + (table[index + 1] - table[index]) * fraction
+    }
+}
@@ -0,0 +1,338 @@
+use std::cmp::Reverse;
+
+use image::{DynamicImage, ImageBuffer, Luma};
+
+use crate::camera::FALLBACK;
+use crate::error::NefError;
+use crate::huffmanv2::LosslessJpeg;
+use crate::ifd::{IfdEntryTag, IfdValue};
+use crate::nef::NefFile;
+use crate::raw_image::RawImage;
+use crate::tiff_writer::TiffDirectory;
+use crate::utils::Endian;
+
+/// Longest side of the thumbnail in IFD0.
+const THUMBNAIL_SIZE: u32 = 256;
+
+/// Width and height of the lossless JPEG tiles.
+const TILE_SIZE: usize = 256;
+
+/// CalibrationIlluminant code of the D65 matrices in the camera database.
+const ILLUMINANT_D65: u16 = 21;
+
+/// How the raw data of a DNG is stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum DngCompression {
+    /// 16 bits per sample, exactly as decoded.
+    #[default]
+    Uncompressed,
+    /// Lossless JPEG in tiles, typically half the size.
+    LosslessJpeg,
+}
+
+/// Settings of the DNG export.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DngOptions {
+    pub compression: DngCompression,
+    /// Embed the largest JPEG preview of the NEF, and a thumbnail made from
+    /// it in IFD0.
+    pub preview: bool,
+}
+
+impl Default for DngOptions {
+    fn default() -> DngOptions {
+        DngOptions {
+            compression: DngCompression::default(),
+            preview: true,
+        }
+    }
+}
+
+impl DngOptions {
+    pub fn new() -> DngOptions {
+        DngOptions::default()
+    }
+
+    pub fn compression(mut self, compression: DngCompression) -> DngOptions {
+        self.compression = compression;
+        self
+    }
+
+    pub fn preview(mut self, preview: bool) -> DngOptions {
+        self.preview = preview;
+        self
+    }
+}
+
+/// `nef_file` as a DNG: the decoded CFA data with its levels, crops and
+/// color calibration, the EXIF and GPS IFDs with the MakerNote, and the
+/// embedded preview.
+///
+/// The camera database holds one matrix per body, calibrated for D65, so
+/// only ColorMatrix1 is written. DNG readers need it for any color image,
+/// so bodies not in the database get that of [`FALLBACK`], as in
+/// development.
+pub fn dng(nef_file: &NefFile, options: &DngOptions) -> Result<Vec<u8>, NefError> {
+    let raw_image = nef_file.raw_image()?;
+    let mut ifd0 = TiffDirectory::from_nef(nef_file);
+
+    ifd0.set(IfdEntryTag::DNGVersion, IfdValue::Bytes(vec![1, 4, 0, 0]));
+    ifd0.set(
+        IfdEntryTag::DNGBackwardVersion,
+        IfdValue::Bytes(vec![1, 1, 0, 0]),
+    );
+    let meta = &nef_file.meta_data;
+    let model = [meta.make.as_deref(), meta.model.as_deref()]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ");
+    ifd0.set(IfdEntryTag::UniqueCameraModel, IfdValue::Ascii(model));
+    ifd0.set_orientation(Some(nef_file.orientation()));
+    ifd0.set(
+        IfdEntryTag::OriginalRawFileName,
+        IfdValue::Ascii(nef_file.file_name.clone()),
+    );
+
+    let camera = nef_file.camera().unwrap_or(&FALLBACK);
+    let matrix = camera.xyz_to_cam.map(|value| (value as i32, 10000));
+    ifd0.set(
+        IfdEntryTag::ColorMatrix1,
+        IfdValue::SRationals(matrix.to_vec()),
+    );
+    ifd0.set(
+        IfdEntryTag::CalibrationIlluminant1,
+        IfdValue::Shorts(vec![ILLUMINANT_D65]),
+    );
+    if let Some(multipliers) = nef_file.as_shot_wb() {
+        // The camera values of a neutral subject, the inverse of the multipliers
+        let neutral = multipliers.map(|multiplier| ((1e6 / multiplier).round() as u32, 1_000_000));
+        ifd0.set(
+            IfdEntryTag::AsShotNeutral,
+            IfdValue::Rationals(neutral.to_vec()),
+        );
+    }
+    if let Some(private_data) = maker_note_private_data(nef_file) {
+        ifd0.set(IfdEntryTag::DNGPrivateData, IfdValue::Bytes(private_data));
+    }
+
+    let mut raw = TiffDirectory::new();
+    set_raw(&mut raw, raw_image, options.compression)?;
+
+    // The largest preview that decodes, which the thumbnail is made from
+    let mut previews = if options.preview {
+        nef_file.previews()
+    } else {
+        Vec::new()
+    };
+    previews.sort_by_key(|preview| Reverse(preview.width * preview.height));
+    let preview = previews.iter().find_map(|preview| {
+        let jpeg = nef_file.preview_data(preview).ok()?;
+        let image = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg).ok()?;
+        Some((jpeg, image))
+    });
+
+    match preview {
+        Some((jpeg, image)) => {
+            // IFD0 is the thumbnail; the raw data and preview are SubIFDs
+            let thumbnail = if image.width().max(image.height()) > THUMBNAIL_SIZE {
+                image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8()
+            } else {
+                image.to_rgb8()
+            };
+            ifd0.set_image(&DynamicImage::ImageRgb8(thumbnail));
+            ifd0.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![1]));
+            let mut preview = TiffDirectory::new();
+            set_jpeg_preview(&mut preview, jpeg, image.width(), image.height());
+            ifd0.set_directories(IfdEntryTag::SubIFDS, vec![raw, preview]);
+        }
+        None => {
+            // Without a preview the raw data is the main image of IFD0
+            ifd0.merge(raw);
+        }
+    }
+    ifd0.to_tiff()
+}
+
+/// Adds the CFA data of `raw_image` with the tags describing its layout,
+/// levels and crops.
+fn set_raw(
+    directory: &mut TiffDirectory,
+    raw_image: RawImage,
+    compression: DngCompression,
+) -> Result<(), NefError> {
+    let (width, height) = (raw_image.width, raw_image.height);
+    let active = raw_image.active_area;
+    let crop = raw_image.default_crop.intersect(active);
+
+    match compression {
+        DngCompression::Uncompressed => {
+            let mosaic: ImageBuffer<Luma<u16>, Vec<u16>> =
+                ImageBuffer::from_raw(width as u32, height as u32, raw_image.data).ok_or_else(
+                    || NefError::InvalidMosaic(format!("data does not fill {width}x{height}")),
+                )?;
+            directory.set_image(&DynamicImage::ImageLuma16(mosaic));
+        }
+        DngCompression::LosslessJpeg => {
+            // The smallest precision that holds every sample
+            let max = raw_image.data.iter().copied().max().unwrap_or(0);
+            let precision = (16 - max.leading_zeros() as u8).max(2);
+            let tiles = lossless_jpeg_tiles(&raw_image.data, width, height, precision)?;
+
+            directory.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![0]));
+            directory.set(IfdEntryTag::ImageWidth, IfdValue::Longs(vec![width as u32]));
+            directory.set(
+                IfdEntryTag::ImageLength,
+                IfdValue::Longs(vec![height as u32]),
+            );
+            directory.set(
+                IfdEntryTag::BitsPerSample,
+                IfdValue::Shorts(vec![precision as u16]),
+            );
+            directory.set(IfdEntryTag::Compression, IfdValue::Shorts(vec![7]));
+            directory.set(IfdEntryTag::SamplesPerPixel, IfdValue::Shorts(vec![1]));
+            directory.set(IfdEntryTag::PlanarConfiguration, IfdValue::Shorts(vec![1]));
+            directory.set(
+                IfdEntryTag::TileWidth,
+                IfdValue::Longs(vec![TILE_SIZE as u32]),
+            );
+            directory.set(
+                IfdEntryTag::TileLength,
+                IfdValue::Longs(vec![TILE_SIZE as u32]),
+            );
+            directory.set_blocks(IfdEntryTag::TileOffsets, IfdEntryTag::TileByteCounts, tiles);
+        }
+    }
+    directory.set(
+        IfdEntryTag::PhotometricInterpretation,
+        IfdValue::Shorts(vec![32803]),
+    );
+
+    directory.set(
+        IfdEntryTag::CFARepeatPatternDim,
+        IfdValue::Shorts(vec![2, 2]),
+    );
+    directory.set(
+        IfdEntryTag::CFAPattern,
+        IfdValue::Bytes(raw_image.cfa.to_vec()),
+    );
+    directory.set(IfdEntryTag::CFAPlaneColor, IfdValue::Bytes(vec![0, 1, 2]));
+    directory.set(IfdEntryTag::CFALayout, IfdValue::Shorts(vec![1]));
+
+    directory.set(
+        IfdEntryTag::BlackLevelRepeatDim,
+        IfdValue::Shorts(vec![2, 2]),
+    );
+    directory.set(
+        IfdEntryTag::BlackLevel,
+        IfdValue::Shorts(raw_image.black_levels.to_vec()),
+    );
+    directory.set(
+        IfdEntryTag::WhiteLevel,
+        IfdValue::Longs(vec![raw_image.white_level as u32]),
+    );
+
+    // ActiveArea is top, left, bottom, right; the default crop is relative
+    // to it and given as horizontal, vertical
+    let long = |value: usize| value as u32;
+    directory.set(
+        IfdEntryTag::ActiveArea,
+        IfdValue::Longs(vec![
+            long(active.top),
+            long(active.left),
+            long(active.top + active.height),
+            long(active.left + active.width),
+        ]),
+    );
+    directory.set(
+        IfdEntryTag::DefaultCropOrigin,
+        IfdValue::Longs(vec![
+            long(crop.left - active.left),
+            long(crop.top - active.top),
+        ]),
+    );
+    directory.set(
+        IfdEntryTag::DefaultCropSize,
+        IfdValue::Longs(vec![long(crop.width), long(crop.height)]),
+    );
+    Ok(())
+}
+
+/// The mosaic cut into tiles, left to right and top to bottom, each encoded
+/// as a two component lossless JPEG of half the tile width so that both
+/// colors of a CFA row are predicted from their own kind. Tiles on the right
+/// and bottom edge are padded by repeating the last column and row.
+fn lossless_jpeg_tiles(
+    data: &[u16],
+    width: usize,
+    height: usize,
+    precision: u8,
+) -> Result<Vec<Vec<u8>>, NefError> {
+    let mut tiles = Vec::new();
+    for top in (0..height).step_by(TILE_SIZE) {
+        for left in (0..width).step_by(TILE_SIZE) {
+            let mut tile = Vec::with_capacity(TILE_SIZE * TILE_SIZE);
+            for row in 0..TILE_SIZE {
+                let y = (top + row).min(height - 1);
+                for col in 0..TILE_SIZE {
+                    let x = (left + col).min(width - 1);
+                    tile.push(data[y * width + x]);
+                }
+            }
+            let jpeg = LosslessJpeg {
+                width: TILE_SIZE / 2,
+                height: TILE_SIZE,
+                components: 2,
+                precision,
+                predictor: 1,
+                data: tile,
+            };
+            tiles.push(jpeg.encode()?);
+        }
+    }
+    Ok(tiles)
+}
+
+/// Adds `jpeg` as a reduced-resolution preview, stored as a single strip.
+fn set_jpeg_preview(directory: &mut TiffDirectory, jpeg: &[u8], width: u32, height: u32) {
+    directory.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![1]));
+    directory.set(IfdEntryTag::ImageWidth, IfdValue::Longs(vec![width]));
+    directory.set(IfdEntryTag::ImageLength, IfdValue::Longs(vec![height]));
+    directory.set(IfdEntryTag::BitsPerSample, IfdValue::Shorts(vec![8, 8, 8]));
+    // JPEG, YCbCr
+    directory.set(IfdEntryTag::Compression, IfdValue::Shorts(vec![7]));
+    directory.set(
+        IfdEntryTag::PhotometricInterpretation,
+        IfdValue::Shorts(vec![6]),
+    );
+    directory.set(IfdEntryTag::SamplesPerPixel, IfdValue::Shorts(vec![3]));
+    directory.set(IfdEntryTag::RowsPerStrip, IfdValue::Longs(vec![height]));
+    directory.set(IfdEntryTag::PlanarConfiguration, IfdValue::Shorts(vec![1]));
+    directory.set_blocks(
+        IfdEntryTag::StripOffsets,
+        IfdEntryTag::StripByteCounts,
+        vec![jpeg.to_vec()],
+    );
+}
+
+/// The MakerNote in the Adobe "MakN" DNGPrivateData layout, which records
+/// the byte order and offset it had in the NEF so readers can resolve
+/// offsets relative to the original file.
+fn maker_note_private_data(nef_file: &NefFile) -> Option<Vec<u8>> {
+    let entry = nef_file.exif_ifd()?.get_entry(IfdEntryTag::MakerNote)?;
+    let maker_note = entry.get_offset_data(nef_file.buffer()).ok()?;
+    let offset = u32::try_from(entry.base_offset + entry.get_data_or_offset()).ok()?;
+    let byte_order = match entry.endian {
+        Endian::Little => b"II",
+        Endian::Big => b"MM",
+    };
+
+    let mut data = Vec::with_capacity(maker_note.len() + 20);
+    data.extend_from_slice(b"Adobe\0MakN");
+    data.extend_from_slice(&(maker_note.len() as u32 + 6).to_be_bytes());
+    data.extend_from_slice(byte_order);
+    data.extend_from_slice(&offset.to_be_bytes());
+    data.extend_from_slice(maker_note);
+    Some(data)
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+
+use crate::ifd::{IfdEntryTag, IfdRole};
+
+/// Errors returned while reading and decoding a NEF file.
+#[derive(Debug)]
+pub enum NefError {
+    /// Reading the file failed.
+    Io(std::io::Error),
+    /// The buffer does not start with a TIFF header.
+    NotTiff,
+    /// A directory the decoder depends on is missing.
+    MissingIfd(IfdRole),
+    /// A tag the decoder depends on is missing.
+    MissingTag(IfdEntryTag),
+    /// A read of `len` bytes at `offset` runs past the end of the buffer.
+    OutOfBounds { offset: usize, len: usize },
+    /// The raw data uses a compression scheme the decoder does not know.
+    UnsupportedCompression(u16),
+    /// An uncompressed strip of `size` bytes matches no known `bps` bit layout.
+    UnsupportedLayout { bps: u16, size: usize },
+    /// The Huffman table definition is inconsistent.
+    InvalidHuffmanTable(String),
+    /// The bitstream contains a code that is not in the Huffman table.
+    InvalidHuffmanCode,
+    /// A lossless JPEG stream is malformed or uses an unsupported feature.
+    InvalidLosslessJpeg(String),
+    /// A mosaic does not match its size or bit depth, or has dimensions the
+    /// decoder or encoder cannot handle.
+    InvalidMosaic(String),
+    /// The compressed raw data ended or broke off at the given pixel.
+    CorruptHuffmanStream { row: usize, col: usize },
+    /// A versioned MakerNote block has a layout the decoder does not know.
+    UnsupportedVersion {
+        block: &'static str,
+        version: String,
+    },
+    /// An encrypted MakerNote block was found without the SerialNumber and
+    /// ShutterCount tags its key is derived from.
+    MissingDecryptionKey,
+    /// A JPEG does not start with the SOI marker.
+    InvalidJpeg,
+    /// An EXIF block of the given size does not fit in a JPEG APP1 segment.
+    ExifTooLarge(usize),
+}
+
+impl fmt::Display for NefError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NefError::Io(e) => write!(f, "I/O error: {e}"),
+            NefError::NotTiff => write!(f, "not a TIFF based file"),
+            NefError::MissingIfd(role) => write!(f, "required {role:?} IFD not found"),
+            NefError::MissingTag(tag) => {
+                write!(
+                    f,
+                    "required tag {:?} ({:#06X}) not found",
+                    tag,
+                    tag.u16_value()
+                )
+            }
+            NefError::OutOfBounds { offset, len } => {
+                write!(f, "read of {len} bytes at offset {offset} is out of bounds")
+            }
+            NefError::UnsupportedCompression(compression) => {
+                write!(f, "unsupported compression {compression}")
+            }
+            NefError::UnsupportedLayout { bps, size } => {
+                write!(
+                    f,
+                    "{size} byte strip does not match a known {bps}-bit layout"
+                )
+            }
+            NefError::InvalidHuffmanTable(reason) => write!(f, "invalid huffman table: {reason}"),
+            NefError::InvalidHuffmanCode => write!(f, "invalid huffman code"),
+            NefError::InvalidLosslessJpeg(reason) => write!(f, "invalid lossless JPEG: {reason}"),
+            NefError::InvalidMosaic(reason) => write!(f, "invalid mosaic: {reason}"),
+            NefError::CorruptHuffmanStream { row, col } => {
+                write!(f, "corrupt huffman stream at row {row}, column {col}")
+            }
+            NefError::UnsupportedVersion { block, version } => {
+                write!(f, "unsupported {block} version {version:?}")
+            }
+            NefError::MissingDecryptionKey => {
+                write!(
+                    f,
+                    "serial number or shutter count needed for decryption not found"
+                )
+            }
+            NefError::InvalidJpeg => write!(f, "not a JPEG stream"),
+            NefError::ExifTooLarge(len) => {
+                write!(f, "{len} byte EXIF block does not fit in an APP1 segment")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NefError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            NefError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NefError {
+    fn from(e: std::io::Error) -> Self {
+        NefError::Io(e)
+    }
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::error::NefError;
+
 const DECODE_CACHE_BITS: u32 = 13;
 
 pub trait BitPump {
@@ -64,6 +66,9 @@ pub struct HuffTable {
     // sized by DECODE_CACHE_BITS and can have 99%+ hit rate with 13 bits
     decodecache: [Option<(u8, i16)>; 1 << DECODE_CACHE_BITS],
 
+    // The code and code length of each value, for encoding
+    encodetable: [Option<(u16, u8)>; 256],
+
     initialized: bool,
 }
 
@@ -110,11 +115,12 @@ impl HuffTable {
             nbits: 0,
             hufftable: Vec::new(),
             decodecache: [None; 1 << DECODE_CACHE_BITS],
+            encodetable: [None; 256],
             initialized: false,
         }
     }
 
-    pub fn new(bits: [u32; 17], huffval: [u32; 256], dng_bug: bool) -> Result<HuffTable, String> {
+    pub fn new(bits: [u32; 17], huffval: [u32; 256], dng_bug: bool) -> Result<HuffTable, NefError> {
         let mut tbl = HuffTable {
             bits,
             huffval,
@@ -125,13 +131,111 @@ impl HuffTable {
             nbits: 0,
             hufftable: Vec::new(),
             decodecache: [None; 1 << DECODE_CACHE_BITS],
+            encodetable: [None; 256],
             initialized: false,
         };
         tbl.initialize()?;
         Ok(tbl)
     }
 
-    pub fn initialize(&mut self) -> Result<(), String> {
+    /// An optimal table for the difference lengths 0 to 16 occurring
+    /// `frequencies` times, built as in JPEG Annex K.2: no code is longer than
+    /// 16 bits and none consists of only ones.
+    pub fn from_frequencies(frequencies: &[u32; 17]) -> Result<HuffTable, NefError> {
+        // A value that never occurs still needs a code to make a valid table
+        let mut freq: Vec<u64> = frequencies.iter().map(|&f| f as u64).collect();
+        if freq.iter().all(|&f| f == 0) {
+            freq[0] = 1;
+        }
+        // The reserved value that keeps the all ones code unused
+        freq.push(1);
+
+        let mut codesize = vec![0_usize; freq.len()];
+        let mut others: Vec<Option<usize>> = vec![None; freq.len()];
+        loop {
+            // The two least frequent values, ties going to the larger value
+            let mut v1: Option<usize> = None;
+            let mut v2: Option<usize> = None;
+            for i in 0..freq.len() {
+                if freq[i] == 0 {
+                    continue;
+                }
+                if v1.is_none_or(|v| freq[i] <= freq[v]) {
+                    v2 = v1;
+                    v1 = Some(i);
+                } else if v2.is_none_or(|v| freq[i] <= freq[v]) {
+                    v2 = Some(i);
+                }
+            }
+            let (Some(v1), Some(v2)) = (v1, v2) else {
+                break;
+            };
+
+            freq[v1] += freq[v2];
+            freq[v2] = 0;
+            let mut v = v1;
+            codesize[v] += 1;
+            while let Some(next) = others[v] {
+                v = next;
+                codesize[v] += 1;
+            }
+            others[v] = Some(v2);
+            let mut v = v2;
+            codesize[v] += 1;
+            while let Some(next) = others[v] {
+                v = next;
+                codesize[v] += 1;
+            }
+        }
+
+        let mut counts = [0_u32; 33];
+        for &size in codesize.iter().filter(|&&size| size > 0) {
+            counts[size] += 1;
+        }
+        // Move codes longer than 16 bits up the tree (Annex K.3)
+        for i in (17..=32).rev() {
+            while counts[i] > 0 {
+                let mut j = i - 2;
+                while counts[j] == 0 {
+                    j -= 1;
+                }
+                counts[i] -= 2;
+                counts[i - 1] += 1;
+                counts[j + 1] += 2;
+                counts[j] -= 1;
+            }
+        }
+        // Drop the reserved value, which has one of the longest codes
+        if let Some(longest) = (1..=16).rev().find(|&i| counts[i] > 0) {
+            counts[longest] -= 1;
+        }
+
+        let mut bits = [0_u32; 17];
+        bits[1..].copy_from_slice(&counts[1..=16]);
+        let mut huffval = [0_u32; 256];
+        let mut pos = 0;
+        for size in 1..=32 {
+            for (value, _) in codesize[..17]
+                .iter()
+                .enumerate()
+                .filter(|&(_, &codesize)| codesize == size)
+            {
+                huffval[pos] = value as u32;
+                pos += 1;
+            }
+        }
+        HuffTable::new(bits, huffval, false)
+    }
+
+    pub fn initialize(&mut self) -> Result<(), NefError> {
+        let codes: u32 = self.bits[1..].iter().sum();
+        if codes as usize > self.huffval.len() {
+            return Err(NefError::InvalidHuffmanTable(format!(
+                "{codes} codes defined, at most {} allowed",
+                self.huffval.len()
+            )));
+        }
+
         // Find out the max code length and allocate a table with that size
         self.nbits = 16;
         for i in 0..16 {
@@ -147,6 +251,11 @@ impl HuffTable {
         let mut pos = 0;
         for len in 0..self.nbits {
             for _ in 0..self.bits[len as usize + 1] {
+                if h + (1 << (self.nbits - len - 1)) > self.hufftable.len() {
+                    return Err(NefError::InvalidHuffmanTable(String::from(
+                        "code lengths oversubscribe the code space",
+                    )));
+                }
                 for _ in 0..(1 << (self.nbits - len - 1)) {
                     self.hufftable[h] = (
                         len as u8 + 1,
@@ -159,6 +268,24 @@ impl HuffTable {
             }
         }
 
+        // Assign the canonical codes of JPEG Annex C in the same order. The
+        // shifted codes of the Nikon lossy after split trees drop low bits of
+        // the difference, so only unshifted codes are used for encoding.
+        self.encodetable = [None; 256];
+        let mut code = 0_u32;
+        let mut pos = 0;
+        for len in 1..=16 {
+            for _ in 0..self.bits[len] {
+                let value = self.huffval[pos] as usize & 0xFF;
+                if self.shiftval[pos] == 0 && self.encodetable[value].is_none() {
+                    self.encodetable[value] = Some((code as u16, len as u8));
+                }
+                code += 1;
+                pos += 1;
+            }
+            code <<= 1;
+        }
+
         // Create the decode cache by running the slow code over all the possible
         // values DECODE_CACHE_BITS wide
         if !self.disable_cache {
@@ -166,8 +293,10 @@ impl HuffTable {
             let mut i = 0;
             loop {
                 pump.set(i, DECODE_CACHE_BITS);
-                let (bits, decode) = self.huff_decode_slow(&mut pump);
-                if pump.validbits() >= 0 {
+                // Invalid codes are left out of the cache so the slow path reports them
+                if let Ok((bits, decode)) = self.huff_decode_slow(&mut pump)
+                    && pump.validbits() >= 0
+                {
                     self.decodecache[i as usize] = Some((bits, decode as i16));
                 }
                 i += 1;
@@ -181,34 +310,36 @@ impl HuffTable {
         Ok(())
     }
 
-    pub fn huff_decode(&self, pump: &mut dyn BitPump) -> Result<i32, String> {
+    pub fn huff_decode(&self, pump: &mut dyn BitPump) -> Result<i32, NefError> {
         let code = pump.peek_bits(DECODE_CACHE_BITS) as usize;
         if let Some((bits, decode)) = self.decodecache[code] {
             pump.consume_bits(bits as u32);
             Ok(decode as i32)
         } else {
-            let decode = self.huff_decode_slow(pump);
+            let decode = self.huff_decode_slow(pump)?;
             Ok(decode.1)
         }
     }
 
-    pub fn huff_decode_slow(&self, pump: &mut dyn BitPump) -> (u8, i32) {
-        let len = self.huff_len(pump);
-        (len.0 + len.1, self.huff_diff(pump, len))
+    pub fn huff_decode_slow(&self, pump: &mut dyn BitPump) -> Result<(u8, i32), NefError> {
+        let len = self.huff_len(pump)?;
+        Ok((len.0 + len.1, self.huff_diff(pump, len)))
     }
 
-    pub fn huff_len(&self, pump: &mut dyn BitPump) -> (u8, u8, u8) {
+    pub fn huff_len(&self, pump: &mut dyn BitPump) -> Result<(u8, u8, u8), NefError> {
         let code = pump.peek_bits(self.nbits) as usize;
         let (bits, len, shift) = self.hufftable[code];
+        // Unused slots of the table have a code length of zero
+        if bits == 0 {
+            return Err(NefError::InvalidHuffmanCode);
+        }
         pump.consume_bits(bits as u32);
-        (bits, len, shift)
+        Ok((bits, len, shift))
     }
 
-    pub fn huff_get_bits(&self, pump: &mut dyn BitPump) -> u32 {
-        let code = pump.peek_bits(self.nbits) as usize;
-        let (bits, len, _) = self.hufftable[code];
-        pump.consume_bits(bits as u32);
-        len as u32
+    pub fn huff_get_bits(&self, pump: &mut dyn BitPump) -> Result<u32, NefError> {
+        let (_, len, _) = self.huff_len(pump)?;
+        Ok(len as u32)
     }
 
     pub fn huff_diff(&self, pump: &mut dyn BitPump, input: (u8, u8, u8)) -> i32 {
@@ -237,6 +368,38 @@ impl HuffTable {
     }
 }
 
+impl HuffTable {
+    /// Writes `diff` as the code of its length followed by the difference
+    /// bits, the inverse of [`HuffTable::huff_decode`] for tables without
+    /// shifts. `diff` must lie in -32768..=32768.
+    pub fn huff_encode(&self, writer: &mut BitWriter, diff: i32) -> Result<(), NefError> {
+        let len = 32 - diff.unsigned_abs().leading_zeros();
+        if len > 16 {
+            return Err(NefError::InvalidHuffmanTable(format!(
+                "difference {diff} does not fit into 16 bits"
+            )));
+        }
+        let (code, code_len) = self.encodetable[len as usize].ok_or_else(|| {
+            NefError::InvalidHuffmanTable(format!("no code for difference length {len}"))
+        })?;
+        writer.put_bits(code as u32, code_len as u32);
+        match len {
+            0 => {}
+            16 => {
+                if self.dng_bug {
+                    writer.put_bits(0, 16);
+                }
+            }
+            len => {
+                // Negative differences are stored as diff - 1 in len bits
+                let bits = if diff < 0 { diff - 1 } else { diff };
+                writer.put_bits(bits as u32 & ((1 << len) - 1), len);
+            }
+        }
+        Ok(())
+    }
+}
+
 impl fmt::Debug for HuffTable {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         if self.initialized {
@@ -251,3 +414,614 @@ impl fmt::Debug for HuffTable {
         }
     }
 }
+
+/// Writes bits most significant first, the counterpart of the MSB bit pumps.
+pub struct BitWriter {
+    out: Vec<u8>,
+    bits: u64,
+    nbits: u32,
+    // JPEG entropy coded data stuffs a zero after every 0xFF byte and pads
+    // the last byte with ones
+    jpeg: bool,
+}
+
+impl BitWriter {
+    pub fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            bits: 0,
+            nbits: 0,
+            jpeg: false,
+        }
+    }
+
+    /// A writer for JPEG entropy coded segments.
+    pub fn jpeg() -> BitWriter {
+        BitWriter {
+            jpeg: true,
+            ..BitWriter::new()
+        }
+    }
+
+    /// Appends the low `num` bits of `value`, at most 32.
+    pub fn put_bits(&mut self, value: u32, num: u32) {
+        if num == 0 {
+            return;
+        }
+        let mask = (1_u64 << num) - 1;
+        self.bits = (self.bits << num) | (value as u64 & mask);
+        self.nbits += num;
+        while self.nbits >= 8 {
+            self.nbits -= 8;
+            let byte = (self.bits >> self.nbits) as u8;
+            self.out.push(byte);
+            if self.jpeg && byte == 0xFF {
+                self.out.push(0);
+            }
+        }
+        self.bits &= (1 << self.nbits) - 1;
+    }
+
+    /// The written bytes, the last one padded.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.nbits > 0 {
+            let padding = 8 - self.nbits;
+            let fill = if self.jpeg { u32::MAX } else { 0 };
+            self.put_bits(fill, padding);
+        }
+        self.out
+    }
+}
+
+impl Default for BitWriter {
+    fn default() -> Self {
+        BitWriter::new()
+    }
+}
+
+/// Reads JPEG entropy coded data: stuffed zeros after 0xFF are dropped and
+/// the stream reads as zeros from the next marker on.
+pub struct BitPumpJpeg<'a> {
+    buffer: &'a [u8],
+    pos: usize,
+    bits: u64,
+    nbits: u32,
+    // Zero bytes fed in at a marker or the end of the buffer
+    padding: u32,
+}
+
+impl<'a> BitPumpJpeg<'a> {
+    pub fn new(src: &'a [u8]) -> BitPumpJpeg<'a> {
+        BitPumpJpeg {
+            buffer: src,
+            pos: 0,
+            bits: 0,
+            nbits: 0,
+            padding: 0,
+        }
+    }
+
+    /// Whether bits past the end of the segment were consumed, which a well
+    /// formed stream never does as its last byte is padded with ones.
+    pub fn is_exhausted(&self) -> bool {
+        self.padding * 8 > self.nbits
+    }
+
+    /// Drops the rest of the current segment and moves past the next
+    /// restart marker. False if there is none.
+    pub fn restart(&mut self) -> bool {
+        self.bits = 0;
+        self.nbits = 0;
+        self.padding = 0;
+        while self.pos + 1 < self.buffer.len() {
+            if self.buffer[self.pos] == 0xFF && (0xD0..=0xD7).contains(&self.buffer[self.pos + 1]) {
+                self.pos += 2;
+                return true;
+            }
+            self.pos += 1;
+        }
+        false
+    }
+
+    fn fill(&mut self) {
+        while self.nbits <= 56 {
+            let byte = match self.buffer.get(self.pos) {
+                Some(0xFF) if self.buffer.get(self.pos + 1) == Some(&0) => {
+                    self.pos += 2;
+                    0xFF
+                }
+                Some(0xFF) | None => {
+                    self.padding += 1;
+                    0
+                }
+                Some(&byte) => {
+                    self.pos += 1;
+                    byte
+                }
+            };
+            self.bits = (self.bits << 8) | byte as u64;
+            self.nbits += 8;
+        }
+    }
+}
+
+impl<'a> BitPump for BitPumpJpeg<'a> {
+    #[inline(always)]
+    fn peek_bits(&mut self, num: u32) -> u32 {
+        if num > self.nbits {
+            self.fill();
+        }
+        (self.bits >> (self.nbits - num)) as u32
+    }
+
+    fn consume_bits(&mut self, num: u32) {
+        self.nbits -= num;
+        self.bits &= (1 << self.nbits) - 1;
+    }
+}
+
+/// A lossless JPEG (ITU T.81 process 14, SOF3) image, the LJ92 format of
+/// compressed DNG tiles. Components are interleaved, so a row holds
+/// `width * components` samples.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LosslessJpeg {
+    pub width: usize,
+    pub height: usize,
+    /// Number of components, 1 to 4.
+    pub components: usize,
+    /// Sample precision in bits, 2 to 16.
+    pub precision: u8,
+    /// Predictor selection value, 1 to 7.
+    pub predictor: u8,
+    pub data: Vec<u16>,
+}
+
+impl LosslessJpeg {
+    /// Decodes a single-scan image whose components are not subsampled,
+    /// honoring the point transform and restart intervals. Restart intervals
+    /// must span whole rows, so that each starts a fresh first row; shorter
+    /// ones are rejected.
+    pub fn decode(src: &[u8]) -> Result<LosslessJpeg, NefError> {
+        if src.get(..2) != Some(&[0xFF, 0xD8][..]) {
+            return Err(invalid_jpeg("missing SOI marker"));
+        }
+        let mut tables: [Option<HuffTable>; 4] = std::array::from_fn(|_| None);
+        // Precision, height, width and component identifiers
+        let mut frame: Option<(u8, usize, usize, Vec<u8>)> = None;
+        let mut restart_interval = 0;
+        let mut pos = 2;
+
+        loop {
+            // Fill bytes may precede a marker
+            while src.get(pos + 1) == Some(&0xFF) {
+                pos += 1;
+            }
+            let (marker, segment) = jpeg_segment(src, pos)?;
+            pos += 2 + 2 + segment.len();
+            match marker {
+                0xC4 => {
+                    let mut rest = segment;
+                    while !rest.is_empty() {
+                        let (id, table, length) = parse_dht(rest)?;
+                        tables[id] = Some(table);
+                        rest = &rest[length..];
+                    }
+                }
+                0xC3 => {
+                    let header = segment
+                        .get(..6)
+                        .ok_or_else(|| invalid_jpeg("short frame header"))?;
+                    let precision = header[0];
+                    let height = u16::from_be_bytes([header[1], header[2]]) as usize;
+                    let width = u16::from_be_bytes([header[3], header[4]]) as usize;
+                    let count = header[5] as usize;
+                    let specs = segment
+                        .get(6..6 + count * 3)
+                        .ok_or_else(|| invalid_jpeg("short frame header"))?;
+                    if !(2..=16).contains(&precision) || !(1..=4).contains(&count) {
+                        return Err(invalid_jpeg("unsupported precision or component count"));
+                    }
+                    if height == 0 || width == 0 {
+                        return Err(invalid_jpeg("image size missing from the frame header"));
+                    }
+                    if specs.chunks_exact(3).any(|spec| spec[1] != 0x11) {
+                        return Err(invalid_jpeg("subsampled components are not supported"));
+                    }
+                    let ids = specs.chunks_exact(3).map(|spec| spec[0]).collect();
+                    frame = Some((precision, height, width, ids));
+                }
+                0xC0..=0xCF if marker != 0xC8 && marker != 0xCC => {
+                    return Err(invalid_jpeg("not a lossless (SOF3) frame"));
+                }
+                0xDD => {
+                    let value = segment
+                        .get(..2)
+                        .ok_or_else(|| invalid_jpeg("short restart interval"))?;
+                    restart_interval = u16::from_be_bytes([value[0], value[1]]) as usize;
+                }
+                0xDA => {
+                    let (precision, height, width, ids) =
+                        frame.ok_or_else(|| invalid_jpeg("scan before the frame header"))?;
+                    let count = *segment.first().unwrap_or(&0) as usize;
+                    let specs = segment
+                        .get(1..1 + count * 2 + 3)
+                        .ok_or_else(|| invalid_jpeg("short scan header"))?;
+                    if count != ids.len() {
+                        return Err(invalid_jpeg("scans of some components are not supported"));
+                    }
+                    // Each component in frame order with its table
+                    let mut selected: Vec<&HuffTable> = Vec::with_capacity(count);
+                    for id in &ids {
+                        let spec = specs[..count * 2]
+                            .chunks_exact(2)
+                            .find(|spec| spec[0] == *id)
+                            .ok_or_else(|| invalid_jpeg("component missing from the scan"))?;
+                        let table = tables
+                            .get((spec[1] >> 4) as usize)
+                            .and_then(Option::as_ref)
+                            .ok_or_else(|| invalid_jpeg("undefined Huffman table"))?;
+                        selected.push(table);
+                    }
+                    if restart_interval % width != 0 {
+                        return Err(invalid_jpeg(
+                            "restart interval is not a whole number of rows",
+                        ));
+                    }
+                    let predictor = specs[count * 2];
+                    let point_transform = specs[count * 2 + 2] & 0x0F;
+                    if !(1..=7).contains(&predictor) || point_transform >= precision {
+                        return Err(invalid_jpeg("invalid predictor or point transform"));
+                    }
+
+                    // Every sample takes at least one bit of the scan, so the
+                    // header cannot claim more than the data can hold
+                    let samples = width * height * count;
+                    if samples > src.len().saturating_sub(pos).saturating_mul(8) {
+                        return Err(invalid_jpeg("frame is larger than the scan data"));
+                    }
+
+                    let mut image = LosslessJpeg {
+                        width,
+                        height,
+                        components: count,
+                        precision,
+                        predictor,
+                        data: vec![0; samples],
+                    };
+                    let mut pump = BitPumpJpeg::new(&src[pos..]);
+                    image.decode_scan(&mut pump, &selected, restart_interval, point_transform)?;
+                    return Ok(image);
+                }
+                0xD9 => return Err(invalid_jpeg("no scan before EOI")),
+                _ => {}
+            }
+        }
+    }
+
+    fn decode_scan(
+        &mut self,
+        pump: &mut BitPumpJpeg,
+        tables: &[&HuffTable],
+        restart_interval: usize,
+        point_transform: u8,
+    ) -> Result<(), NefError> {
+        let initial = 1 << (self.precision - point_transform - 1);
+        let mut first_row = 0;
+        for row in 0..self.height {
+            if restart_interval > 0
+                && row > 0
+                && (row * self.width).is_multiple_of(restart_interval)
+            {
+                if !pump.restart() {
+                    return Err(NefError::CorruptHuffmanStream { row, col: 0 });
+                }
+                first_row = row;
+            }
+            for col in 0..self.width {
+                for (component, table) in tables.iter().enumerate() {
+                    let prediction = self.prediction(row, col, component, first_row, initial);
+                    let diff = table
+                        .huff_decode(pump)
+                        .map_err(|_| NefError::CorruptHuffmanStream { row, col })?;
+                    let index = (row * self.width + col) * self.components + component;
+                    self.data[index] = (prediction + diff) as u16;
+                }
+            }
+            if pump.is_exhausted() {
+                return Err(NefError::CorruptHuffmanStream { row, col: 0 });
+            }
+        }
+        if point_transform > 0 {
+            for value in self.data.iter_mut() {
+                *value <<= point_transform;
+            }
+        }
+        Ok(())
+    }
+
+    /// Encodes the image with a Huffman table optimized for its differences.
+    pub fn encode(&self) -> Result<Vec<u8>, NefError> {
+        if !(2..=16).contains(&self.precision)
+            || !(1..=4).contains(&self.components)
+            || !(1..=7).contains(&self.predictor)
+        {
+            return Err(invalid_jpeg(
+                "unsupported precision, components or predictor",
+            ));
+        }
+        if self.width == 0
+            || self.height == 0
+            || self.width > 0xFFFF
+            || self.height > 0xFFFF
+            || self.data.len() != self.width * self.height * self.components
+        {
+            return Err(invalid_jpeg("image size does not fit the frame header"));
+        }
+        if self
+            .data
+            .iter()
+            .any(|&value| value as u32 >> self.precision != 0)
+        {
+            return Err(invalid_jpeg("samples exceed the precision"));
+        }
+
+        // The differences to the predictions the decoder will make, taken
+        // modulo 2^16
+        let initial = 1 << (self.precision - 1);
+        let mut diffs = Vec::with_capacity(self.data.len());
+        let mut frequencies = [0_u32; 17];
+        for row in 0..self.height {
+            for col in 0..self.width {
+                for component in 0..self.components {
+                    let prediction = self.prediction(row, col, component, 0, initial);
+                    let index = (row * self.width + col) * self.components + component;
+                    let diff = (self.data[index] as i32 - prediction) as i16 as i32;
+                    frequencies[(32 - diff.unsigned_abs().leading_zeros()) as usize] += 1;
+                    diffs.push(diff);
+                }
+            }
+        }
+        let table = HuffTable::from_frequencies(&frequencies)?;
+
+        let mut out = vec![0xFF, 0xD8];
+        let codes = table.bits[1..].iter().sum::<u32>() as usize;
+        let mut dht = vec![0x00];
+        dht.extend(table.bits[1..].iter().map(|&count| count as u8));
+        dht.extend(table.huffval[..codes].iter().map(|&value| value as u8));
+        push_segment(&mut out, 0xC4, &dht);
+
+        let mut sof = vec![self.precision];
+        sof.extend_from_slice(&(self.height as u16).to_be_bytes());
+        sof.extend_from_slice(&(self.width as u16).to_be_bytes());
+        sof.push(self.components as u8);
+        for id in 1..=self.components as u8 {
+            sof.extend_from_slice(&[id, 0x11, 0]);
+        }
+        push_segment(&mut out, 0xC3, &sof);
+
+        let mut sos = vec![self.components as u8];
+        for id in 1..=self.components as u8 {
+            sos.extend_from_slice(&[id, 0x00]);
+        }
+        sos.extend_from_slice(&[self.predictor, 0, 0]);
+        push_segment(&mut out, 0xDA, &sos);
+
+        let mut writer = BitWriter::jpeg();
+        for diff in diffs {
+            table.huff_encode(&mut writer, diff)?;
+        }
+        out.extend(writer.finish());
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        Ok(out)
+    }
+
+    /// The prediction of a sample from its left (a), upper (b) and upper left
+    /// (c) neighbours. The first row of a scan or restart interval predicts
+    /// from the left, the first column from above.
+    fn prediction(
+        &self,
+        row: usize,
+        col: usize,
+        component: usize,
+        first_row: usize,
+        initial: i32,
+    ) -> i32 {
+        let stride = self.width * self.components;
+        let index = (row * self.width + col) * self.components + component;
+        let sample = |index: usize| self.data[index] as i32;
+        match (row == first_row, col == 0) {
+            (true, true) => initial,
+            (true, false) => sample(index - self.components),
+            (false, true) => sample(index - stride),
+            (false, false) => {
+                let a = sample(index - self.components);
+                let b = sample(index - stride);
+                let c = sample(index - stride - self.components);
+                match self.predictor {
+                    1 => a,
+                    2 => b,
+                    3 => c,
+                    4 => a + b - c,
+                    5 => a + ((b - c) >> 1),
+                    6 => b + ((a - c) >> 1),
+                    _ => (a + b) >> 1,
+                }
+            }
+        }
+    }
+}
+
+fn invalid_jpeg(reason: &str) -> NefError {
+    NefError::InvalidLosslessJpeg(String::from(reason))
+}
+
+/// The marker at `pos` and the payload of its segment.
+fn jpeg_segment(src: &[u8], pos: usize) -> Result<(u8, &[u8]), NefError> {
+    let header = src.get(pos..pos + 4).ok_or(NefError::OutOfBounds {
+        offset: pos,
+        len: 4,
+    })?;
+    if header[0] != 0xFF {
+        return Err(invalid_jpeg("marker expected"));
+    }
+    // EOI has no length
+    if header[1] == 0xD9 {
+        return Ok((0xD9, &[]));
+    }
+    let length = u16::from_be_bytes([header[2], header[3]]) as usize;
+    let payload = src
+        .get(pos + 4..pos + 2 + length.max(2))
+        .ok_or(NefError::OutOfBounds {
+            offset: pos + 4,
+            len: length.saturating_sub(2),
+        })?;
+    Ok((header[1], payload))
+}
+
+/// The first table of a DHT segment with its identifier and the bytes it
+/// takes.
+fn parse_dht(data: &[u8]) -> Result<(usize, HuffTable, usize), NefError> {
+    let counts = data
+        .get(1..17)
+        .ok_or_else(|| invalid_jpeg("short Huffman table"))?;
+    let id = (data[0] & 0x0F) as usize;
+    if data[0] >> 4 != 0 || id > 3 {
+        return Err(invalid_jpeg("invalid Huffman table class or identifier"));
+    }
+    let mut bits = [0_u32; 17];
+    for (bit, &count) in bits[1..].iter_mut().zip(counts) {
+        *bit = count as u32;
+    }
+    let codes = bits.iter().sum::<u32>() as usize;
+    let values = data
+        .get(17..17 + codes)
+        .ok_or_else(|| invalid_jpeg("short Huffman table"))?;
+    let mut huffval = [0_u32; 256];
+    for (value, &byte) in huffval.iter_mut().zip(values) {
+        *value = byte as u32;
+    }
+    Ok((id, HuffTable::new(bits, huffval, false)?, 17 + codes))
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 8-bit image of 129, 128 / 130, 128 with predictor 1 and a
+    /// restart interval of one row. The table codes differences of 0, 1 and
+    /// 2 bits as `0`, `10` and `110`. Row 1 restarts from the initial
+    /// prediction of 128, not from the 129 above it:
+    /// `10 1`, `10 0` and `110 10`, `110 01`, each padded with ones.
+    const RESTARTS: [u8; 62] = [
+        0xFF, 0xD8, // SOI
+        0xFF, 0xC4, 0x00, 0x16, 0x00, // DHT
+        0x01, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, // code counts
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // code counts
+        0x00, 0x01, 0x02, // values
+        0xFF, 0xC3, 0x00, 0x0B, 0x08, 0x00, 0x02, 0x00, 0x02, 0x01, 0x01, 0x11, 0x00, // SOF3
+        0xFF, 0xDD, 0x00, 0x04, 0x00, 0x02, // DRI
+        0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x01, 0x00, 0x00, // SOS
+        0xB3, 0xFF, 0xD0, 0xD6, 0x7F, // scan with RST0
+        0xFF, 0xD9, // EOI
+    ];
+
+    fn image(components: usize, predictor: u8) -> LosslessJpeg {
+        let (width, height) = (7, 5);
+        let data = (0..width * height * components)
+            .map(|i| ((i * 7919 + i / 3 * 104729) % 4096) as u16)
+            .collect();
+        LosslessJpeg {
+            width,
+            height,
+            components,
+            precision: 12,
+            predictor,
+            data,
+        }
+    }
+
+    #[test]
+    fn restarts_reset_the_prediction() {
+        let image = LosslessJpeg::decode(&RESTARTS).unwrap();
+        assert_eq!((image.width, image.height, image.components), (2, 2, 1));
+        assert_eq!(image.data, [129, 128, 130, 128]);
+    }
+
+    #[test]
+    fn rejects_restarts_inside_a_row() {
+        let mut src = RESTARTS;
+        // A restart interval of one MCU
+        src[44] = 0x01;
+        assert!(matches!(
+            LosslessJpeg::decode(&src),
+            Err(NefError::InvalidLosslessJpeg(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_frames_larger_than_the_scan() {
+        let mut src = RESTARTS;
+        // 65535 x 65535 samples for five bytes of scan data, restarting
+        // every row
+        src[31..35].copy_from_slice(&[0xFF; 4]);
+        src[43..45].copy_from_slice(&[0xFF; 2]);
+        assert!(matches!(
+            LosslessJpeg::decode(&src),
+            Err(NefError::InvalidLosslessJpeg(reason)) if reason.contains("scan data")
+        ));
+    }
+
+    #[test]
+    fn predicts_from_the_neighbours() {
+        // Three components; the third holds c = 10, b = 20 on the first row
+        // and a = 30 at the start of the second
+        let mut data = vec![0; 2 * 2 * 3];
+        for (index, value) in [(2, 10), (5, 20), (8, 30)] {
+            data[index] = value;
+        }
+        let mut image = LosslessJpeg {
+            width: 2,
+            height: 2,
+            components: 3,
+            precision: 8,
+            predictor: 1,
+            data,
+        };
+        for (predictor, expected) in [
+            (1, 30),
+            (2, 20),
+            (3, 10),
+            (4, 40),
+            (5, 35),
+            (6, 30),
+            (7, 25),
+        ] {
+            image.predictor = predictor;
+            assert_eq!(image.prediction(1, 1, 2, 0, 128), expected, "{predictor}");
+        }
+        // The first row and column ignore the predictor
+        assert_eq!(image.prediction(0, 1, 2, 0, 128), 10);
+        assert_eq!(image.prediction(1, 0, 2, 0, 128), 10);
+        assert_eq!(image.prediction(0, 0, 2, 0, 128), 128);
+    }
+
+    #[test]
+    fn every_predictor_and_component_count_round_trips() {
+        for components in 1..=4 {
+            for predictor in 1..=7 {
+                let image = image(components, predictor);
+                let decoded = LosslessJpeg::decode(&image.encode().unwrap()).unwrap();
+                assert_eq!(
+                    decoded, image,
+                    "{components} components, predictor {predictor}"
+                );
+            }
+        }
+    }
+}
@@ -1,12 +1,37 @@
+use std::collections::HashSet;
+use std::fmt;
 use std::sync::Arc;
 
-use crate::utils::bytes_to_num;
+use crate::error::NefError;
+use crate::utils::{Endian, bytes_to_num, checked_slice};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Ifd {
     pub name: Option<Arc<str>>,
     pub offset_location: usize,
+    pub endian: Endian,
+    pub role: IfdRole,
     pub entries: Vec<IfdEntry>,
+    /// Position the offsets in this IFD are relative to: zero for the file's
+    /// own IFDs, the embedded TIFF header for the MakerNote and its sub-IFDs.
+    pub base_offset: usize,
+    /// Absolute offset of the next IFD in the chain, if this IFD links one.
+    pub next_ifd_offset: Option<usize>,
+}
+
+/// What an IFD holds, decided by the pointer it was reached through or,
+/// for plain image directories, by its content.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum IfdRole {
+    /// The full-resolution CFA image.
+    Raw,
+    /// A reduced-resolution image: the IFD0 thumbnail or an embedded JPEG.
+    Preview,
+    Exif,
+    Gps,
+    MakerNote,
+    #[default]
+    Unknown,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -14,8 +39,13 @@ pub struct IfdEntry {
     pub tag: IfdEntryTag,
     pub data_type: IfdEntryType,
     pub data_or_offset: [u8; 4],
+    pub count: usize,
     pub data_length: usize,
     pub offset: bool,
+    pub endian: Endian,
+    /// Position the value offset is relative to: zero for the file's own
+    /// IFDs, the embedded TIFF header for a Nikon MakerNote.
+    pub base_offset: usize,
     pub raw_entry: [u8; 12],
 }
 
@@ -41,6 +71,10 @@ pub enum IfdEntryTag {
     Software,
     DateTime,
     Artist,
+    TileWidth,
+    TileLength,
+    TileOffsets,
+    TileByteCounts,
     SubIFDS,
     JpgFromRawStart,
     JpgFromRawLength,
@@ -76,6 +110,7 @@ pub enum IfdEntryTag {
     SubSecTime,
     SubSecTimeOriginal,
     SubSecTimeDigitized,
+    InteropIFDPointer,
     NikonAFInfo2, // kysymysmerkki
     FileSource,
     NikonCaptureVersion,
@@ -90,6 +125,27 @@ pub enum IfdEntryTag {
     NikonCaptureColorHue,
     NikonCaptureSaturation,
     NikonCaptureNoiseReduction,
+    LensSpecification,
+    LensMake,
+    LensModel,
+    DNGVersion,
+    DNGBackwardVersion,
+    UniqueCameraModel,
+    CFAPlaneColor,
+    CFALayout,
+    BlackLevelRepeatDim,
+    BlackLevel,
+    WhiteLevel,
+    DefaultCropOrigin,
+    DefaultCropSize,
+    ColorMatrix1,
+    ColorMatrix2,
+    AsShotNeutral,
+    DNGPrivateData,
+    CalibrationIlluminant1,
+    CalibrationIlluminant2,
+    OriginalRawFileName,
+    ActiveArea,
     // Add other tags as needed.
     Unknown(usize),
 }
@@ -111,10 +167,27 @@ pub enum IfdEntryType {
     Unknown(u8),
 }
 
+/// A tag value decoded according to its [`IfdEntryType`] and byte order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum IfdValue {
+    Bytes(Vec<u8>),
+    Ascii(String),
+    Shorts(Vec<u16>),
+    Longs(Vec<u32>),
+    Rationals(Vec<(u32, u32)>),
+    SBytes(Vec<i8>),
+    Undefined(Vec<u8>),
+    SShorts(Vec<i16>),
+    SLongs(Vec<i32>),
+    SRationals(Vec<(i32, i32)>),
+    Floats(Vec<f32>),
+    Doubles(Vec<f64>),
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum TagParam {
     IfdEntry(IfdEntryTag),
-    U8(u8),
+    Offset(usize),
 }
 
 impl From<usize> for IfdEntryTag {
@@ -140,6 +213,10 @@ impl From<usize> for IfdEntryTag {
             0x131 => IfdEntryTag::Software,
             0x132 => IfdEntryTag::DateTime,
             0x13B => IfdEntryTag::Artist,
+            0x142 => IfdEntryTag::TileWidth,
+            0x143 => IfdEntryTag::TileLength,
+            0x144 => IfdEntryTag::TileOffsets,
+            0x145 => IfdEntryTag::TileByteCounts,
             0x14A => IfdEntryTag::SubIFDS,
             0x201 => IfdEntryTag::JpgFromRawStart,
             0x202 => IfdEntryTag::JpgFromRawLength,
@@ -175,6 +252,7 @@ impl From<usize> for IfdEntryTag {
             0x9290 => IfdEntryTag::SubSecTime,
             0x9291 => IfdEntryTag::SubSecTimeOriginal,
             0x9292 => IfdEntryTag::SubSecTimeDigitized,
+            0xA005 => IfdEntryTag::InteropIFDPointer,
             0xA217 => IfdEntryTag::NikonAFInfo2,
             0xA300 => IfdEntryTag::FileSource,
             0xA301 => IfdEntryTag::NikonCaptureVersion,
@@ -189,6 +267,27 @@ impl From<usize> for IfdEntryTag {
             0xA409 => IfdEntryTag::NikonCaptureColorHue,
             0xA40A => IfdEntryTag::NikonCaptureSaturation,
             0xA40C => IfdEntryTag::NikonCaptureNoiseReduction,
+            0xA432 => IfdEntryTag::LensSpecification,
+            0xA433 => IfdEntryTag::LensMake,
+            0xA434 => IfdEntryTag::LensModel,
+            0xC612 => IfdEntryTag::DNGVersion,
+            0xC613 => IfdEntryTag::DNGBackwardVersion,
+            0xC614 => IfdEntryTag::UniqueCameraModel,
+            0xC616 => IfdEntryTag::CFAPlaneColor,
+            0xC617 => IfdEntryTag::CFALayout,
+            0xC619 => IfdEntryTag::BlackLevelRepeatDim,
+            0xC61A => IfdEntryTag::BlackLevel,
+            0xC61D => IfdEntryTag::WhiteLevel,
+            0xC61F => IfdEntryTag::DefaultCropOrigin,
+            0xC620 => IfdEntryTag::DefaultCropSize,
+            0xC621 => IfdEntryTag::ColorMatrix1,
+            0xC622 => IfdEntryTag::ColorMatrix2,
+            0xC628 => IfdEntryTag::AsShotNeutral,
+            0xC634 => IfdEntryTag::DNGPrivateData,
+            0xC65A => IfdEntryTag::CalibrationIlluminant1,
+            0xC65B => IfdEntryTag::CalibrationIlluminant2,
+            0xC68B => IfdEntryTag::OriginalRawFileName,
+            0xC68D => IfdEntryTag::ActiveArea,
             // Add other tag value mappings as needed.
             _ => IfdEntryTag::Unknown(tag_value),
         }
@@ -218,6 +317,10 @@ impl IfdEntryTag {
             IfdEntryTag::Software => 0x131,
             IfdEntryTag::DateTime => 0x132,
             IfdEntryTag::Artist => 0x13B,
+            IfdEntryTag::TileWidth => 0x142,
+            IfdEntryTag::TileLength => 0x143,
+            IfdEntryTag::TileOffsets => 0x144,
+            IfdEntryTag::TileByteCounts => 0x145,
             IfdEntryTag::SubIFDS => 0x14A,
             IfdEntryTag::JpgFromRawStart => 0x201,
             IfdEntryTag::JpgFromRawLength => 0x202,
@@ -253,6 +356,7 @@ impl IfdEntryTag {
             IfdEntryTag::SubSecTime => 0x9290,
             IfdEntryTag::SubSecTimeOriginal => 0x9291,
             IfdEntryTag::SubSecTimeDigitized => 0x9292,
+            IfdEntryTag::InteropIFDPointer => 0xA005,
             IfdEntryTag::NikonAFInfo2 => 0xA217,
             IfdEntryTag::FileSource => 0xA300,
             IfdEntryTag::NikonCaptureVersion => 0xA301,
@@ -267,6 +371,27 @@ impl IfdEntryTag {
             IfdEntryTag::NikonCaptureColorHue => 0xA409,
             IfdEntryTag::NikonCaptureSaturation => 0xA40A,
             IfdEntryTag::NikonCaptureNoiseReduction => 0xA40C,
+            IfdEntryTag::LensSpecification => 0xA432,
+            IfdEntryTag::LensMake => 0xA433,
+            IfdEntryTag::LensModel => 0xA434,
+            IfdEntryTag::DNGVersion => 0xC612,
+            IfdEntryTag::DNGBackwardVersion => 0xC613,
+            IfdEntryTag::UniqueCameraModel => 0xC614,
+            IfdEntryTag::CFAPlaneColor => 0xC616,
+            IfdEntryTag::CFALayout => 0xC617,
+            IfdEntryTag::BlackLevelRepeatDim => 0xC619,
+            IfdEntryTag::BlackLevel => 0xC61A,
+            IfdEntryTag::WhiteLevel => 0xC61D,
+            IfdEntryTag::DefaultCropOrigin => 0xC61F,
+            IfdEntryTag::DefaultCropSize => 0xC620,
+            IfdEntryTag::ColorMatrix1 => 0xC621,
+            IfdEntryTag::ColorMatrix2 => 0xC622,
+            IfdEntryTag::AsShotNeutral => 0xC628,
+            IfdEntryTag::DNGPrivateData => 0xC634,
+            IfdEntryTag::CalibrationIlluminant1 => 0xC65A,
+            IfdEntryTag::CalibrationIlluminant2 => 0xC65B,
+            IfdEntryTag::OriginalRawFileName => 0xC68B,
+            IfdEntryTag::ActiveArea => 0xC68D,
             IfdEntryTag::Unknown(value) => *value as u16,
         }
     }
@@ -311,129 +436,404 @@ impl IfdEntryType {
     }
 }
 
+impl IfdValue {
+    /// Number of components; characters for ASCII values.
+    pub fn len(&self) -> usize {
+        match self {
+            IfdValue::Bytes(values) | IfdValue::Undefined(values) => values.len(),
+            IfdValue::Ascii(text) => text.len(),
+            IfdValue::Shorts(values) => values.len(),
+            IfdValue::Longs(values) => values.len(),
+            IfdValue::Rationals(values) => values.len(),
+            IfdValue::SBytes(values) => values.len(),
+            IfdValue::SShorts(values) => values.len(),
+            IfdValue::SLongs(values) => values.len(),
+            IfdValue::SRationals(values) => values.len(),
+            IfdValue::Floats(values) => values.len(),
+            IfdValue::Doubles(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The integer component at `index`. Negative values give `None`.
+    pub fn get_u32(&self, index: usize) -> Option<u32> {
+        match self {
+            IfdValue::Bytes(values) | IfdValue::Undefined(values) => {
+                values.get(index).map(|&v| v as u32)
+            }
+            IfdValue::Shorts(values) => values.get(index).map(|&v| v as u32),
+            IfdValue::Longs(values) => values.get(index).copied(),
+            _ => self.get_i32(index).and_then(|v| u32::try_from(v).ok()),
+        }
+    }
+
+    /// The integer component at `index`, for signed and unsigned types alike.
+    pub fn get_i32(&self, index: usize) -> Option<i32> {
+        match self {
+            IfdValue::SBytes(values) => values.get(index).map(|&v| v as i32),
+            IfdValue::SShorts(values) => values.get(index).map(|&v| v as i32),
+            IfdValue::SLongs(values) => values.get(index).copied(),
+            IfdValue::Bytes(_) | IfdValue::Undefined(_) | IfdValue::Shorts(_) => {
+                self.get_u32(index).map(|v| v as i32)
+            }
+            IfdValue::Longs(values) => values.get(index).and_then(|&v| i32::try_from(v).ok()),
+            _ => None,
+        }
+    }
+
+    /// Any numeric component at `index` as a float; rationals are divided out.
+    pub fn get_f64(&self, index: usize) -> Option<f64> {
+        match self {
+            IfdValue::Rationals(values) => values
+                .get(index)
+                .filter(|(_, d)| *d != 0)
+                .map(|&(n, d)| n as f64 / d as f64),
+            IfdValue::SRationals(values) => values
+                .get(index)
+                .filter(|(_, d)| *d != 0)
+                .map(|&(n, d)| n as f64 / d as f64),
+            IfdValue::Floats(values) => values.get(index).map(|&v| v as f64),
+            IfdValue::Doubles(values) => values.get(index).copied(),
+            IfdValue::Ascii(_) => None,
+            _ => self.get_i32(index).map(|v| v as f64),
+        }
+    }
+
+    pub fn as_u32(&self) -> Option<u32> {
+        self.get_u32(0)
+    }
+
+    pub fn as_i32(&self) -> Option<i32> {
+        self.get_i32(0)
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        self.get_f64(0)
+    }
+
+    pub fn as_rational(&self) -> Option<(u32, u32)> {
+        match self {
+            IfdValue::Rationals(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    pub fn as_srational(&self) -> Option<(i32, i32)> {
+        match self {
+            IfdValue::SRationals(values) => values.first().copied(),
+            _ => None,
+        }
+    }
+
+    /// Text of ASCII values, and of byte values that hold text (as many Nikon
+    /// tags do), with trailing NULs and padding removed.
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            IfdValue::Ascii(text) => Some(text.trim_end().to_owned()),
+            IfdValue::Bytes(bytes) | IfdValue::Undefined(bytes) => {
+                let text = bytes.split(|&byte| byte == 0).next().unwrap_or_default();
+                Some(String::from_utf8_lossy(text).trim_end().to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            IfdValue::Bytes(bytes) | IfdValue::Undefined(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for IfdValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn join<T: fmt::Display>(values: &[T]) -> String {
+            values
+                .iter()
+                .map(|value| value.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        // Long binary blobs are shortened to their first bytes
+        fn hex(bytes: &[u8]) -> String {
+            let shown: Vec<String> = bytes.iter().take(16).map(|b| format!("{b:02X}")).collect();
+            if bytes.len() > 16 {
+                format!("{} ... ({} bytes)", shown.join(" "), bytes.len())
+            } else {
+                shown.join(" ")
+            }
+        }
+        match self {
+            IfdValue::Bytes(bytes) | IfdValue::Undefined(bytes) => write!(f, "{}", hex(bytes)),
+            IfdValue::Ascii(text) => write!(f, "{text}"),
+            IfdValue::Shorts(values) => write!(f, "{}", join(values)),
+            IfdValue::Longs(values) => write!(f, "{}", join(values)),
+            IfdValue::Rationals(values) => {
+                let values: Vec<String> = values.iter().map(|(n, d)| format!("{n}/{d}")).collect();
+                write!(f, "{}", values.join(", "))
+            }
+            IfdValue::SBytes(values) => write!(f, "{}", join(values)),
+            IfdValue::SShorts(values) => write!(f, "{}", join(values)),
+            IfdValue::SLongs(values) => write!(f, "{}", join(values)),
+            IfdValue::SRationals(values) => {
+                let values: Vec<String> = values.iter().map(|(n, d)| format!("{n}/{d}")).collect();
+                write!(f, "{}", values.join(", "))
+            }
+            IfdValue::Floats(values) => write!(f, "{}", join(values)),
+            IfdValue::Doubles(values) => write!(f, "{}", join(values)),
+        }
+    }
+}
+
+impl IfdRole {
+    /// Classifies an image IFD by its content. The full-resolution raw image
+    /// is the main image (NewSubfileType 0) with CFA photometric interpretation
+    /// and Nikon compressed (34713), uncompressed (1) or, in DNGs, lossless
+    /// JPEG (7) data.
+    pub fn classify(ifd: &Ifd) -> IfdRole {
+        let value = |tag| ifd.get_entry(tag).map(|entry| entry.get_data_or_offset());
+        let subfile_type = value(IfdEntryTag::NewSubfileType).unwrap_or(0);
+        let photometric = value(IfdEntryTag::PhotometricInterpretation);
+        let compression = value(IfdEntryTag::Compression);
+
+        if subfile_type == 0
+            && photometric == Some(32803)
+            && matches!(compression, Some(1 | 7 | 34713))
+        {
+            IfdRole::Raw
+        } else if subfile_type & 1 == 1
+            || ifd.get_entry(IfdEntryTag::JpgFromRawStart).is_some()
+            || matches!(compression, Some(6 | 7))
+        {
+            IfdRole::Preview
+        } else {
+            IfdRole::Unknown
+        }
+    }
+
+    fn from_pointer(tag: IfdEntryTag) -> Option<IfdRole> {
+        match tag {
+            IfdEntryTag::ExifIFDPointer => Some(IfdRole::Exif),
+            IfdEntryTag::GPSInfo => Some(IfdRole::Gps),
+            IfdEntryTag::MakerNote => Some(IfdRole::MakerNote),
+            _ => None,
+        }
+    }
+}
+
 impl Ifd {
-    pub fn parse_ifd(buffer: &[u8], offset: usize) -> Vec<Self> {
+    /// Parses the IFD at `offset` and every IFD reachable from it.
+    ///
+    /// The byte order is taken from a TIFF header at `offset` (a plain file
+    /// header or the one embedded in a Nikon MakerNote). Without a header the
+    /// directory is read as little-endian; linked IFDs inherit the byte order
+    /// of the IFD pointing to them.
+    ///
+    /// Every offset is parsed at most once, so directories pointing back at
+    /// themselves or at each other cannot make the parser loop.
+    pub fn parse_ifd(buffer: &[u8], offset: usize) -> Result<Vec<Self>, NefError> {
+        let mut visited = HashSet::new();
+        Self::parse_ifd_with_endian(buffer, offset, Endian::Little, 0, &mut visited)
+    }
+
+    fn parse_ifd_with_endian(
+        buffer: &[u8],
+        offset: usize,
+        endian: Endian,
+        base_offset: usize,
+        visited: &mut HashSet<usize>,
+    ) -> Result<Vec<Self>, NefError> {
         let mut ifds = Vec::new();
         let mut internal_offset;
         let mut nikon_mapping = false;
-        let ifd_buffer = &buffer[offset..];
+        let mut endian = endian;
+        let mut base_offset = base_offset;
+        let ifd_buffer = buffer.get(offset..).ok_or(NefError::OutOfBounds {
+            offset,
+            len: buffer.len().saturating_sub(offset),
+        })?;
         let mut offset_location = offset;
+        // Reads are checked against the whole file so errors report absolute offsets
+        let read = |start: usize, len: usize| checked_slice(buffer, offset + start, len);
 
         let nikon_bytes = [0x4E, 0x69, 0x6B, 0x6F, 0x6E];
-        let nikon_patterns = vec![
+        let nikon_patterns = [
             [nikon_bytes, [0x00, 0x02, 0x00, 0x00, 0x00]].concat(),
             [nikon_bytes, [0x00, 0x02, 0x10, 0x00, 0x00]].concat(),
             [nikon_bytes, [0x00, 0x02, 0x11, 0x00, 0x00]].concat(),
         ];
 
-        // println!("{:?}", &buffer[0..10]);
-        if ifd_buffer[0..8] == [73, 73, 42, 0, 8, 0, 0, 0] {
-            internal_offset = 8;
+        if let Some(header_endian) = Endian::from_tiff_header(ifd_buffer) {
+            endian = header_endian;
+            internal_offset = endian.u32(read(4, 4)?) as usize;
         } else if nikon_patterns
             .iter()
-            .any(|pattern| pattern == &ifd_buffer[0..10])
+            .any(|pattern| Some(pattern.as_slice()) == ifd_buffer.get(0..10))
         {
-            internal_offset = 10 + 8; // 10 nikon header bytes + 8 tiff header bytes
+            // 10 nikon header bytes followed by an embedded tiff header, which
+            // sets the byte order and the base for all offsets in the MakerNote
+            let tiff_header = read(10, 8)?;
+            endian = Endian::from_tiff_header(tiff_header).unwrap_or(endian);
+            internal_offset = 10 + endian.u32(&tiff_header[4..8]) as usize;
             nikon_mapping = true;
             offset_location += 10;
-            // println!("Nikon mapping");
+            base_offset = offset_location;
         } else {
             internal_offset = 0; // ifd starts right away
         }
 
-        let num_entries = bytes_to_num(&ifd_buffer[internal_offset..internal_offset + 2]);
+        // Directories are tracked by their own position, so a pointer to the
+        // file header and one to IFD0 are recognized as the same directory
+        if !visited.insert(offset + internal_offset) {
+            return Ok(ifds);
+        }
+
+        let num_entries = bytes_to_num(read(internal_offset, 2)?, endian);
         internal_offset += 2;
 
-        let ifd_entries = Vec::with_capacity(num_entries);
+        let entries_data = read(internal_offset, num_entries * 12)?;
         let mut ifd = Ifd {
             name: None::<Arc<str>>,
             offset_location,
-            entries: ifd_entries,
+            endian,
+            role: IfdRole::Unknown,
+            entries: Vec::with_capacity(num_entries),
+            base_offset,
+            next_ifd_offset: None,
         };
 
-        for _ in 0..num_entries {
-            let ifd_entry;
-            let entry_data = &ifd_buffer[internal_offset..internal_offset + 12]
-                .try_into()
-                .unwrap();
-            if !nikon_mapping {
-                ifd_entry = IfdEntry::parse_entry(entry_data);
-            } else {
-                // ifd_entry = IfdEntryNikon::parse_entry(entry_data);
-                ifd_entry = IfdEntry::parse_entry(entry_data);
-            }
+        for entry_data in entries_data.chunks_exact(12) {
+            let entry_data = entry_data.try_into().expect("chunks are 12 bytes");
+            ifd.entries
+                .push(IfdEntry::parse_entry(entry_data, endian, base_offset));
+        }
+        ifd.role = IfdRole::classify(&ifd);
+        internal_offset += num_entries * 12;
 
-            ifd.entries.push(ifd_entry);
-            internal_offset += 12;
+        // The chain of the MakerNote IFD itself is not followed
+        if !nikon_mapping {
+            let offset_of_next_ifd = bytes_to_num(read(internal_offset, 4)?, endian);
+            ifd.next_ifd_offset = Some(offset_of_next_ifd)
+                .filter(|&offset| offset != 0)
+                .map(|offset| base_offset + offset);
         }
         ifds.push(ifd.clone());
-        // println!("Successfully fetched first IFD");
 
-        if !nikon_mapping {
+        if nikon_mapping {
+            // Nikon PreviewIFD (0x0011) holding the MakerNote JPEG preview
+            Self::try_fetch_ifds(
+                &ifd,
+                TagParam::IfdEntry(IfdEntryTag::Unknown(0x11)),
+                buffer,
+                &mut ifds,
+                visited,
+            );
+        } else {
             Self::try_fetch_ifds(
                 &ifd,
                 TagParam::IfdEntry(IfdEntryTag::SubIFDS),
                 buffer,
                 &mut ifds,
+                visited,
             );
             Self::try_fetch_ifds(
                 &ifd,
                 TagParam::IfdEntry(IfdEntryTag::ExifIFDPointer),
                 buffer,
                 &mut ifds,
+                visited,
+            );
+            Self::try_fetch_ifds(
+                &ifd,
+                TagParam::IfdEntry(IfdEntryTag::GPSInfo),
+                buffer,
+                &mut ifds,
+                visited,
             );
             Self::try_fetch_ifds(
                 &ifd,
                 TagParam::IfdEntry(IfdEntryTag::MakerNote),
                 buffer,
                 &mut ifds,
+                visited,
             );
 
             // fetch ifds linked at the end
-            let offset_to_next_ifd = &ifd_buffer[internal_offset..internal_offset + 4];
-            if offset_to_next_ifd == [0, 0, 0, 0] {
-                // println!("No linked ifd at the end of the current ifd");
-            } else {
-                let offset_of_next_ifd = bytes_to_num(&offset_to_next_ifd);
+            if let Some(offset_of_next_ifd) = ifd.next_ifd_offset {
                 Self::try_fetch_ifds(
                     &ifd,
-                    TagParam::U8(offset_of_next_ifd as u8),
+                    TagParam::Offset(offset_of_next_ifd),
                     buffer,
                     &mut ifds,
+                    visited,
                 );
             }
         }
 
-        ifds
+        Ok(ifds)
     }
 
-    fn try_fetch_ifds(ifd: &Ifd, tag: TagParam, buffer: &[u8], ifds: &mut Vec<Ifd>) {
+    /// Parses the directories `tag` leads to and appends them to `ifds`.
+    ///
+    /// Linked directories are optional: one that cannot be read, such as a
+    /// truncated GPS IFD or a MakerNote in an unknown format, is skipped so
+    /// the rest of the file stays readable. Callers that need a directory,
+    /// like the raw IFD, report it as missing.
+    fn try_fetch_ifds(
+        ifd: &Ifd,
+        tag: TagParam,
+        buffer: &[u8],
+        ifds: &mut Vec<Ifd>,
+        visited: &mut HashSet<usize>,
+    ) {
         let mut ifd_offsets: Vec<usize> = Vec::new();
         match tag {
             TagParam::IfdEntry(ifd_tag) => {
                 if let Some(offset_to_ifd) = ifd.get_entry(ifd_tag) {
-                    println!("Parsing ifd from tag: {:?}", ifd_tag);
                     if offset_to_ifd.offset && ifd_tag != IfdEntryTag::MakerNote {
-                        println!("IFD {:?} offset true", ifd_tag);
                         let bytes_per_comp = offset_to_ifd.data_type.bytes_per_component();
-                        let offset_data = offset_to_ifd.get_offset_data(&buffer);
+                        let Ok(offset_data) = offset_to_ifd.get_offset_data(buffer) else {
+                            return;
+                        };
                         ifd_offsets = offset_data
-                            .chunks(bytes_per_comp as usize)
-                            .map(|chunk| bytes_to_num(chunk))
+                            .chunks(bytes_per_comp.max(1) as usize)
+                            .map(|chunk| ifd.base_offset + bytes_to_num(chunk, ifd.endian))
                             .collect();
                     } else {
-                        ifd_offsets.push(bytes_to_num(&offset_to_ifd.data_or_offset));
+                        ifd_offsets.push(
+                            ifd.base_offset
+                                + bytes_to_num(&offset_to_ifd.data_or_offset, ifd.endian),
+                        );
                     }
                 }
             }
-            TagParam::U8(u8_val) => {
-                ifd_offsets.push(u8_val as usize);
+            TagParam::Offset(offset) => {
+                ifd_offsets.push(offset);
             }
         }
         for ifd_offset in ifd_offsets {
-            let mut sub_ifds = Self::parse_ifd(buffer.clone(), ifd_offset);
+            let Ok(mut sub_ifds) = Self::parse_ifd_with_endian(
+                buffer,
+                ifd_offset,
+                ifd.endian,
+                ifd.base_offset,
+                visited,
+            ) else {
+                continue;
+            };
+            // The directory a pointer tag leads to is known by the tag rather
+            // than by its content
+            if let TagParam::IfdEntry(ifd_tag) = tag
+                && let Some(role) = IfdRole::from_pointer(ifd_tag)
+                && let Some(pointed_ifd) = sub_ifds.first_mut()
+            {
+                pointed_ifd.role = role;
+            }
             ifds.append(&mut sub_ifds);
-            println!("Successfully fetched {:?} IFD at {}", tag, ifd_offset);
         }
     }
 
@@ -441,6 +841,11 @@ impl Ifd {
         self.entries.iter().find(|entry| entry.tag == ifd_name)
     }
 
+    pub fn get_required_entry(&self, ifd_name: IfdEntryTag) -> Result<&IfdEntry, NefError> {
+        self.get_entry(ifd_name)
+            .ok_or(NefError::MissingTag(ifd_name))
+    }
+
     pub fn get_entry_by_byte(&self, ifd_name: u16) -> Option<&IfdEntry> {
         self.entries
             .iter()
@@ -463,112 +868,138 @@ impl Ifd {
             print!("Data length: {:?}. ", entry.data_length);
             print!(
                 "Data value or offset to data value: {:#02X}, {:?}, {:?}. ",
-                bytes_to_num(&entry.data_or_offset),
-                bytes_to_num(&entry.data_or_offset),
+                entry.get_data_or_offset(),
+                entry.get_data_or_offset(),
                 entry.data_or_offset
             );
             println!("Offset: {}.", entry.offset);
         }
         println!("Start of IFD: {:?}", &self.offset_location);
+        println!("Byte order: {:?}", &self.endian);
+        println!("Role: {:?}", &self.role);
         println!("-------------------------");
         // println!("End of IFD: {:?}", &self.end);
         for ifd_entry in &self.entries {
             print_ifd_entry(ifd_entry);
         }
-        // if self.offset_to_next_ifd == 0 {
-        //     println!("No linked IFD! Value {:?}", &self.offset_to_next_ifd)
-        // } else {
-        //     println!("Offset to next IFD: {:?}", &self.offset_to_next_ifd)
-        // }
+        match self.next_ifd_offset {
+            None => println!("No linked IFD!"),
+            Some(offset) => println!("Offset to next IFD: {:?}", offset),
+        }
         println!("-------------------------");
     }
 
-    pub fn get_encoded_data(&self, buffer: Arc<[u8]>) -> Option<Arc<[u8]>> {
-        if let (Some(strip_offsets), Some(strip_byte_counts)) = (
-            self.get_entry(IfdEntryTag::StripOffsets),
-            self.get_entry(IfdEntryTag::StripByteCounts),
-        ) {
-            let encoded_data = buffer[strip_offsets.data_or_offset[0] as usize
-                ..strip_offsets.data_or_offset[0] as usize
-                    + strip_byte_counts.data_or_offset[0] as usize]
-                .to_vec();
-            Some(Arc::from(encoded_data))
-        } else {
-            println!("Image data not found");
-            None
-        }
+    pub fn get_encoded_data(&self, buffer: Arc<[u8]>) -> Result<Arc<[u8]>, NefError> {
+        let strip_offset = self
+            .get_required_entry(IfdEntryTag::StripOffsets)?
+            .get_data_or_offset();
+        let strip_length = self
+            .get_required_entry(IfdEntryTag::StripByteCounts)?
+            .get_data_or_offset();
+        let encoded_data = checked_slice(&buffer, strip_offset, strip_length)?;
+        Ok(Arc::from(encoded_data))
     }
 }
 
 impl IfdEntry {
-    pub fn parse_entry(data: &[u8; 12]) -> Self {
+    pub fn parse_entry(data: &[u8; 12], endian: Endian, base_offset: usize) -> Self {
         let raw_entry = *data;
-        let tag = IfdEntryTag::from(bytes_to_num(&data[0..2]));
-        let data_type = IfdEntryType::from(bytes_to_num(&data[2..4]));
-        let num_values = bytes_to_num(&data[4..8]);
-        let data_length = num_values * data_type.bytes_per_component() as usize;
+        let tag = IfdEntryTag::from(bytes_to_num(&data[0..2], endian));
+        let data_type = IfdEntryType::from(bytes_to_num(&data[2..4], endian));
+        let count = bytes_to_num(&data[4..8], endian);
+        let data_length = count * data_type.bytes_per_component() as usize;
         let offset = data_length > 4;
         let data_or_offset = [data[8], data[9], data[10], data[11]];
         Self {
             tag,
             data_type,
             data_or_offset,
+            count,
             data_length,
             offset,
+            endian,
+            base_offset,
             raw_entry,
         }
     }
-    pub fn get_human_readable_value(&self) -> String {
-        if !self.offset {
-            // Value is stored directly in the data_or_offset field
-            match self.data_type {
-                // IfdType::UnsignedByte(_) => {
-                //     format_bytes_per_component_u8(&self.data_or_offset, num_components)
-                // }
-                IfdEntryType::AsciiString(_) => {
-                    String::from_utf8_lossy(&self.data_or_offset).to_string()
-                }
-                _ => String::from("Not implemented yet"), // IfdType::UnsignedShort(_) => {
-                                                          //     format_bytes_per_component_u16(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::UnsignedLong(_) => {
-                                                          //     format_bytes_per_component_u32(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::UnsignedRational(_) => {
-                                                          //     format_rational(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::SignedByte(_) => {
-                                                          //     format_bytes_per_component_i8(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::Undefined(_) => {
-                                                          //     format_bytes_per_component_u8(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::SignedShort(_) => {
-                                                          //     format_bytes_per_component_i16(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::SignedLong(_) => {
-                                                          //     format_bytes_per_component_i32(&self.data_or_offset, num_components)
-                                                          // }
-                                                          // IfdType::SignedRational(_) => format_rational(&self.data_or_offset, num_components),
-                                                          // IfdType::SingleFloat(_) => format_float(&self.data_or_offset, num_components, 4),
-                                                          // IfdType::DoubleFloat(_) => format_float(&self.data_or_offset, num_components, 8),
-            }
-        } else {
-            // Value is stored at an offset in the file
-            String::from_utf8_lossy(&self.data_or_offset).to_string()
+
+    pub fn get_human_readable_value(&self, buffer: &[u8]) -> String {
+        match self.get_value(buffer) {
+            Ok(value) => value.to_string(),
+            Err(e) => format!("<{e}>"),
         }
     }
 
+    /// Decodes all `count` components of the value, read from the entry
+    /// itself when they fit into four bytes and from `buffer` otherwise.
+    pub fn get_value(&self, buffer: &[u8]) -> Result<IfdValue, NefError> {
+        let bytes = if self.offset {
+            self.get_offset_data(buffer)?
+        } else {
+            &self.data_or_offset[..self.data_length]
+        };
+        let endian = self.endian;
+        let u16s = || bytes.chunks_exact(2).map(|c| endian.u16(c));
+        let u32s = || bytes.chunks_exact(4).map(|c| endian.u32(c));
+        let pairs = || {
+            bytes
+                .chunks_exact(8)
+                .map(|c| (endian.u32(&c[0..4]), endian.u32(&c[4..8])))
+        };
+        let u64s = || bytes.chunks_exact(8).map(|c| endian.u64(c));
+
+        let value = match self.data_type {
+            IfdEntryType::UnsignedByte(_) => IfdValue::Bytes(bytes.to_vec()),
+            IfdEntryType::AsciiString(_) => {
+                let text = bytes.split(|&byte| byte == 0).next().unwrap_or_default();
+                IfdValue::Ascii(String::from_utf8_lossy(text).into_owned())
+            }
+            IfdEntryType::UnsignedShort(_) => IfdValue::Shorts(u16s().collect()),
+            IfdEntryType::UnsignedLong(_) => IfdValue::Longs(u32s().collect()),
+            IfdEntryType::UnsignedRational(_) => IfdValue::Rationals(pairs().collect()),
+            IfdEntryType::SignedByte(_) => {
+                IfdValue::SBytes(bytes.iter().map(|&byte| byte as i8).collect())
+            }
+            IfdEntryType::Undefined(_) | IfdEntryType::Unknown(_) => {
+                IfdValue::Undefined(bytes.to_vec())
+            }
+            IfdEntryType::SignedShort(_) => {
+                IfdValue::SShorts(u16s().map(|short| short as i16).collect())
+            }
+            IfdEntryType::SignedLong(_) => {
+                IfdValue::SLongs(u32s().map(|long| long as i32).collect())
+            }
+            IfdEntryType::SignedRational(_) => {
+                IfdValue::SRationals(pairs().map(|(n, d)| (n as i32, d as i32)).collect())
+            }
+            IfdEntryType::SingleFloat(_) => IfdValue::Floats(u32s().map(f32::from_bits).collect()),
+            IfdEntryType::DoubleFloat(_) => IfdValue::Doubles(u64s().map(f64::from_bits).collect()),
+        };
+        Ok(value)
+    }
+
+    /// Returns the inline value, or the offset to the value when it does not
+    /// fit into the entry. Inline bytes and shorts are left-justified in the
+    /// four byte field, so only the first component is read for them.
     pub fn get_data_or_offset(&self) -> usize {
-        bytes_to_num(&self.data_or_offset)
+        let width = if self.offset {
+            4
+        } else {
+            match self.data_type.bytes_per_component() {
+                1 => 1,
+                2 => 2,
+                _ => 4,
+            }
+        };
+        bytes_to_num(&self.data_or_offset[..width], self.endian)
     }
 
-    pub fn get_offset_data<'a>(&self, buffer: &'a [u8]) -> &'a [u8] {
+    pub fn get_offset_data<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8], NefError> {
         if self.offset {
-            let offset = bytes_to_num(&self.data_or_offset) as usize;
-            &buffer[offset..offset + self.data_length]
+            let offset = self.base_offset + bytes_to_num(&self.data_or_offset, self.endian);
+            checked_slice(buffer, offset, self.data_length)
         } else {
-            &[]
+            Ok(&[])
         }
     }
 
@@ -576,3 +1007,251 @@ impl IfdEntry {
         self.raw_entry
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// IFD0 at 8 with ImageWidth SHORT 0x0123 and ImageLength LONG
+    /// 0x00010203, and no next IFD.
+    const BIG_ENDIAN: [u8; 38] = [
+        b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, // header
+        0x00, 0x02, // entry count
+        0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x01, 0x23, 0x00, 0x00, // width
+        0x01, 0x01, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x02, 0x03, // length
+        0x00, 0x00, 0x00, 0x00, // next IFD
+    ];
+
+    /// The same directory in little-endian order.
+    const LITTLE_ENDIAN: [u8; 38] = [
+        b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, // header
+        0x02, 0x00, // entry count
+        0x00, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x23, 0x01, 0x00, 0x00, // width
+        0x01, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x02, 0x01, 0x00, // length
+        0x00, 0x00, 0x00, 0x00, // next IFD
+    ];
+
+    /// IFD0 pointing to an Exif IFD at 50 holding ISOSpeedRatings 200 and
+    /// to a GPS IFD at 68 that claims five entries but ends after the count.
+    const TRUNCATED_GPS: [u8; 70] = [
+        b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, // header
+        0x00, 0x03, // entry count
+        0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x01, 0x23, 0x00, 0x00, // width
+        0x87, 0x69, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x32, // Exif
+        0x88, 0x25, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x44, // GPS
+        0x00, 0x00, 0x00, 0x00, // next IFD
+        0x00, 0x01, // Exif entry count
+        0x88, 0x27, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0xC8, 0x00, 0x00, // ISO
+        0x00, 0x00, 0x00, 0x00, // next IFD
+        0x00, 0x05, // GPS entry count
+    ];
+
+    /// A little-endian directory of SHORT entries.
+    fn directory(entries: &[(u16, u16)]) -> Ifd {
+        let entries = entries
+            .iter()
+            .map(|&(tag, value)| {
+                let mut data = [0; 12];
+                data[0..2].copy_from_slice(&tag.to_le_bytes());
+                data[2..4].copy_from_slice(&3_u16.to_le_bytes());
+                data[4..8].copy_from_slice(&1_u32.to_le_bytes());
+                data[8..10].copy_from_slice(&value.to_le_bytes());
+                IfdEntry::parse_entry(&data, Endian::Little, 0)
+            })
+            .collect();
+        Ifd {
+            name: None,
+            offset_location: 0,
+            endian: Endian::Little,
+            role: IfdRole::Unknown,
+            entries,
+            base_offset: 0,
+            next_ifd_offset: None,
+        }
+    }
+
+    #[test]
+    fn detects_the_byte_order() {
+        assert_eq!(Endian::from_tiff_header(&BIG_ENDIAN), Some(Endian::Big));
+        assert_eq!(
+            Endian::from_tiff_header(&LITTLE_ENDIAN),
+            Some(Endian::Little)
+        );
+        assert_eq!(Endian::from_tiff_header(b"MM*\0"), None);
+        assert_eq!(Endian::from_tiff_header(b"II"), None);
+    }
+
+    #[test]
+    fn parses_both_byte_orders() {
+        for (file, endian) in [(&BIG_ENDIAN, Endian::Big), (&LITTLE_ENDIAN, Endian::Little)] {
+            let ifds = Ifd::parse_ifd(file, 0).unwrap();
+            assert_eq!(ifds.len(), 1);
+            let ifd = &ifds[0];
+            assert_eq!(ifd.endian, endian);
+            assert_eq!(ifd.next_ifd_offset, None);
+            let value = |tag| ifd.get_entry(tag).unwrap().get_data_or_offset();
+            assert_eq!(value(IfdEntryTag::ImageWidth), 0x0123, "{endian:?}");
+            assert_eq!(value(IfdEntryTag::ImageLength), 0x0001_0203, "{endian:?}");
+        }
+    }
+
+    #[test]
+    fn skips_unreadable_sub_ifds() {
+        let ifds = Ifd::parse_ifd(&TRUNCATED_GPS, 0).unwrap();
+        let roles: Vec<IfdRole> = ifds.iter().map(|ifd| ifd.role).collect();
+        assert_eq!(roles, [IfdRole::Unknown, IfdRole::Exif]);
+        let iso = ifds[1].get_entry(IfdEntryTag::ISOSpeedRatings).unwrap();
+        assert_eq!(iso.get_data_or_offset(), 200);
+
+        // A pointer past the end of the file is skipped the same way
+        let mut past_end = TRUNCATED_GPS;
+        past_end[45] = 0xFF;
+        assert_eq!(Ifd::parse_ifd(&past_end, 0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn truncated_directory_is_an_error() {
+        assert!(matches!(
+            Ifd::parse_ifd(&BIG_ENDIAN[..30], 0),
+            Err(NefError::OutOfBounds { .. })
+        ));
+    }
+
+    #[test]
+    fn classifies_image_directories_by_content() {
+        const SUBFILE: u16 = 0xFE;
+        const COMPRESSION: u16 = 0x103;
+        const PHOTOMETRIC: u16 = 0x106;
+        let role = |entries: &[(u16, u16)]| IfdRole::classify(&directory(entries));
+
+        // Nikon compressed, uncompressed and DNG lossless JPEG CFA data
+        for compression in [34713, 1, 7] {
+            assert_eq!(
+                role(&[
+                    (SUBFILE, 0),
+                    (COMPRESSION, compression),
+                    (PHOTOMETRIC, 32803)
+                ]),
+                IfdRole::Raw,
+                "compression {compression}"
+            );
+        }
+        // The RGB thumbnail of IFD0, a JPEG preview and a reduced CFA image
+        assert_eq!(
+            role(&[(SUBFILE, 1), (COMPRESSION, 1), (PHOTOMETRIC, 2)]),
+            IfdRole::Preview
+        );
+        assert_eq!(
+            role(&[(COMPRESSION, 6), (PHOTOMETRIC, 6)]),
+            IfdRole::Preview
+        );
+        assert_eq!(
+            role(&[(SUBFILE, 1), (COMPRESSION, 34713), (PHOTOMETRIC, 32803)]),
+            IfdRole::Preview
+        );
+        assert_eq!(role(&[(SUBFILE, 0), (COMPRESSION, 1)]), IfdRole::Unknown);
+        assert_eq!(role(&[]), IfdRole::Unknown);
+    }
+
+    #[test]
+    fn follows_next_ifd_offsets_past_255() {
+        // IFD0 at 8 links IFD1 at 300, which links back to IFD0
+        let mut file = vec![0; 318];
+        file[..8].copy_from_slice(&[b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00]);
+        for (offset, width, next) in [(8, 0x0123_u16, 300_u32), (300, 0x0456, 8)] {
+            let ifd = &mut file[offset..offset + 18];
+            ifd[..10]
+                .copy_from_slice(&[0x01, 0x00, 0x00, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00]);
+            ifd[10..12].copy_from_slice(&width.to_le_bytes());
+            ifd[14..18].copy_from_slice(&next.to_le_bytes());
+        }
+
+        let ifds = Ifd::parse_ifd(&file, 0).unwrap();
+        let widths: Vec<usize> = ifds
+            .iter()
+            .map(|ifd| {
+                ifd.get_entry(IfdEntryTag::ImageWidth)
+                    .unwrap()
+                    .get_data_or_offset()
+            })
+            .collect();
+        assert_eq!(widths, [0x0123, 0x0456]);
+        assert_eq!(ifds[0].next_ifd_offset, Some(300));
+        assert_eq!(ifds[1].offset_location, 300);
+        assert_eq!(ifds[1].next_ifd_offset, Some(8));
+    }
+
+    #[test]
+    fn decodes_every_value_type() {
+        // Out-of-line values: RATIONAL 1/250, SRATIONAL -1/3 and DOUBLE 2.25
+        let buffer = [
+            0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0xFA, // 1/250
+            0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x03, // -1/3
+            0x40, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // 2.25
+        ];
+        let value = |data_type: u16, count: u32, data: [u8; 4]| {
+            let mut entry = [0; 12];
+            entry[2..4].copy_from_slice(&data_type.to_be_bytes());
+            entry[4..8].copy_from_slice(&count.to_be_bytes());
+            entry[8..12].copy_from_slice(&data);
+            IfdEntry::parse_entry(&entry, Endian::Big, 0)
+                .get_value(&buffer)
+                .unwrap()
+        };
+
+        assert_eq!(value(1, 3, [1, 2, 3, 0]), IfdValue::Bytes(vec![1, 2, 3]));
+        assert_eq!(value(2, 4, *b"Nik\0"), IfdValue::Ascii(String::from("Nik")));
+        assert_eq!(
+            value(3, 2, [0x01, 0x02, 0x03, 0x04]),
+            IfdValue::Shorts(vec![0x0102, 0x0304])
+        );
+        assert_eq!(
+            value(4, 1, [0, 1, 0, 0]),
+            IfdValue::Longs(vec![0x0001_0000])
+        );
+        assert_eq!(value(5, 1, [0; 4]), IfdValue::Rationals(vec![(1, 250)]));
+        assert_eq!(
+            value(6, 2, [0xFF, 0x02, 0, 0]),
+            IfdValue::SBytes(vec![-1, 2])
+        );
+        assert_eq!(
+            value(7, 4, [0xDE, 0xAD, 0xBE, 0xEF]),
+            IfdValue::Undefined(vec![0xDE, 0xAD, 0xBE, 0xEF])
+        );
+        assert_eq!(
+            value(8, 2, [0xFF, 0xFE, 0x00, 0x05]),
+            IfdValue::SShorts(vec![-2, 5])
+        );
+        assert_eq!(
+            value(9, 1, [0xFF, 0xFF, 0xFF, 0xFD]),
+            IfdValue::SLongs(vec![-3])
+        );
+        assert_eq!(
+            value(10, 1, [0, 0, 0, 8]),
+            IfdValue::SRationals(vec![(-1, 3)])
+        );
+        assert_eq!(
+            value(11, 1, [0x3F, 0xC0, 0, 0]),
+            IfdValue::Floats(vec![1.5])
+        );
+        assert_eq!(value(12, 1, [0, 0, 0, 16]), IfdValue::Doubles(vec![2.25]));
+    }
+
+    #[test]
+    fn converts_between_numeric_types() {
+        assert_eq!(IfdValue::Rationals(vec![(1, 250)]).as_f64(), Some(0.004));
+        assert_eq!(IfdValue::Rationals(vec![(1, 0)]).as_f64(), None);
+        assert_eq!(IfdValue::SRationals(vec![(-1, 4)]).as_f64(), Some(-0.25));
+        assert_eq!(IfdValue::SShorts(vec![-2]).as_i32(), Some(-2));
+        assert_eq!(IfdValue::SShorts(vec![-2]).as_u32(), None);
+        assert_eq!(IfdValue::Shorts(vec![7, 9]).get_u32(1), Some(9));
+        assert_eq!(IfdValue::Longs(vec![u32::MAX]).as_i32(), None);
+        assert_eq!(IfdValue::Ascii(String::from("5")).as_f64(), None);
+        assert_eq!(
+            IfdValue::Undefined(b"0211\0\0".to_vec())
+                .as_string()
+                .as_deref(),
+            Some("0211")
+        );
+    }
+}
@@ -0,0 +1,18 @@
+pub mod camera;
+pub mod color;
+pub mod decrypt;
+pub mod demosaic;
+pub mod develop;
+pub mod dng;
+pub mod error;
+pub mod huffmanv2;
+pub mod ifd;
+pub mod linearization;
+pub mod makernote;
+pub mod nef;
+pub mod orientation;
+pub mod packed;
+pub mod raw_image;
+pub mod tiff_writer;
+pub mod utils;
+pub mod white_balance;
@@ -0,0 +1,238 @@
+use crate::error::NefError;
+use crate::utils::{Endian, checked_slice};
+
+/// The NEFLinearizationTable (MakerNote 0x96): the decompression parameters
+/// of Nikon compressed raw data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinearizationTable {
+    /// The two version bytes. The first tells lossy (0x44) from lossless
+    /// (0x46) compression, 0x49 marks the layout of newer bodies.
+    pub version: (u8, u8),
+    /// Initial vertical predictors for the two rows and two columns of the
+    /// CFA pattern.
+    pub predictors: [[u16; 2]; 2],
+    /// The curve points as stored. For version 0x44 0x20 only every n-th
+    /// point of the curve is stored; see [`LinearizationTable::curve`].
+    pub points: Vec<u16>,
+    /// Row at which lossy after split files (version 0x44 0x20) switch to the
+    /// second Huffman tree.
+    pub split_row: Option<usize>,
+    /// Bits needed for the largest curve value, 12 or 14 on most bodies.
+    /// `None` if the table stores no curve.
+    pub bit_depth: Option<u32>,
+}
+
+impl LinearizationTable {
+    /// Parses the 0x96 blob, stored in the MakerNote byte order `endian`.
+    pub fn parse(data: &[u8], endian: Endian) -> Result<LinearizationTable, NefError> {
+        let version = checked_slice(data, 0, 2)?;
+        let version = (version[0], version[1]);
+        if !matches!(version.0, 0x44 | 0x46 | 0x49) {
+            return Err(NefError::UnsupportedVersion {
+                block: "NEFLinearizationTable",
+                version: format!("{:02X} {:02X}", version.0, version.1),
+            });
+        }
+        let mut pointer = 2;
+
+        // Newer bodies put 2110 bytes of other data before the predictors
+        if version.0 == 0x49 || version.1 == 0x58 {
+            pointer += 2110;
+        }
+
+        let mut predictors = [[0; 2]; 2];
+        for row in predictors.iter_mut() {
+            for value in row.iter_mut() {
+                *value = endian.read_u16(data, &mut pointer, false)?;
+            }
+        }
+
+        let curve_size = endian.read_u16(data, &mut pointer, false)? as usize;
+        let mut points = Vec::with_capacity(curve_size);
+        // Lossless tables may announce a curve but do not always store it
+        if version.0 != 0x46 || checked_slice(data, pointer, curve_size * 2).is_ok() {
+            for _ in 0..curve_size {
+                points.push(endian.read_u16(data, &mut pointer, false)?);
+            }
+        }
+
+        let split_row = if version == (0x44, 0x20) {
+            let mut split_pointer = 562;
+            Some(endian.read_u16(data, &mut split_pointer, false)? as usize)
+        } else {
+            None
+        };
+
+        let bit_depth = points
+            .iter()
+            .max()
+            .map(|&max| u16::BITS - max.leading_zeros());
+
+        Ok(LinearizationTable {
+            version,
+            predictors,
+            points,
+            split_row,
+            bit_depth,
+        })
+    }
+
+    /// A lossless table with the given initial predictors. It stores no
+    /// curve, so values decode through the identity.
+    pub fn lossless(predictors: [[u16; 2]; 2]) -> LinearizationTable {
+        LinearizationTable {
+            version: (0x46, 0x30),
+            predictors,
+            points: Vec::new(),
+            split_row: None,
+            bit_depth: None,
+        }
+    }
+
+    /// The 0x96 blob in the MakerNote byte order `endian`, as
+    /// [`LinearizationTable::parse`] reads it. The data newer bodies put
+    /// before the predictors is written as zeros.
+    pub fn to_bytes(&self, endian: Endian) -> Vec<u8> {
+        let mut out = vec![self.version.0, self.version.1];
+        if self.version.0 == 0x49 || self.version.1 == 0x58 {
+            out.resize(out.len() + 2110, 0);
+        }
+        for &value in self.predictors.iter().flatten() {
+            endian.write_u16(&mut out, value);
+        }
+        endian.write_u16(&mut out, self.points.len() as u16);
+        for &point in &self.points {
+            endian.write_u16(&mut out, point);
+        }
+        if let Some(split_row) = self.split_row {
+            let mut split = Vec::with_capacity(2);
+            endian.write_u16(&mut split, split_row as u16);
+            out.resize(out.len().max(564), 0);
+            out[562..564].copy_from_slice(&split);
+        }
+        out
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.version.0 == 0x46
+    }
+
+    /// The full curve mapping decoded values of a `bps` bit image to linear
+    /// values. Lossless files and tables without a usable curve map values to
+    /// themselves.
+    pub fn curve(&self, bps: u16) -> Vec<u16> {
+        let max = 1_usize << bps.min(16);
+        let identity = || (0..max).map(|i| i as u16).collect();
+        let csize = self.points.len();
+        let step = if csize > 1 { max / (csize - 1) } else { 0 };
+
+        if self.version == (0x44, 0x20) && step > 0 {
+            // Only every step-th point is stored, the rest is interpolated.
+            // The last stored point lies just past the end of the curve.
+            let mut curve: Vec<u16> = (0..=max).map(|i| i as u16).collect();
+            for (i, &point) in self.points.iter().enumerate().take(max / step + 1) {
+                if let Some(value) = curve.get_mut(i * step) {
+                    *value = point;
+                }
+            }
+            for i in 0..max {
+                let lower = i - i % step;
+                let upper = curve.get(lower + step).copied().unwrap_or(curve[lower]);
+                curve[i] = ((curve[lower] as usize * (step - i % step)
+                    + upper as usize * (i % step))
+                    / step) as u16;
+            }
+            curve.truncate(max);
+            curve
+        } else if !self.is_lossless() && (1..=0x4001).contains(&csize) {
+            self.points.clone()
+        } else {
+            identity()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_a_lossy_table() {
+        let blob = [
+            0x44, 0x10, // version
+            0x08, 0x00, 0x08, 0x01, 0x08, 0x02, 0x08, 0x03, // predictors
+            0x00, 0x03, // curve size
+            0x00, 0x00, 0x00, 0x64, 0x0F, 0xFF, // points
+        ];
+        let table = LinearizationTable::parse(&blob, Endian::Big).unwrap();
+        assert_eq!(table.version, (0x44, 0x10));
+        assert_eq!(table.predictors, [[0x800, 0x801], [0x802, 0x803]]);
+        assert_eq!(table.points, [0, 100, 4095]);
+        assert_eq!(table.split_row, None);
+        assert_eq!(table.bit_depth, Some(12));
+        assert!(!table.is_lossless());
+        assert_eq!(table.curve(12), [0, 100, 4095]);
+    }
+
+    #[test]
+    fn interpolates_a_lossy_after_split_curve() {
+        // Five points for a 12-bit curve, one every 1024 values, and the
+        // split row at byte 562
+        let mut blob = vec![0x44, 0x20, 0, 0, 0, 0, 0, 0, 0, 0, 0x05, 0x00];
+        for point in [0_u16, 2048, 3000, 3500, 4000] {
+            blob.extend_from_slice(&point.to_le_bytes());
+        }
+        blob.resize(564, 0);
+        blob[562..564].copy_from_slice(&1000_u16.to_le_bytes());
+
+        let table = LinearizationTable::parse(&blob, Endian::Little).unwrap();
+        assert_eq!(table.split_row, Some(1000));
+        let curve = table.curve(12);
+        assert_eq!(curve.len(), 4096);
+        assert_eq!(curve[512], 1024);
+        assert_eq!(curve[1024], 2048);
+        assert_eq!(curve[2560], 3250);
+        // (3500 * 1 + 4000 * 1023) / 1024
+        assert_eq!(curve[4095], 3999);
+    }
+
+    #[test]
+    fn lossless_tables_map_through_the_identity() {
+        // The curve size is announced but no points follow
+        let blob = [0x46, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0x00];
+        let table = LinearizationTable::parse(&blob, Endian::Little).unwrap();
+        assert!(table.is_lossless());
+        assert!(table.points.is_empty());
+        assert_eq!(table.bit_depth, None);
+        let curve = table.curve(14);
+        assert_eq!(curve.len(), 1 << 14);
+        assert!(
+            curve
+                .iter()
+                .enumerate()
+                .all(|(i, &value)| value as usize == i)
+        );
+    }
+
+    #[test]
+    fn skips_the_data_of_newer_bodies() {
+        let mut blob = vec![0x49, 0x00];
+        blob.resize(2 + 2110, 0xEE);
+        blob.extend_from_slice(&[0x01, 0x00, 0x02, 0x00, 0x03, 0x00, 0x04, 0x00, 0x00, 0x00]);
+        let table = LinearizationTable::parse(&blob, Endian::Little).unwrap();
+        assert_eq!(table.predictors, [[1, 2], [3, 4]]);
+        assert!(table.points.is_empty());
+    }
+
+    #[test]
+    fn rejects_unknown_versions_and_short_blobs() {
+        assert!(matches!(
+            LinearizationTable::parse(&[0x40, 0x10, 0, 0], Endian::Little),
+            Err(NefError::UnsupportedVersion { .. })
+        ));
+        assert!(matches!(
+            LinearizationTable::parse(&[0x44, 0x10, 0, 0], Endian::Little),
+            Err(NefError::OutOfBounds { .. })
+        ));
+    }
+}
@@ -1,104 +1,780 @@
-mod huffmanv2;
-mod ifd;
-mod nef;
-mod utils;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageBuffer, ImageEncoder, Luma, Rgb};
+use read_nef::color::ColorSpace;
+use read_nef::demosaic::DemosaicMethod;
+use read_nef::develop::{Develop, Gamma, Highlights};
+use read_nef::dng::{DngCompression, DngOptions};
+use read_nef::ifd::IfdRole;
+use read_nef::makernote::NikonTag;
+use read_nef::nef::NefFile;
+use read_nef::orientation::{jpeg_with_exif, orientation_exif};
+use read_nef::raw_image::{Area, Crop};
+use read_nef::tiff_writer::nef_tiff;
+use read_nef::white_balance::WhiteBalance;
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{fs::File, io::BufWriter};
 
-use crate::nef::NefFile;
-use image::{ImageBuffer, Luma};
-use std::{fs::File, io::BufWriter, path::Path};
+/// Inspect and decode Nikon NEF raw files.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print a metadata summary
+    Info {
+        /// NEF files to read
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Print every IFD with its decoded entries
+    Dump {
+        /// NEF files to read
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Extract the embedded JPEG preview without decoding the raw data
+    Preview {
+        #[command(flatten)]
+        io: InputOutput,
+        /// Extract every embedded JPEG instead of only the largest
+        #[arg(long)]
+        all: bool,
+    },
+    /// Decode the raw CFA mosaic to a 16-bit grayscale image
+    Decode {
+        #[command(flatten)]
+        io: InputOutput,
+        #[arg(short, long, value_enum, default_value_t = RawFormat::Pgm)]
+        format: RawFormat,
+        /// Part of the sensor to output
+        #[arg(long, value_enum, default_value_t = Framing::Full)]
+        crop: Framing,
+    },
+    /// Render the raw data to a viewable image
+    Convert {
+        #[command(flatten)]
+        io: InputOutput,
+        #[arg(short, long, value_enum, default_value_t = ImageFormat::Jpeg)]
+        format: ImageFormat,
+        #[arg(short, long, value_enum, default_value_t = Engine::Native)]
+        engine: Engine,
+        /// Bits per channel; 16 needs PNG or TIFF output
+        #[arg(short, long, value_enum, default_value_t = Depth::Eight)]
+        bits: Depth,
+        #[command(flatten)]
+        develop: DevelopArgs,
+    },
+    /// Write the raw data and metadata as a DNG
+    Dng {
+        /// NEF files to read
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Directory the output files are written to
+        #[arg(short, long, default_value = ".")]
+        output_dir: PathBuf,
+        /// Leave out the embedded preview and thumbnail
+        #[arg(long)]
+        no_preview: bool,
+        /// Store the raw data as lossless JPEG
+        #[arg(long)]
+        compress: bool,
+    },
+    /// Compare the decoded raw data against rawloader
+    Compare {
+        /// NEF files to read
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+}
+
+#[derive(Args)]
+struct InputOutput {
+    /// NEF files to read
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+    /// Directory the output files are written to
+    #[arg(short, long, default_value = ".")]
+    output_dir: PathBuf,
+    /// How the EXIF Orientation of the NEF is handled
+    #[arg(long, value_enum, default_value_t = OrientationMode::Apply)]
+    orientation: OrientationMode,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum OrientationMode {
+    /// Rotate and mirror the pixels upright
+    Apply,
+    /// Keep the pixels as stored and write the Orientation tag to the output
+    Tag,
+    /// Keep the pixels as stored and drop the tag
+    Ignore,
+}
+
+/// Settings of the native engine
+#[derive(Args)]
+struct DevelopArgs {
+    /// Demosaic algorithm
+    #[arg(short, long, value_enum, default_value_t = Demosaic::Ppg)]
+    demosaic: Demosaic,
+    /// White balance
+    #[arg(short, long, value_enum, default_value_t = Wb::AsShot)]
+    white_balance: Wb,
+    /// Exposure compensation in EV
+    #[arg(long, default_value_t = 0.0, allow_negative_numbers = true)]
+    exposure: f32,
+    /// Desaturate highlights pushed past white instead of clipping them
+    #[arg(long)]
+    blend_highlights: bool,
+    /// Output color space
+    #[arg(short, long, value_enum, default_value_t = Space::Srgb)]
+    color_space: Space,
+    /// Part of the sensor to output
+    #[arg(long, value_enum, default_value_t = Framing::Default)]
+    crop: Framing,
+    /// Write linear values instead of gamma encoding them
+    #[arg(long)]
+    linear: bool,
+}
+
+impl DevelopArgs {
+    fn settings(&self) -> Develop {
+        let (color_space, gamma) = match self.color_space {
+            Space::Srgb => (ColorSpace::Srgb, Gamma::Srgb),
+            Space::AdobeRgb => (ColorSpace::AdobeRgb, Gamma::Power(2.2)),
+            Space::ProPhoto => (ColorSpace::ProPhotoRgb, Gamma::Power(1.8)),
+        };
+        let gamma = if self.linear { Gamma::Linear } else { gamma };
+        let white_balance = match self.white_balance {
+            Wb::AsShot => WhiteBalance::AsShot,
+            Wb::Daylight => WhiteBalance::Daylight,
+        };
+        let highlights = if self.blend_highlights {
+            Highlights::Blend
+        } else {
+            Highlights::Clip
+        };
+        Develop::new()
+            .demosaic(self.demosaic.into())
+            .white_balance(white_balance)
+            .exposure(self.exposure)
+            .highlights(highlights)
+            .color_space(color_space)
+            .gamma(gamma)
+            .crop(self.crop.into())
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum RawFormat {
+    Pgm,
+    Tiff,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum ImageFormat {
+    Jpeg,
+    Png,
+    Tiff,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum Depth {
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Engine {
+    /// The decoder in this crate
+    Native,
+    /// rawloader and imagepipe
+    Imagepipe,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Demosaic {
+    Bilinear,
+    /// Malvar-He-Cutler
+    Malvar,
+    /// Patterned Pixel Grouping
+    Ppg,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Wb {
+    AsShot,
+    Daylight,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Space {
+    Srgb,
+    AdobeRgb,
+    ProPhoto,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum Framing {
+    /// Every stored sample, masked borders included
+    Full,
+    /// The area that records the picture
+    Active,
+    /// The image area selected on the camera
+    Default,
+}
+
+impl From<Framing> for Crop {
+    fn from(framing: Framing) -> Self {
+        match framing {
+            Framing::Full => Crop::Full,
+            Framing::Active => Crop::ActiveArea,
+            Framing::Default => Crop::Default,
+        }
+    }
+}
+
+impl From<Demosaic> for DemosaicMethod {
+    fn from(demosaic: Demosaic) -> Self {
+        match demosaic {
+            Demosaic::Bilinear => DemosaicMethod::Bilinear,
+            Demosaic::Malvar => DemosaicMethod::MalvarHeCutler,
+            Demosaic::Ppg => DemosaicMethod::Ppg,
+        }
+    }
+}
+
+impl ImageFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Png => "png",
+            ImageFormat::Tiff => "tiff",
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let (files, output_dir) = match &cli.command {
+        Command::Info { files } | Command::Dump { files } | Command::Compare { files } => {
+            (files, None)
+        }
+        Command::Preview { io, .. } | Command::Decode { io, .. } | Command::Convert { io, .. } => {
+            (&io.files, Some(io.output_dir.as_path()))
+        }
+        Command::Dng {
+            files, output_dir, ..
+        } => (files, Some(output_dir.as_path())),
+    };
+
+    if let Some(output_dir) = output_dir
+        && let Err(e) = std::fs::create_dir_all(output_dir)
+    {
+        eprintln!("Failed to create {}: {e}", output_dir.display());
+        return ExitCode::FAILURE;
+    }
+
+    let mut failed = false;
+    for file_path in files {
+        let output_dir = output_dir.unwrap_or(Path::new("."));
+        if let Err(e) = run(&cli.command, file_path, output_dir) {
+            eprintln!("{}: {e}", file_path.display());
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn run(command: &Command, file_path: &Path, output_dir: &Path) -> Result<(), anyhow::Error> {
+    if let Command::Convert {
+        format,
+        engine: Engine::Imagepipe,
+        ..
+    } = command
+    {
+        let output = output_path(file_path, output_dir, "", format.extension());
+        return image_pipe_covert(file_path, &output, *format);
+    }
+
+    let nef_file = NefFile::open(file_path)?;
+    match command {
+        Command::Info { .. } => info(&nef_file),
+        Command::Dump { .. } => dump(&nef_file),
+        Command::Preview { io, all } => preview(&nef_file, output_dir, *all, io.orientation)?,
+        Command::Decode { io, format, crop } => decode(
+            &nef_file,
+            output_dir,
+            *format,
+            (*crop).into(),
+            io.orientation,
+        )?,
+        Command::Convert {
+            io,
+            format,
+            bits,
+            develop,
+            ..
+        } => {
+            let output = output_path(file_path, output_dir, "", format.extension());
+            let settings = develop
+                .settings()
+                .orientation(io.orientation == OrientationMode::Apply);
+            convert(
+                &nef_file,
+                &output,
+                *format,
+                *bits,
+                &settings,
+                io.orientation,
+            )?
+        }
+        Command::Dng {
+            no_preview,
+            compress,
+            ..
+        } => {
+            let output = output_path(file_path, output_dir, "", "dng");
+            let compression = if *compress {
+                DngCompression::LosslessJpeg
+            } else {
+                DngCompression::Uncompressed
+            };
+            let options = DngOptions::new()
+                .preview(!no_preview)
+                .compression(compression);
+            nef_file.write_dng(&output, &options)?;
+            println!("Wrote {}", output.display());
+        }
+        Command::Compare { .. } => compare(&nef_file, file_path)?,
+    }
+    Ok(())
+}
+
+fn output_path(file_path: &Path, output_dir: &Path, suffix: &str, extension: &str) -> PathBuf {
+    let stem = file_path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    output_dir.join(format!("{stem}{suffix}.{extension}"))
+}
+
+fn info(nef_file: &NefFile) {
+    let meta = &nef_file.meta_data;
+    let text = |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("-"));
+
+    println!("{}", nef_file.file_name);
+    println!("  Camera:       {} {}", text(&meta.make), text(&meta.model));
+    println!("  Lens:         {}", text(&meta.lens_model));
+    println!("  Taken:        {}", text(&meta.date_time_original));
+    println!(
+        "  Raw size:     {} x {}",
+        nef_file.image_data.width, nef_file.image_data.height
+    );
+    let crop = nef_file.default_crop();
+    if crop != Area::full(nef_file.image_data.width, nef_file.image_data.height) {
+        println!(
+            "  Crop:         {} x {} at {}, {}",
+            crop.width, crop.height, crop.left, crop.top
+        );
+    }
+    if let (Ok(black_levels), Ok(white_level)) = (nef_file.black_levels(), nef_file.white_level()) {
+        println!("  Levels:       black {black_levels:?} (RGGB), white {white_level}");
+    }
+    if let Some(exposure_time) = meta.exposure_time {
+        if exposure_time > 0.0 && exposure_time < 1.0 {
+            println!("  Exposure:     1/{:.0} s", 1.0 / exposure_time);
+        } else {
+            println!("  Exposure:     {exposure_time} s");
+        }
+    }
+    if let Some(f_number) = meta.f_number {
+        println!("  Aperture:     f/{f_number:.1}");
+    }
+    if let Some(iso) = meta.iso_speed_ratings {
+        println!("  ISO:          {iso}");
+    }
+    if let Some(focal_length) = meta.focal_length {
+        println!("  Focal length: {focal_length} mm");
+    }
+    if let Some(exposure_bias) = meta.exposure_bias {
+        println!("  Exposure bias: {exposure_bias:+.2} EV");
+    }
+    if let Some(flash) = meta.flash {
+        let fired = if flash & 1 == 1 {
+            "fired"
+        } else {
+            "did not fire"
+        };
+        println!("  Flash:        {fired}");
+    }
+    if let Some(metering_mode) = meta.metering_mode {
+        println!("  Metering:     {metering_mode}");
+    }
+    if let Some(orientation) = meta.orientation {
+        println!("  Orientation:  {orientation}");
+    }
+    if let Some(artist) = &meta.artist {
+        println!("  Artist:       {artist}");
+    }
+    if let Some(copyright) = &meta.copyright {
+        println!("  Copyright:    {copyright}");
+    }
+    if let Some(maker_note) = nef_file.maker_note() {
+        let text = |value: &Option<String>| value.clone().unwrap_or_else(|| String::from("-"));
+        println!("  MakerNote:    version {}", text(&maker_note.version));
+        println!("  Serial:       {}", text(&maker_note.serial_number));
+        if let Some(shutter_count) = maker_note.shutter_count {
+            println!("  Shutter count: {shutter_count}");
+        }
+        println!("  Quality:      {}", text(&maker_note.quality));
+        println!("  White balance: {}", text(&maker_note.white_balance));
+        println!("  Focus mode:   {}", text(&maker_note.focus_mode));
+        if let Some(lens) = maker_note.lens {
+            println!(
+                "  Lens range:   {}-{} mm f/{}-{}",
+                lens.min_focal_length,
+                lens.max_focal_length,
+                lens.max_aperture_at_min_focal,
+                lens.max_aperture_at_max_focal
+            );
+        }
+        if let Some(crop_hi_speed) = maker_note.crop_hi_speed {
+            println!("  Image area:   {:?}", crop_hi_speed.mode);
+        }
+        if let Some(active_d_lighting) = maker_note.active_d_lighting {
+            println!("  Active D-Lighting: {active_d_lighting:?}");
+        }
+        if let Some(nef_compression) = maker_note.nef_compression {
+            println!("  NEF compression: {nef_compression:?}");
+        }
+        if let Ok(color_balance) = nef_file.color_balance() {
+            println!("  WB levels:    {:?} (RGGB)", color_balance.wb_rggb_levels);
+        }
+        if let Some([red, green, blue]) = nef_file.as_shot_wb() {
+            println!("  As-shot WB:   {red:.3} {green:.3} {blue:.3} (RGB)");
+        }
+        if let Some([red, green, blue]) = nef_file.wb_multipliers(WhiteBalance::Daylight) {
+            println!("  Daylight WB:  {red:.3} {green:.3} {blue:.3} (RGB)");
+        }
+        if let Ok(lens_data) = nef_file.lens_data()
+            && let Some(focus_distance) = lens_data.focus_distance
+        {
+            println!("  Focus distance: {focus_distance:.2} m");
+        }
+        if let Ok(shot_info) = nef_file.shot_info()
+            && let Some(firmware_version) = shot_info.firmware_version
+        {
+            println!("  Firmware:     {firmware_version}");
+        }
+    }
+    for preview in nef_file.previews() {
+        println!(
+            "  Preview:      {} x {} JPEG, {} bytes ({:?})",
+            preview.width, preview.height, preview.length, preview.source
+        );
+    }
+}
 
-fn main() {
-    let file_path = Path::new("test_data/DSC_3935.NEF");
+fn dump(nef_file: &NefFile) {
+    println!("{}", nef_file.file_name);
+    for ifd in &nef_file.ifds {
+        println!(
+            "IFD at {} ({:?}, {:?}, {} entries)",
+            ifd.offset_location,
+            ifd.role,
+            ifd.endian,
+            ifd.entries.len()
+        );
+        for entry in &ifd.entries {
+            // MakerNote tag numbers mean something else than the TIFF ones
+            let name = if ifd.role == IfdRole::MakerNote {
+                format!("{:?}", NikonTag::from(entry.tag.u16_value() as usize))
+            } else {
+                format!("{:?}", entry.tag)
+            };
+            println!(
+                "  {:#06X} {} [{:?} x {}] = {}",
+                entry.tag.u16_value(),
+                name,
+                entry.data_type,
+                entry.count,
+                entry.get_human_readable_value(nef_file.buffer())
+            );
+        }
+    }
+}
+
+fn preview(
+    nef_file: &NefFile,
+    output_dir: &Path,
+    all: bool,
+    mode: OrientationMode,
+) -> Result<(), anyhow::Error> {
+    let orientation = nef_file.orientation();
+    if !all {
+        let jpeg = nef_file.extract_largest_preview()?;
+        let output = output_path(&nef_file.file_path, output_dir, "_preview", "jpg");
+        std::fs::write(&output, orient_jpeg(jpeg, orientation, mode)?)?;
+        println!("Wrote {}", output.display());
+        return Ok(());
+    }
 
-    if false {
-        // Imagepipe
-        if let Err(e) = image_pipe_covert(file_path) {
-            eprintln!("Failed to convert image with imagepipe: {e}");
+    for (index, preview) in nef_file.previews().iter().enumerate() {
+        let suffix = format!("_preview{index}");
+        let output = output_path(&nef_file.file_path, output_dir, &suffix, "jpg");
+        let jpeg = nef_file.preview_data(preview)?;
+        std::fs::write(&output, orient_jpeg(jpeg, orientation, mode)?)?;
+        println!(
+            "Wrote {} ({} x {})",
+            output.display(),
+            preview.width,
+            preview.height
+        );
+    }
+    Ok(())
+}
+
+/// The preview as stored, with an Orientation tag, or decoded, turned
+/// upright and encoded again.
+fn orient_jpeg(
+    jpeg: &[u8],
+    orientation: Orientation,
+    mode: OrientationMode,
+) -> Result<Cow<'_, [u8]>, anyhow::Error> {
+    if orientation == Orientation::NoTransforms {
+        return Ok(Cow::Borrowed(jpeg));
+    }
+    match mode {
+        OrientationMode::Ignore => Ok(Cow::Borrowed(jpeg)),
+        OrientationMode::Tag => Ok(Cow::Owned(jpeg_with_exif(
+            jpeg,
+            &orientation_exif(orientation),
+        )?)),
+        OrientationMode::Apply => {
+            let mut img = image::load_from_memory_with_format(jpeg, image::ImageFormat::Jpeg)?;
+            img.apply_orientation(orientation);
+            let mut out = Vec::new();
+            let jpg_encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut out, 95);
+            img.write_with_encoder(jpg_encoder)?;
+            Ok(Cow::Owned(out))
         }
     }
+}
+
+fn decode(
+    nef_file: &NefFile,
+    output_dir: &Path,
+    format: RawFormat,
+    crop: Crop,
+    mode: OrientationMode,
+) -> Result<(), anyhow::Error> {
+    let raw_image = nef_file.raw_image()?;
+    let raw_image = match crop {
+        Crop::Full => raw_image,
+        _ => raw_image.cropped(raw_image.crop_area(crop)),
+    };
+    let raw_img: ImageBuffer<Luma<u16>, Vec<u16>> = ImageBuffer::from_raw(
+        raw_image.width as u32,
+        raw_image.height as u32,
+        raw_image.data,
+    )
+    .ok_or(anyhow::Error::msg("Failed to create ImageBuffer"))?;
 
-    match nef::NefFile::open(file_path) {
-        Ok(nef_file) => {
-            if let Err(e) = convert(nef_file) {
-                eprintln!("Failed to convert image: {e}");
+    let orientation = nef_file.orientation();
+    let raw_img = match mode {
+        OrientationMode::Apply => {
+            let mut img = DynamicImage::ImageLuma16(raw_img);
+            img.apply_orientation(orientation);
+            img.into_luma16()
+        }
+        _ => raw_img,
+    };
+    let (width, height) = (raw_img.width() as usize, raw_img.height() as usize);
+
+    let output = match format {
+        RawFormat::Pgm => {
+            if mode == OrientationMode::Tag && orientation != Orientation::NoTransforms {
+                eprintln!(
+                    "Warning: PGM cannot store the Orientation tag, pixels are left as stored"
+                );
             }
+            let output = output_path(&nef_file.file_path, output_dir, "_raw", "pgm");
+            write_pgm(&output, &raw_img, width, height)?;
+            output
+        }
+        RawFormat::Tiff => {
+            let output = output_path(&nef_file.file_path, output_dir, "_raw", "tiff");
+            let img = DynamicImage::ImageLuma16(raw_img);
+            std::fs::write(
+                &output,
+                nef_tiff(nef_file, &img, tiff_orientation(nef_file, mode))?,
+            )?;
+            output
+        }
+    };
+    println!("Wrote {}", output.display());
+    Ok(())
+}
+
+/// Binary 16-bit PGM, samples stored big-endian as the format requires.
+fn write_pgm(output: &Path, data: &[u16], width: usize, height: usize) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(output)?);
+    write!(writer, "P5\n{width} {height}\n65535\n")?;
+    for value in data {
+        writer.write_all(&value.to_be_bytes())?;
+    }
+    writer.flush()
+}
+
+fn compare(nef_file: &NefFile, file_path: &Path) -> Result<(), anyhow::Error> {
+    let out = nef_file.parse_raw_image_data()?;
+    let reference = rawloader::decode_file(file_path)?;
+    let rawloader::RawImageData::Integer(expected) = reference.data else {
+        anyhow::bail!("rawloader returned floating point data");
+    };
+
+    println!("{}", nef_file.file_name);
+    println!(
+        "  Size:      {} x {} (rawloader {} x {})",
+        nef_file.image_data.width, nef_file.image_data.height, reference.width, reference.height
+    );
+    if out.len() != expected.len() {
+        anyhow::bail!(
+            "sample count differs: {} vs {} from rawloader",
+            out.len(),
+            expected.len()
+        );
+    }
+
+    let mut mismatches = 0;
+    let mut max_diff = 0;
+    let mut total_diff: u64 = 0;
+    for (&value, &reference) in out.iter().zip(&expected) {
+        let diff = value.abs_diff(reference);
+        if diff != 0 {
+            mismatches += 1;
+            max_diff = max_diff.max(diff);
+            total_diff += diff as u64;
         }
-        Err(e) => eprintln!("Error: {:?}", e),
     }
+    if mismatches == 0 {
+        println!("  Identical to rawloader");
+    } else {
+        println!(
+            "  Differing samples: {} of {} ({:.4}%)",
+            mismatches,
+            out.len(),
+            mismatches as f64 * 100.0 / out.len() as f64
+        );
+        println!("  Max difference:    {max_diff}");
+        println!(
+            "  Mean difference:   {:.3}",
+            total_diff as f64 / out.len() as f64
+        );
+    }
+    Ok(())
 }
 
-fn image_pipe_covert(file_path: &Path) -> Result<(), anyhow::Error> {
-    use image::ExtendedColorType::Rgb8;
+fn image_pipe_covert(
+    file_path: &Path,
+    output: &Path,
+    format: ImageFormat,
+) -> Result<(), anyhow::Error> {
     use imagepipe::Pipeline;
 
     let mut pipeline = Pipeline::new_from_file(file_path).map_err(anyhow::Error::msg)?;
     let decoded = pipeline.output_8bit(None).map_err(anyhow::Error::msg)?;
-    let mut jpg_file = file_path.to_path_buf();
-    jpg_file.set_extension("jpg");
-
-    let outfile = File::create(jpg_file)?;
-    let mut writer = BufWriter::new(outfile);
-    let mut jpg_encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, 100);
-    jpg_encoder
-        .encode(
-            &decoded.data,
-            decoded.width as u32,
-            decoded.height as u32,
-            Rgb8,
-        )
-        .map_err(anyhow::Error::msg)
-}
-
-/// Currently, only grayscale supported, but the raw image data is available.
-fn convert(nef_file: NefFile) -> Result<(), anyhow::Error> {
-    let out = nef_file.parse_raw_image_data()?;
+    let rgb_img: ImageBuffer<Rgb<u8>, Vec<u8>> =
+        ImageBuffer::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+            .ok_or(anyhow::Error::msg("Failed to create ImageBuffer"))?;
+
+    save_image(DynamicImage::ImageRgb8(rgb_img), output, format, None)?;
+    println!("Wrote {}", output.display());
+    Ok(())
+}
 
-    let width = nef_file.image_data.width as u32;
-    let height = nef_file.image_data.height as u32;
-
-    // Convert 16-bit raw values to 8-bit using simple normalization
-    let gray8 = normalize_to_u8(&out);
-    let gray_img: ImageBuffer<Luma<u8>, Vec<u8>> = ImageBuffer::from_raw(width, height, gray8)
-        .ok_or(anyhow::Error::msg("Failed to create ImageBuffer"))?;
-
-    let gray_output = File::create("output_gray.jpg").expect("Failed to create output file");
-    let mut gray_writer = BufWriter::new(gray_output);
-    image::codecs::jpeg::JpegEncoder::new(&mut gray_writer).encode_image(&gray_img)?;
-
-    // let mut f = BufWriter::new(File::create("output.ppm").unwrap());
-    // let preamble = format!("P6 {} {} {}\n", width, height, 65535).into_bytes();
-    // f.write_all(&preamble).unwrap();
-    // for pix in out {
-    //     // Do an extremely crude "demosaic" by setting R=G=B
-    //     let pixhigh = (pix >> 8).saturating_add(50) as u8;
-    //     let pixlow = (pix & 0xff).saturating_add(50) as u8;
-    //     f.write_all(&[pixhigh, pixlow, pixhigh, pixlow, pixhigh, pixlow])
-    //         .unwrap();
-    // }
+fn convert(
+    nef_file: &NefFile,
+    output: &Path,
+    format: ImageFormat,
+    bits: Depth,
+    develop: &Develop,
+    mode: OrientationMode,
+) -> Result<(), anyhow::Error> {
+    let img = match bits {
+        Depth::Eight => DynamicImage::ImageRgb8(develop.develop_rgb8(nef_file)?),
+        Depth::Sixteen if matches!(format, ImageFormat::Jpeg) => {
+            anyhow::bail!("JPEG output is limited to 8 bits per channel")
+        }
+        Depth::Sixteen => DynamicImage::ImageRgb16(develop.develop_rgb16(nef_file)?),
+    };
+    if let ImageFormat::Tiff = format {
+        std::fs::write(
+            output,
+            nef_tiff(nef_file, &img, tiff_orientation(nef_file, mode))?,
+        )?;
+    } else {
+        let exif = (mode == OrientationMode::Tag).then(|| orientation_exif(nef_file.orientation()));
+        save_image(img, output, format, exif)?;
+    }
+    println!("Wrote {}", output.display());
     Ok(())
 }
 
-fn normalize_to_u8(raw: &[u16]) -> Vec<u8> {
-    // Auto-normalize u16 values to 0..255 for a reasonable grayscale output
-    if raw.is_empty() {
-        return Vec::new();
+/// The Orientation tag of TIFF output: upright once applied, the NEF's own
+/// when tagging, none when ignored.
+fn tiff_orientation(nef_file: &NefFile, mode: OrientationMode) -> Option<Orientation> {
+    match mode {
+        OrientationMode::Apply => Some(Orientation::NoTransforms),
+        OrientationMode::Tag => Some(nef_file.orientation()),
+        OrientationMode::Ignore => None,
     }
-    let mut min_v = u16::MAX;
-    let mut max_v = u16::MIN;
-    for &v in raw {
-        if v < min_v {
-            min_v = v;
+}
+
+/// Saves `img`, storing the `exif` block in formats that can hold one.
+fn save_image(
+    img: DynamicImage,
+    output: &Path,
+    format: ImageFormat,
+    exif: Option<Vec<u8>>,
+) -> Result<(), anyhow::Error> {
+    let exif = exif.unwrap_or_default();
+    match format {
+        ImageFormat::Jpeg => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            let mut jpg_encoder =
+                image::codecs::jpeg::JpegEncoder::new_with_quality(&mut writer, 100);
+            jpg_encoder.set_exif_metadata(exif)?;
+            img.write_with_encoder(jpg_encoder)?;
         }
-        if v > max_v {
-            max_v = v;
+        ImageFormat::Png => {
+            let mut writer = BufWriter::new(File::create(output)?);
+            let mut png_encoder = image::codecs::png::PngEncoder::new(&mut writer);
+            png_encoder.set_exif_metadata(exif)?;
+            img.write_with_encoder(png_encoder)?;
+        }
+        ImageFormat::Tiff => {
+            if !exif.is_empty() {
+                eprintln!("Warning: TIFF output does not carry EXIF metadata");
+            }
+            img.save_with_format(output, image::ImageFormat::Tiff)?
         }
     }
-    let range = (max_v as i32 - min_v as i32).max(1) as f32;
-    let min_f = min_v as f32;
-    let mut out = Vec::with_capacity(raw.len());
-    for &v in raw {
-        let n = ((v as f32 - min_f) / range * 255.0).clamp(0.0, 255.0) as u8;
-        out.push(n);
-    }
-    out
+    Ok(())
 }
@@ -0,0 +1,515 @@
+use crate::ifd::{Ifd, IfdEntry, IfdValue};
+
+/// Tags of the Nikon type 3 MakerNote. Their numbers overlap the TIFF tags in
+/// [`IfdEntryTag`](crate::ifd::IfdEntryTag), so MakerNote entries are looked up
+/// by number through [`NikonTag::u16_value`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NikonTag {
+    MakerNoteVersion,
+    ISO,
+    ColorMode,
+    Quality,
+    WhiteBalance,
+    Sharpness,
+    FocusMode,
+    FlashSetting,
+    FlashType,
+    WBRBLevels,
+    ProgramShift,
+    ExposureDifference,
+    PreviewIFD,
+    FlashExposureComp,
+    ISOSetting,
+    ImageBoundary,
+    ImageProcessing,
+    CropHiSpeed,
+    SerialNumber,
+    ActiveDLighting,
+    ISOInfo,
+    BlackLevel,
+    ImageSizeRaw,
+    LensType,
+    Lens,
+    FlashMode,
+    ShootingMode,
+    LensFStops,
+    ShotInfo,
+    NEFCompression,
+    NEFLinearizationTable,
+    ColorBalance,
+    LensData,
+    RawImageCenter,
+    SensorPixelSize,
+    ShutterCount,
+    ImageOptimization,
+    SaturationAdj,
+    VariProgram,
+    PowerUpTime,
+    Unknown(usize),
+}
+
+impl From<usize> for NikonTag {
+    fn from(tag_value: usize) -> Self {
+        match tag_value {
+            0x01 => NikonTag::MakerNoteVersion,
+            0x02 => NikonTag::ISO,
+            0x03 => NikonTag::ColorMode,
+            0x04 => NikonTag::Quality,
+            0x05 => NikonTag::WhiteBalance,
+            0x06 => NikonTag::Sharpness,
+            0x07 => NikonTag::FocusMode,
+            0x08 => NikonTag::FlashSetting,
+            0x09 => NikonTag::FlashType,
+            0x0C => NikonTag::WBRBLevels,
+            0x0D => NikonTag::ProgramShift,
+            0x0E => NikonTag::ExposureDifference,
+            0x11 => NikonTag::PreviewIFD,
+            0x12 => NikonTag::FlashExposureComp,
+            0x13 => NikonTag::ISOSetting,
+            0x16 => NikonTag::ImageBoundary,
+            0x1A => NikonTag::ImageProcessing,
+            0x1B => NikonTag::CropHiSpeed,
+            0x1D => NikonTag::SerialNumber,
+            0x22 => NikonTag::ActiveDLighting,
+            0x25 => NikonTag::ISOInfo,
+            0x3D => NikonTag::BlackLevel,
+            0x3E => NikonTag::ImageSizeRaw,
+            0x83 => NikonTag::LensType,
+            0x84 => NikonTag::Lens,
+            0x87 => NikonTag::FlashMode,
+            0x89 => NikonTag::ShootingMode,
+            0x8B => NikonTag::LensFStops,
+            0x91 => NikonTag::ShotInfo,
+            0x93 => NikonTag::NEFCompression,
+            0x96 => NikonTag::NEFLinearizationTable,
+            0x97 => NikonTag::ColorBalance,
+            0x98 => NikonTag::LensData,
+            0x99 => NikonTag::RawImageCenter,
+            0x9A => NikonTag::SensorPixelSize,
+            0xA7 => NikonTag::ShutterCount,
+            0xA9 => NikonTag::ImageOptimization,
+            0xAA => NikonTag::SaturationAdj,
+            0xAB => NikonTag::VariProgram,
+            0xB6 => NikonTag::PowerUpTime,
+            _ => NikonTag::Unknown(tag_value),
+        }
+    }
+}
+
+impl NikonTag {
+    pub fn u16_value(&self) -> u16 {
+        match self {
+            NikonTag::MakerNoteVersion => 0x01,
+            NikonTag::ISO => 0x02,
+            NikonTag::ColorMode => 0x03,
+            NikonTag::Quality => 0x04,
+            NikonTag::WhiteBalance => 0x05,
+            NikonTag::Sharpness => 0x06,
+            NikonTag::FocusMode => 0x07,
+            NikonTag::FlashSetting => 0x08,
+            NikonTag::FlashType => 0x09,
+            NikonTag::WBRBLevels => 0x0C,
+            NikonTag::ProgramShift => 0x0D,
+            NikonTag::ExposureDifference => 0x0E,
+            NikonTag::PreviewIFD => 0x11,
+            NikonTag::FlashExposureComp => 0x12,
+            NikonTag::ISOSetting => 0x13,
+            NikonTag::ImageBoundary => 0x16,
+            NikonTag::ImageProcessing => 0x1A,
+            NikonTag::CropHiSpeed => 0x1B,
+            NikonTag::SerialNumber => 0x1D,
+            NikonTag::ActiveDLighting => 0x22,
+            NikonTag::ISOInfo => 0x25,
+            NikonTag::BlackLevel => 0x3D,
+            NikonTag::ImageSizeRaw => 0x3E,
+            NikonTag::LensType => 0x83,
+            NikonTag::Lens => 0x84,
+            NikonTag::FlashMode => 0x87,
+            NikonTag::ShootingMode => 0x89,
+            NikonTag::LensFStops => 0x8B,
+            NikonTag::ShotInfo => 0x91,
+            NikonTag::NEFCompression => 0x93,
+            NikonTag::NEFLinearizationTable => 0x96,
+            NikonTag::ColorBalance => 0x97,
+            NikonTag::LensData => 0x98,
+            NikonTag::RawImageCenter => 0x99,
+            NikonTag::SensorPixelSize => 0x9A,
+            NikonTag::ShutterCount => 0xA7,
+            NikonTag::ImageOptimization => 0xA9,
+            NikonTag::SaturationAdj => 0xAA,
+            NikonTag::VariProgram => 0xAB,
+            NikonTag::PowerUpTime => 0xB6,
+            NikonTag::Unknown(value) => *value as u16,
+        }
+    }
+}
+
+impl Ifd {
+    /// Looks a tag up by its Nikon MakerNote meaning.
+    pub fn get_nikon_entry(&self, tag: NikonTag) -> Option<&IfdEntry> {
+        self.get_entry_by_byte(tag.u16_value())
+    }
+}
+
+/// How the raw data was stored, from NEFCompression (0x93).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NefCompression {
+    LossyType1,
+    Uncompressed,
+    Lossless,
+    LossyType2,
+    StripedPacked12Bit,
+    UncompressedReduced12Bit,
+    Unpacked12Bit,
+    Small,
+    Packed12Bit,
+    Packed14Bit,
+    HighEfficiency,
+    HighEfficiencyStar,
+    Unknown(u16),
+}
+
+impl From<u16> for NefCompression {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => NefCompression::LossyType1,
+            2 => NefCompression::Uncompressed,
+            3 => NefCompression::Lossless,
+            4 => NefCompression::LossyType2,
+            5 => NefCompression::StripedPacked12Bit,
+            6 => NefCompression::UncompressedReduced12Bit,
+            7 => NefCompression::Unpacked12Bit,
+            8 => NefCompression::Small,
+            9 => NefCompression::Packed12Bit,
+            10 => NefCompression::Packed14Bit,
+            13 => NefCompression::HighEfficiency,
+            14 => NefCompression::HighEfficiencyStar,
+            _ => NefCompression::Unknown(value),
+        }
+    }
+}
+
+/// Active D-Lighting strength (0x22).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ActiveDLighting {
+    Off,
+    Low,
+    Normal,
+    High,
+    ExtraHigh,
+    ExtraHigh1,
+    ExtraHigh2,
+    ExtraHigh3,
+    ExtraHigh4,
+    Auto,
+    Unknown(u16),
+}
+
+impl From<u16> for ActiveDLighting {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => ActiveDLighting::Off,
+            1 => ActiveDLighting::Low,
+            3 => ActiveDLighting::Normal,
+            5 => ActiveDLighting::High,
+            7 => ActiveDLighting::ExtraHigh,
+            8 => ActiveDLighting::ExtraHigh1,
+            9 => ActiveDLighting::ExtraHigh2,
+            10 => ActiveDLighting::ExtraHigh3,
+            11 => ActiveDLighting::ExtraHigh4,
+            0xFFFF => ActiveDLighting::Auto,
+            _ => ActiveDLighting::Unknown(value),
+        }
+    }
+}
+
+/// Image area setting of CropHiSpeed (0x1B).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CropMode {
+    Off,
+    Crop1_3x,
+    DxCrop,
+    Crop5x4,
+    Crop3x2,
+    Crop16x9,
+    Crop2_7x,
+    DxMovieCrop,
+    Crop1_3xMovie,
+    FxUncropped,
+    DxUncropped,
+    Crop1_5xMovie,
+    Crop1x1,
+    Unknown(u16),
+}
+
+impl From<u16> for CropMode {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => CropMode::Off,
+            1 => CropMode::Crop1_3x,
+            2 => CropMode::DxCrop,
+            3 => CropMode::Crop5x4,
+            4 => CropMode::Crop3x2,
+            6 => CropMode::Crop16x9,
+            8 => CropMode::Crop2_7x,
+            9 => CropMode::DxMovieCrop,
+            10 => CropMode::Crop1_3xMovie,
+            11 => CropMode::FxUncropped,
+            12 => CropMode::DxUncropped,
+            15 => CropMode::Crop1_5xMovie,
+            17 => CropMode::Crop1x1,
+            _ => CropMode::Unknown(value),
+        }
+    }
+}
+
+/// The image area setting and the crop it selects on the full sensor
+/// (0x1B), in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct CropHiSpeed {
+    pub mode: CropMode,
+    pub full_width: u16,
+    pub full_height: u16,
+    pub crop_width: u16,
+    pub crop_height: u16,
+    pub crop_left: u16,
+    pub crop_top: u16,
+}
+
+/// Raw image size setting (0x3E) of bodies that can record smaller NEFs.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageSizeRaw {
+    Large,
+    Medium,
+    Small,
+    Unknown(u16),
+}
+
+impl From<u16> for ImageSizeRaw {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => ImageSizeRaw::Large,
+            2 => ImageSizeRaw::Medium,
+            3 => ImageSizeRaw::Small,
+            _ => ImageSizeRaw::Unknown(value),
+        }
+    }
+}
+
+/// Lens feature flags (0x83).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LensType(pub u8);
+
+impl LensType {
+    pub fn is_manual_focus(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    /// Reports focus distance (D, G and E lenses).
+    pub fn is_d(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    /// No aperture ring (G and E lenses).
+    pub fn is_g(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    pub fn has_vr(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    pub fn is_af_p(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    /// Electromagnetic aperture (E lenses).
+    pub fn is_e(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+/// Focal length and maximum aperture range of the lens (0x84).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LensRange {
+    pub min_focal_length: f32,
+    pub max_focal_length: f32,
+    /// Maximum aperture at the shortest focal length.
+    pub max_aperture_at_min_focal: f32,
+    /// Maximum aperture at the longest focal length.
+    pub max_aperture_at_max_focal: f32,
+}
+
+/// ISO details (0x25). The sensitivities are stored as 1/12 EV steps from
+/// ISO 3.125 and converted here.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct IsoInfo {
+    pub iso: f32,
+    /// Lo/Hi expansion setting; 0 when the ISO is within the native range.
+    pub iso_expansion: u16,
+    pub iso2: f32,
+    pub iso_expansion2: u16,
+}
+
+/// The commonly used fields of the Nikon MakerNote, decoded from its IFD.
+/// Value offsets are resolved against the MakerNote's embedded TIFF header.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NikonMakerNote {
+    /// MakerNoteVersion, e.g. `0211`.
+    pub version: Option<String>,
+    /// ISO speed from the ISO tag (0x02).
+    pub iso: Option<u16>,
+    pub iso_info: Option<IsoInfo>,
+    /// Image quality, e.g. `RAW` or `FINE`.
+    pub quality: Option<String>,
+    /// White balance setting, e.g. `AUTO` or `SUNNY`.
+    pub white_balance: Option<String>,
+    /// Focus mode, e.g. `AF-S` or `MANUAL`.
+    pub focus_mode: Option<String>,
+    /// As-shot red and blue multipliers relative to green (0x0C).
+    pub wb_rb_levels: Option<[f32; 2]>,
+    pub serial_number: Option<String>,
+    pub shutter_count: Option<u32>,
+    pub lens_type: Option<LensType>,
+    pub lens: Option<LensRange>,
+    /// Built-in flash mode: 0 did not fire, 1 fired manual, 7 external,
+    /// 8 fired commander mode, 9 fired TTL.
+    pub flash_mode: Option<u8>,
+    pub crop_hi_speed: Option<CropHiSpeed>,
+    pub active_d_lighting: Option<ActiveDLighting>,
+    pub image_size_raw: Option<ImageSizeRaw>,
+    pub nef_compression: Option<NefCompression>,
+    /// Per-channel black level in R, G, G, B order, on a 14-bit scale
+    /// regardless of the raw bit depth.
+    pub black_level: Option<[u16; 4]>,
+}
+
+impl NikonMakerNote {
+    /// Decodes the MakerNote fields from `ifd`. Missing or malformed tags are
+    /// left as `None`.
+    pub fn parse(ifd: &Ifd, buffer: &[u8]) -> NikonMakerNote {
+        let value = |tag| {
+            ifd.get_nikon_entry(tag)
+                .and_then(|entry| entry.get_value(buffer).ok())
+        };
+        let text = |tag| {
+            value(tag)
+                .and_then(|value: IfdValue| value.as_string())
+                .filter(|text| !text.is_empty())
+        };
+        let short = |tag| {
+            value(tag)
+                .and_then(|value| value.as_u32())
+                .and_then(|value| u16::try_from(value).ok())
+        };
+
+        let lens = value(NikonTag::Lens).and_then(|value| {
+            Some(LensRange {
+                min_focal_length: value.get_f64(0)? as f32,
+                max_focal_length: value.get_f64(1)? as f32,
+                max_aperture_at_min_focal: value.get_f64(2)? as f32,
+                max_aperture_at_max_focal: value.get_f64(3)? as f32,
+            })
+        });
+
+        NikonMakerNote {
+            version: text(NikonTag::MakerNoteVersion),
+            iso: value(NikonTag::ISO)
+                .and_then(|value| value.get_u32(1))
+                .and_then(|value| u16::try_from(value).ok()),
+            iso_info: value(NikonTag::ISOInfo).and_then(|value| parse_iso_info(&value, ifd)),
+            quality: text(NikonTag::Quality),
+            white_balance: text(NikonTag::WhiteBalance),
+            focus_mode: text(NikonTag::FocusMode),
+            wb_rb_levels: value(NikonTag::WBRBLevels)
+                .and_then(|value| Some([value.get_f64(0)? as f32, value.get_f64(1)? as f32])),
+            serial_number: text(NikonTag::SerialNumber),
+            shutter_count: value(NikonTag::ShutterCount).and_then(|value| value.as_u32()),
+            lens_type: value(NikonTag::LensType)
+                .and_then(|value| value.as_u32())
+                .map(|value| LensType(value as u8)),
+            lens,
+            flash_mode: value(NikonTag::FlashMode)
+                .and_then(|value| value.as_u32())
+                .map(|value| value as u8),
+            crop_hi_speed: value(NikonTag::CropHiSpeed).and_then(|value| {
+                let field = |index| value.get_u32(index).map(|field| field as u16);
+                Some(CropHiSpeed {
+                    mode: CropMode::from(field(0)?),
+                    full_width: field(1)?,
+                    full_height: field(2)?,
+                    crop_width: field(3)?,
+                    crop_height: field(4)?,
+                    crop_left: field(5)?,
+                    crop_top: field(6)?,
+                })
+            }),
+            active_d_lighting: short(NikonTag::ActiveDLighting).map(ActiveDLighting::from),
+            image_size_raw: short(NikonTag::ImageSizeRaw).map(ImageSizeRaw::from),
+            nef_compression: short(NikonTag::NEFCompression).map(NefCompression::from),
+            black_level: value(NikonTag::BlackLevel).and_then(|value| {
+                let level = |index| value.get_u32(index).map(|level| level as u16);
+                Some([level(0)?, level(1)?, level(2)?, level(3)?])
+            }),
+        }
+    }
+}
+
+fn parse_iso_info(value: &IfdValue, ifd: &Ifd) -> Option<IsoInfo> {
+    let bytes = value.as_bytes()?;
+    if bytes.len() < 12 {
+        return None;
+    }
+    let iso = |step: u8| 100.0 * 2_f32.powf(step as f32 / 12.0 - 5.0);
+    Some(IsoInfo {
+        iso: iso(bytes[0]),
+        iso_expansion: ifd.endian.u16(&bytes[4..6]),
+        iso2: iso(bytes[6]),
+        iso_expansion2: ifd.endian.u16(&bytes[10..12]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Four bytes of other data, then a MakerNote whose embedded big-endian
+    /// TIFF header at 14 is the base of its offsets. BlackLevel is stored out
+    /// of line at 14 + 0x3E.
+    const MAKER_NOTE: [u8; 84] = [
+        b'X', b'X', b'X', b'X', // other data
+        b'N', b'i', b'k', b'o', b'n', 0x00, 0x02, 0x10, 0x00, 0x00, // Nikon header
+        b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, // TIFF header
+        0x00, 0x04, // entry count
+        0x00, 0x01, 0x00, 0x07, 0x00, 0x00, 0x00, 0x04, b'0', b'2', b'1', b'1', // version
+        0x00, 0x3D, 0x00, 0x03, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x3E, // black
+        0x00, 0x93, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x03, 0x00, 0x00, // compression
+        0x00, 0xA7, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x30,
+        0x39, // shutter count
+        0x00, 0x00, 0x00, 0x00, // next IFD
+        0x01, 0x90, 0x01, 0x91, 0x01, 0x92, 0x01, 0x93, // black levels
+    ];
+
+    #[test]
+    fn maps_tag_numbers_both_ways() {
+        for number in 0..=0xFF {
+            let tag = NikonTag::from(number);
+            assert_eq!(tag.u16_value() as usize, number, "{tag:?}");
+        }
+    }
+
+    #[test]
+    fn parses_an_embedded_maker_note() {
+        let ifds = Ifd::parse_ifd(&MAKER_NOTE, 4).unwrap();
+        assert_eq!(ifds.len(), 1);
+        let ifd = &ifds[0];
+        assert_eq!(ifd.base_offset, 14);
+
+        let maker_note = NikonMakerNote::parse(ifd, &MAKER_NOTE);
+        assert_eq!(maker_note.version.as_deref(), Some("0211"));
+        assert_eq!(maker_note.black_level, Some([400, 401, 402, 403]));
+        assert_eq!(maker_note.nef_compression, Some(NefCompression::Lossless));
+        assert_eq!(maker_note.shutter_count, Some(12345));
+        assert_eq!(maker_note.serial_number, None);
+    }
+}
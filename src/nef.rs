@@ -1,9 +1,18 @@
-use crate::huffmanv2::{BitPump, HuffTable};
-use crate::ifd::Ifd;
-use crate::utils::{read_beu32, read_leu16};
-use byteorder::{ByteOrder, LittleEndian};
-use std::hash::Hash;
-use std::hash::{DefaultHasher, Hasher};
+use crate::camera::{Camera, FALLBACK, Levels};
+use crate::color::{ColorSpace, camera_to_space};
+use crate::decrypt::{ColorBalance, LensData, NikonKey, ShotInfo};
+use crate::dng::{DngOptions, dng};
+use crate::error::NefError;
+use crate::huffmanv2::{BitPump, BitWriter, HuffTable, LosslessJpeg};
+use crate::ifd::{Ifd, IfdEntryTag, IfdRole, IfdValue};
+use crate::linearization::LinearizationTable;
+use crate::makernote::{CropHiSpeed, NikonMakerNote, NikonTag};
+use crate::packed::{PackedLayout, decode_uncompressed};
+use crate::raw_image::{Area, RawImage};
+use crate::utils::{Endian, checked_slice, jpeg_dimensions, read_beu32};
+use crate::white_balance::{DAYLIGHT, WhiteBalance};
+use image::metadata::Orientation;
+use std::borrow::Cow;
 use std::path::PathBuf;
 use std::{fs::File, io::Read, path::Path};
 
@@ -46,7 +55,7 @@ const NIKON_TREE: [[[u8; 16]; 3]; 6] = [
     ],
 ];
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NefFile {
     pub file_name: String,
     pub file_path: PathBuf,
@@ -56,14 +65,33 @@ pub struct NefFile {
     buffer: Vec<u8>,
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+/// Capture metadata collected from IFD0 and the EXIF IFD.
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct ImageMetadata {
+    /// Size of the NEF file in bytes.
     pub image_size: usize,
-    // pub make: String,
-    // pub model: String,
-    // pub iso_speed_ratings: Option<u16>,
-    // pub exposure_time: Option<f32>,
-    // pub f_number: Option<f32>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    /// DateTimeOriginal with SubSecTimeOriginal appended as a fraction,
+    /// e.g. `2024:05:01 12:30:45.37`.
+    pub date_time_original: Option<String>,
+    /// Exposure time in seconds.
+    pub exposure_time: Option<f32>,
+    pub f_number: Option<f32>,
+    pub iso_speed_ratings: Option<u16>,
+    /// Focal length in millimeters.
+    pub focal_length: Option<f32>,
+    /// Exposure compensation in EV.
+    pub exposure_bias: Option<f32>,
+    /// EXIF Flash bit field; bit 0 tells whether the flash fired.
+    pub flash: Option<u16>,
+    /// EXIF MeteringMode (e.g. 2 center-weighted, 3 spot, 5 matrix).
+    pub metering_mode: Option<u16>,
+    /// EXIF Orientation, 1 to 8.
+    pub orientation: Option<u16>,
+    pub artist: Option<String>,
+    pub copyright: Option<String>,
+    pub lens_model: Option<String>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -75,19 +103,40 @@ pub struct ImageData {
     // pub raw_image: Vec<u16>,
 }
 
+/// Where an embedded JPEG preview was found.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PreviewSource {
+    /// JpgFromRaw in a SubIFD of IFD0, usually the full-size preview.
+    SubIfd,
+    /// JPEGInterchangeFormat in a chained IFD such as IFD1.
+    ChainedIfd,
+    /// The Nikon PreviewIFD (0x0011) inside the MakerNote.
+    MakerNote,
+}
+
+/// An embedded JPEG preview, located by its byte range in the NEF file.
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub struct ImageThumbnail {}
+pub struct ImageThumbnail {
+    pub source: PreviewSource,
+    pub offset: usize,
+    pub length: usize,
+    pub width: usize,
+    pub height: usize,
+}
 
 impl NefFile {
-    pub fn open(file_path: &Path) -> Result<NefFile, anyhow::Error> {
-        let mut file = File::open(file_path).expect("Error loading file");
-        let file_path = Self::get_absolute_path(file_path).expect("Error getting full path");
-        let file_name = file_path.file_name().unwrap().to_str().unwrap().to_owned();
+    pub fn open(file_path: &Path) -> Result<NefFile, NefError> {
+        let mut file = File::open(file_path)?;
+        let file_path = Self::get_absolute_path(file_path)?;
+        let file_name = file_path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
 
         let mut buffer = Vec::new();
-        file.read_to_end(&mut buffer).expect("Error reading file");
+        file.read_to_end(&mut buffer)?;
 
-        let meta_data = ImageMetadata { image_size: 0 };
+        let meta_data = ImageMetadata::default();
         let image_data = ImageData {
             height: 0,
             width: 0,
@@ -104,200 +153,638 @@ impl NefFile {
             buffer,
         };
 
-        let mut ifds = nef_file.parse_ifds().expect("Error parsing ifds");
+        let mut ifds = nef_file.parse_ifds()?;
 
         nef_file.ifds.append(&mut ifds);
 
-        nef_file.add_metadata().expect("Error adding metadata!");
+        nef_file.add_metadata()?;
 
         Ok(nef_file)
     }
 
-    fn add_metadata(&mut self) -> Result<(), anyhow::Error> {
-        let data_ifd = self.ifds[2].clone();
+    fn add_metadata(&mut self) -> Result<(), NefError> {
+        let data_ifd = self.raw_ifd().ok_or(NefError::MissingIfd(IfdRole::Raw))?;
         let width = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::ImageWidth)
-            .unwrap()
+            .get_required_entry(IfdEntryTag::ImageWidth)?
             .get_data_or_offset();
         let height = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::ImageLength)
-            .unwrap()
+            .get_required_entry(IfdEntryTag::ImageLength)?
             .get_data_or_offset();
         self.image_data.height = height;
         self.image_data.width = width;
+        self.meta_data = self.read_metadata();
         Ok(())
     }
 
-    fn get_absolute_path(file_name: &Path) -> Option<PathBuf> {
-        let current_dir = std::env::current_dir().ok()?;
+    fn read_metadata(&self) -> ImageMetadata {
+        let text = |tag| {
+            self.find_value(tag)
+                .and_then(|value| value.as_string())
+                .filter(|text| !text.is_empty())
+        };
+        let float = |tag| {
+            self.find_value(tag)
+                .and_then(|value| value.as_f64())
+                .map(|value| value as f32)
+        };
+        let short = |tag| {
+            self.find_value(tag)
+                .and_then(|value| value.as_u32())
+                .and_then(|value| u16::try_from(value).ok())
+        };
+
+        let date_time_original = text(IfdEntryTag::DateTimeOriginal).map(|date_time| {
+            match text(IfdEntryTag::SubSecTimeOriginal) {
+                Some(sub_sec) => format!("{}.{}", date_time, sub_sec),
+                None => date_time,
+            }
+        });
+
+        ImageMetadata {
+            image_size: self.buffer.len(),
+            make: text(IfdEntryTag::Make),
+            model: text(IfdEntryTag::Model),
+            date_time_original,
+            exposure_time: float(IfdEntryTag::ExposureTime),
+            f_number: float(IfdEntryTag::FNumber),
+            iso_speed_ratings: short(IfdEntryTag::ISOSpeedRatings),
+            focal_length: float(IfdEntryTag::FocalLength),
+            exposure_bias: float(IfdEntryTag::ExposureBias),
+            flash: short(IfdEntryTag::Flash),
+            metering_mode: short(IfdEntryTag::ExposureMeteringMode),
+            orientation: short(IfdEntryTag::Orientation),
+            artist: text(IfdEntryTag::Artist),
+            copyright: text(IfdEntryTag::CopyRight),
+            lens_model: text(IfdEntryTag::LensModel),
+        }
+    }
+
+    /// The complete file contents; IFD entry offsets index into this buffer.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Looks a tag up in IFD0 first and in the EXIF IFD second.
+    fn find_value(&self, tag: IfdEntryTag) -> Option<IfdValue> {
+        self.ifds
+            .first()
+            .into_iter()
+            .chain(self.exif_ifd())
+            .find_map(|ifd| ifd.get_entry(tag))
+            .and_then(|entry| entry.get_value(&self.buffer).ok())
+    }
+
+    /// The full-resolution raw image IFD. If several directories qualify, the
+    /// largest one is returned.
+    pub fn raw_ifd(&self) -> Option<&Ifd> {
+        self.ifds
+            .iter()
+            .filter(|ifd| ifd.role == IfdRole::Raw)
+            .max_by_key(|ifd| {
+                let value = |tag| {
+                    ifd.get_entry(tag)
+                        .map_or(0, |entry| entry.get_data_or_offset())
+                };
+                value(IfdEntryTag::ImageWidth) * value(IfdEntryTag::ImageLength)
+            })
+    }
+
+    /// The top-level IFD chain (IFD0, IFD1, ...) in link order.
+    pub fn ifd_chain(&self) -> Vec<&Ifd> {
+        let mut chain: Vec<&Ifd> = Vec::new();
+        let mut next = self.ifds.first();
+        while let Some(ifd) = next {
+            chain.push(ifd);
+            next = ifd.next_ifd_offset.and_then(|offset| {
+                self.ifds.iter().find(|candidate| {
+                    candidate.offset_location == offset
+                        && !chain.iter().any(|linked| std::ptr::eq(*linked, *candidate))
+                })
+            });
+        }
+        chain
+    }
+
+    /// The thumbnail and embedded JPEG preview IFDs, in file order.
+    pub fn preview_ifds(&self) -> Vec<&Ifd> {
+        self.ifds_with_role(IfdRole::Preview).collect()
+    }
+
+    pub fn exif_ifd(&self) -> Option<&Ifd> {
+        self.ifds_with_role(IfdRole::Exif).next()
+    }
+
+    pub fn gps_ifd(&self) -> Option<&Ifd> {
+        self.ifds_with_role(IfdRole::Gps).next()
+    }
+
+    pub fn makernote_ifd(&self) -> Option<&Ifd> {
+        self.ifds_with_role(IfdRole::MakerNote).next()
+    }
+
+    /// The decoded Nikon MakerNote, if the file has one.
+    pub fn maker_note(&self) -> Option<NikonMakerNote> {
+        self.makernote_ifd()
+            .map(|ifd| NikonMakerNote::parse(ifd, &self.buffer))
+    }
+
+    /// The key of the encrypted MakerNote blocks, if the MakerNote has both
+    /// a serial number and a shutter count.
+    pub fn nikon_key(&self) -> Option<NikonKey> {
+        let maker_note = self.maker_note()?;
+        Some(NikonKey::new(
+            &maker_note.serial_number?,
+            maker_note.shutter_count?,
+        ))
+    }
+
+    /// The decrypted ColorBalance (0x97) block.
+    pub fn color_balance(&self) -> Result<ColorBalance, NefError> {
+        let (data, endian) = self.makernote_block(NikonTag::ColorBalance)?;
+        ColorBalance::parse(data, endian, self.nikon_key().as_ref())
+    }
+
+    /// The decrypted LensData (0x98) block.
+    pub fn lens_data(&self) -> Result<LensData, NefError> {
+        let (data, _) = self.makernote_block(NikonTag::LensData)?;
+        LensData::parse(data, self.nikon_key().as_ref())
+    }
+
+    /// The decrypted ShotInfo (0x91) block.
+    pub fn shot_info(&self) -> Result<ShotInfo, NefError> {
+        let (data, _) = self.makernote_block(NikonTag::ShotInfo)?;
+        ShotInfo::parse(data, self.nikon_key().as_ref())
+    }
+
+    /// The EXIF Orientation of IFD0. Missing or invalid values mean the
+    /// pixels are stored upright.
+    pub fn orientation(&self) -> Orientation {
+        self.meta_data
+            .orientation
+            .and_then(|orientation| Orientation::from_exif(orientation as u8))
+            .unwrap_or(Orientation::NoTransforms)
+    }
+
+    /// The camera database entry for this file's Make and Model.
+    pub fn camera(&self) -> Option<&'static Camera> {
+        Camera::lookup(
+            self.meta_data.make.as_deref()?,
+            self.meta_data.model.as_deref()?,
+        )
+    }
+
+    /// As-shot R, G, B white balance multipliers, normalized to green. Taken
+    /// from WB_RBLevels (0x0C) when present, from the decrypted ColorBalance
+    /// block (0x97) otherwise.
+    pub fn as_shot_wb(&self) -> Option<[f32; 3]> {
+        if let Some([red, blue]) = self
+            .maker_note()
+            .and_then(|maker_note| maker_note.wb_rb_levels)
+            .filter(|levels| levels.iter().all(|&level| level > 0.0))
+        {
+            return Some([red, 1.0, blue]);
+        }
+
+        let [red, green_red, green_blue, blue] = self.color_balance().ok()?.wb_rggb_levels;
+        let green = (green_red as f32 + green_blue as f32) / 2.0;
+        if red == 0 || blue == 0 || green == 0.0 {
+            return None;
+        }
+        Some([red as f32 / green, 1.0, blue as f32 / green])
+    }
+
+    /// The R, G, B multipliers for `white_balance`, or `None` if the file
+    /// does not record them.
+    pub fn wb_multipliers(&self, white_balance: WhiteBalance) -> Option<[f32; 3]> {
+        match white_balance {
+            WhiteBalance::AsShot => self.as_shot_wb(),
+            WhiteBalance::Daylight => {
+                Some(self.camera().map_or(DAYLIGHT, Camera::daylight_multipliers))
+            }
+            WhiteBalance::Custom(multipliers) => Some(multipliers),
+        }
+    }
+
+    /// The decoded raw data with its CFA pattern and black and white levels.
+    pub fn raw_image(&self) -> Result<RawImage, NefError> {
+        let data = self.parse_raw_image_data()?;
+        // Default to RGGB, the layout of nearly every Nikon sensor
+        let cfa = self.cfa_pattern_2x2().unwrap_or([0, 1, 1, 2]);
+
+        // Map the R, G, G, B levels onto the CFA positions. The first green
+        // is the one sharing a row with red.
+        let [red, green_red, green_blue, blue] = self.black_levels()?;
+        let red_row = cfa.iter().position(|&color| color == 0).unwrap_or(0) / 2;
+        let mut black_levels = [0; 4];
+        for (index, level) in black_levels.iter_mut().enumerate() {
+            *level = match cfa[index] {
+                0 => red,
+                2 => blue,
+                _ if index / 2 == red_row => green_red,
+                _ => green_blue,
+            };
+        }
+
+        Ok(RawImage {
+            width: self.image_data.width,
+            height: self.image_data.height,
+            data,
+            cfa,
+            black_levels,
+            white_level: self.white_level()?,
+            active_area: self.active_area(),
+            default_crop: self.default_crop(),
+        })
+    }
+
+    /// Writes the raw data and metadata to `path` as a DNG, see [`dng`].
+    pub fn write_dng(&self, path: &Path, options: &DngOptions) -> Result<(), NefError> {
+        std::fs::write(path, dng(self, options)?)?;
+        Ok(())
+    }
+
+    /// The part of the raw image that records the picture: the full frame
+    /// minus the unusable borders the camera database lists. The borders are
+    /// those of full sensor readouts, so in-camera cropped files (DX crop on
+    /// FX bodies, 1.3x crop) keep their whole frame.
+    pub fn active_area(&self) -> Area {
+        let (width, height) = (self.image_data.width, self.image_data.height);
+        let full = Area::full(width, height);
+        let in_camera_crop = self.crop_hi_speed().is_some_and(|crop| {
+            (crop.crop_width as usize, crop.crop_height as usize) == (width, height)
+                && (crop.crop_width, crop.crop_height) != (crop.full_width, crop.full_height)
+        });
+        let Some(camera) = self.camera().filter(|_| !in_camera_crop) else {
+            return full;
+        };
+
+        let [top, right, bottom, left] = camera.crop.map(usize::from);
+        if left + right >= width || top + bottom >= height {
+            return full;
+        }
+        Area {
+            left,
+            top,
+            width: width - left - right,
+            height: height - top - bottom,
+        }
+    }
+
+    /// The framing of the finished image. Files that store the full sensor
+    /// with a smaller image area selected are cropped to the CropHiSpeed
+    /// (0x1B) rectangle; otherwise this is the active area.
+    pub fn default_crop(&self) -> Area {
+        let active_area = self.active_area();
+        let Some(crop) = self.crop_hi_speed() else {
+            return active_area;
+        };
+        let stores_full_sensor = (crop.full_width as usize, crop.full_height as usize)
+            == (self.image_data.width, self.image_data.height);
+        if !stores_full_sensor || crop.crop_width == 0 || crop.crop_height == 0 {
+            return active_area;
+        }
+        let area = Area {
+            left: crop.crop_left as usize,
+            top: crop.crop_top as usize,
+            width: crop.crop_width as usize,
+            height: crop.crop_height as usize,
+        }
+        .intersect(active_area);
+        if area.width == 0 || area.height == 0 {
+            active_area
+        } else {
+            area
+        }
+    }
+
+    fn crop_hi_speed(&self) -> Option<CropHiSpeed> {
+        self.maker_note()?.crop_hi_speed
+    }
+
+    /// Per-channel black levels in R, G, G, B order from the MakerNote
+    /// BlackLevel tag (0x3D), scaled to the raw bit depth. Bodies that do not
+    /// record one use the camera database, or zero if not listed.
+    pub fn black_levels(&self) -> Result<[u16; 4], NefError> {
+        let bps = self.bits_per_sample()?;
+        if let Some(levels) = self
+            .maker_note()
+            .and_then(|maker_note| maker_note.black_level)
+        {
+            return Ok(levels.map(|level| level >> 14_u16.saturating_sub(bps)));
+        }
+        let black = self.camera_levels(bps).map_or(0, |levels| levels.black);
+        Ok([black; 4])
+    }
+
+    /// The saturation level from the camera database. Unlisted bodies use
+    /// the top of the linearization curve for lossy compressed files and the
+    /// largest value of the bit depth otherwise.
+    pub fn white_level(&self) -> Result<u16, NefError> {
+        let bps = self.bits_per_sample()?;
+        if let Some(levels) = self.camera_levels(bps) {
+            return Ok(levels.white);
+        }
+        let full_scale = ((1_u32 << bps.min(16)) - 1) as u16;
+        let curve_max = self
+            .linearization_table()
+            .ok()
+            .filter(|table| !table.is_lossless())
+            .and_then(|table| table.curve(bps).into_iter().max());
+        Ok(curve_max.unwrap_or(full_scale))
+    }
+
+    fn camera_levels(&self, bps: u16) -> Option<Levels> {
+        self.camera()?.levels(bps)
+    }
+
+    /// The matrix from white balanced camera RGB to `space`, from the camera
+    /// database entry for this body or [`FALLBACK`] for unlisted bodies.
+    pub fn color_matrix(&self, space: ColorSpace) -> Option<[[f32; 3]; 3]> {
+        let camera = self.camera().unwrap_or(&FALLBACK);
+        camera_to_space(camera.xyz_to_cam(), space)
+    }
+
+    fn bits_per_sample(&self) -> Result<u16, NefError> {
+        let data_ifd = self.raw_ifd().ok_or(NefError::MissingIfd(IfdRole::Raw))?;
+        Ok(data_ifd
+            .get_required_entry(IfdEntryTag::BitsPerSample)?
+            .get_data_or_offset() as u16)
+    }
+
+    /// The parsed NEFLinearizationTable (0x96) of Nikon compressed files.
+    pub fn linearization_table(&self) -> Result<LinearizationTable, NefError> {
+        let (data, endian) = self.makernote_block(NikonTag::NEFLinearizationTable)?;
+        LinearizationTable::parse(data, endian)
+    }
+
+    /// The raw bytes of a MakerNote tag and the MakerNote byte order.
+    fn makernote_block(&self, tag: NikonTag) -> Result<(&[u8], Endian), NefError> {
+        let makernote_ifd = self
+            .makernote_ifd()
+            .ok_or(NefError::MissingIfd(IfdRole::MakerNote))?;
+        let entry = makernote_ifd
+            .get_nikon_entry(tag)
+            .ok_or(NefError::MissingTag(IfdEntryTag::Unknown(
+                tag.u16_value() as usize
+            )))?;
+        let data = if entry.offset {
+            entry.get_offset_data(&self.buffer)?
+        } else {
+            &entry.data_or_offset[..entry.data_length]
+        };
+        Ok((data, makernote_ifd.endian))
+    }
+
+    fn ifds_with_role(&self, role: IfdRole) -> impl Iterator<Item = &Ifd> {
+        self.ifds.iter().filter(move |ifd| ifd.role == role)
+    }
+
+    fn get_absolute_path(file_name: &Path) -> Result<PathBuf, NefError> {
+        let current_dir = std::env::current_dir()?;
         let file_path = current_dir.join(file_name);
-        file_path.canonicalize().ok()
+        Ok(file_path.canonicalize()?)
     }
 
-    fn parse_ifds(&self) -> Result<Vec<Ifd>, anyhow::Error> {
-        let parsed_ifds = Ifd::parse_ifd(self.buffer.as_slice(), 0);
-        Ok(parsed_ifds)
+    fn parse_ifds(&self) -> Result<Vec<Ifd>, NefError> {
+        if Endian::from_tiff_header(&self.buffer).is_none() {
+            return Err(NefError::NotTiff);
+        }
+        Ifd::parse_ifd(self.buffer.as_slice(), 0)
     }
     // fn parse_metadata(&mut self) -> Result<(), Error> {
     //     // Extract metadata from the file.
     // }
 
-    pub fn parse_raw_image_data(&self) -> Result<Vec<u16>, anyhow::Error> {
-        // Clone the third IFD (Image File Directory) which contains the raw image data
-        let data_ifd = self.ifds[2].clone();
+    pub fn parse_raw_image_data(&self) -> Result<Vec<u16>, NefError> {
+        // The raw IFD (Image File Directory) contains the raw image data
+        let data_ifd = self.raw_ifd().ok_or(NefError::MissingIfd(IfdRole::Raw))?;
 
         // init width and height
         let width = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::ImageWidth)
-            .unwrap()
+            .get_required_entry(IfdEntryTag::ImageWidth)?
             .get_data_or_offset();
         let height = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::ImageLength)
-            .unwrap()
+            .get_required_entry(IfdEntryTag::ImageLength)?
             .get_data_or_offset();
 
-        let stripoffsets = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::StripOffsets)
-            .unwrap()
-            .get_data_or_offset();
-
-        let stripbytecounts = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::StripByteCounts)
-            .unwrap()
-            .get_data_or_offset();
-
-        let src = &self.buffer[stripoffsets..stripoffsets + stripbytecounts];
-        println!(
-            "StripOffsets: {} StripByteCounts: {}",
-            stripoffsets, stripbytecounts
-        );
-
-        // Initialize split value
-        let split = 0;
-
-        // Clone the last IFD which contains the MakerNote
-        let makernote_ifd = self.ifds.last().unwrap().clone();
-        println!("MakeNote IFD offset: {}", makernote_ifd.offset_location);
-
-        // Get the entry for the 0x96 tag from the MakerNote IFD
-        let entry_0x96 = *makernote_ifd.get_entry_by_byte(0x96).unwrap();
-
-        // Get the offset for the 0x96 tag data
-        let pointer_0x96 = entry_0x96.get_data_or_offset();
-        println!("Pointer 0x96: {}", pointer_0x96);
+        let compression = data_ifd
+            .get_required_entry(IfdEntryTag::Compression)?
+            .get_data_or_offset() as u16;
 
         // Get the BitsPerSample value from the data IFD
         let tiff_bps = data_ifd
-            .get_entry(crate::ifd::IfdEntryTag::BitsPerSample)
-            .unwrap()
+            .get_required_entry(IfdEntryTag::BitsPerSample)?
             .get_data_or_offset() as u16;
 
-        // Calculate the total pointer for the 0x96 tag data
-        let mut pointer = pointer_0x96 + makernote_ifd.offset_location;
-
-        println!("Pointer total 0x96: {}\n", pointer);
+        if compression == 7 {
+            return self.decode_lossless_jpeg(data_ifd, width, height);
+        }
 
-        // Get the version bytes
-        let (ver0, ver1) = (self.buffer[pointer], self.buffer[pointer + 1]);
-        println!("Version: {:#02X} {:#02X}", ver0, ver1);
-        pointer += 2;
+        let src = self.strip_data(data_ifd)?;
+        let layout = PackedLayout::detect(&src, width, height, tiff_bps);
 
-        // Determine the Huffman compression type based on the version bytes and BitsPerSample
-        let mut huff_select = if ver0 == 0x46 { 2 } else { 0 };
-        if tiff_bps == 14 {
-            huff_select += 3;
+        match compression {
+            1 => {
+                let layout = layout.ok_or(NefError::UnsupportedLayout {
+                    bps: tiff_bps,
+                    size: src.len(),
+                })?;
+                decode_uncompressed(&src, width, height, layout, data_ifd.endian)
+            }
+            34713 => match layout {
+                // Some bodies, the D100 among them, tag uncompressed data as
+                // Nikon compressed; the strip size gives them away
+                Some(layout @ PackedLayout::Packed12WithControl { .. }) => {
+                    decode_uncompressed(&src, width, height, layout, data_ifd.endian)
+                }
+                Some(layout) if src.len() == width * height * tiff_bps as usize / 8 => {
+                    decode_uncompressed(&src, width, height, layout, data_ifd.endian)
+                }
+                _ => self.decode_nikon_compressed(&src, width, height, tiff_bps),
+            },
+            _ => Err(NefError::UnsupportedCompression(compression)),
         }
+    }
 
-        // Create the Huffman table
-        let huff_table = create_hufftable(huff_select).expect("Error creating huffman table");
+    /// The raw strips concatenated. Single strip files are borrowed as is.
+    fn strip_data(&self, data_ifd: &Ifd) -> Result<Cow<'_, [u8]>, NefError> {
+        let strip_offsets = data_ifd
+            .get_required_entry(IfdEntryTag::StripOffsets)?
+            .get_value(&self.buffer)?;
+        let strip_byte_counts = data_ifd
+            .get_required_entry(IfdEntryTag::StripByteCounts)?
+            .get_value(&self.buffer)?;
 
-        // Read the vertical predictor values
-        let mut vpred: [[u16; 2]; 2] = [[0; 2]; 2];
-        let vpred_bytes = &self.buffer[pointer..pointer + 8];
-        for i in 0..2 {
-            for j in 0..2 {
-                vpred[i][j] = LittleEndian::read_u16(&vpred_bytes[(i * 2 + j) * 2..]);
+        let strips = (0..strip_offsets.len().min(strip_byte_counts.len())).filter_map(|i| {
+            Some((
+                strip_offsets.get_u32(i)? as usize,
+                strip_byte_counts.get_u32(i)? as usize,
+            ))
+        });
+        let mut data = Cow::Borrowed(&[][..]);
+        for (offset, length) in strips {
+            let strip = checked_slice(&self.buffer, offset, length)?;
+            if data.is_empty() {
+                data = Cow::Borrowed(strip);
+            } else {
+                data.to_mut().extend_from_slice(strip);
             }
         }
-        let mut pred_up1 = [vpred[0][0] as i32, vpred[0][1] as i32];
-        let mut pred_up2 = [vpred[1][0] as i32, vpred[1][1] as i32];
-        println!("Predictors: {:?} {:?}", pred_up1, pred_up2);
-
-        pointer += 8;
-
-        // Get curve
-        let curve = nikon_read_curve(self.buffer.as_slice(), &mut pointer, tiff_bps, ver0, ver1);
-        let mut hasher = DefaultHasher::new();
-        curve.table.hash(&mut hasher);
-        println!("Curve hash: {:?}", hasher.finish());
-
-        println!("src len: {}", src.len());
-        println!(
-            "src ten first and last: {:?} {:?}",
-            &src[0..10],
-            &src[src.len() - 10..]
-        );
+        Ok(data)
+    }
 
-        let mut pump = BitPumpMSB::new(src);
-        let mut random = pump.peek_bits(24);
-
-        // Reduce the max value by checking the curve array from the end
-        // If the last two elements of the curve array are equal, decrease max by 1
-        // This process continues until the last two elements are not equal or max is less than or equal to 2
-        let mut out = vec![0; width * height];
-        println!("out.len: {}", out.len());
-        let bps: u32 = tiff_bps as u32;
-        println!("random: {:?}", random);
-        for row in 0..height {
-            // println!("Row: {}", row);
-            if split > 0 && row == split {
-                // This should not happen
-                // huff_table =
-                //     create_hufftable(huff_select + 1).expect("Error creating huffman table");
+    /// Decodes the lossless JPEG (LJ92) data of DNGs, stored in tiles or in
+    /// strips, which act as full-width tiles. The samples of each JPEG fill
+    /// its tile row by row, whatever the component layout.
+    fn decode_lossless_jpeg(
+        &self,
+        data_ifd: &Ifd,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<u16>, NefError> {
+        if width == 0 || height == 0 {
+            return Err(NefError::InvalidMosaic(format!(
+                "{width} x {height} image has no samples"
+            )));
+        }
+        let value = |tag| {
+            data_ifd
+                .get_entry(tag)
+                .map(|entry| entry.get_data_or_offset())
+                .filter(|&value| value > 0)
+        };
+        let (tile_width, tile_length, offsets_tag, counts_tag) = match (
+            value(IfdEntryTag::TileWidth),
+            value(IfdEntryTag::TileLength),
+        ) {
+            (Some(tile_width), Some(tile_length)) => (
+                tile_width,
+                tile_length,
+                IfdEntryTag::TileOffsets,
+                IfdEntryTag::TileByteCounts,
+            ),
+            _ => (
+                width.max(1),
+                value(IfdEntryTag::RowsPerStrip).unwrap_or(height),
+                IfdEntryTag::StripOffsets,
+                IfdEntryTag::StripByteCounts,
+            ),
+        };
+        let offsets = data_ifd
+            .get_required_entry(offsets_tag)?
+            .get_value(&self.buffer)?;
+        let counts = data_ifd
+            .get_required_entry(counts_tag)?
+            .get_value(&self.buffer)?;
+
+        // Every sample takes at least one bit of lossless JPEG data
+        let data_len: usize = (0..counts.len())
+            .filter_map(|index| counts.get_u32(index))
+            .map(|count| count as usize)
+            .sum();
+        let samples = sample_count(width, height, data_len.saturating_mul(8))?;
+
+        let across = width.div_ceil(tile_width);
+        let mut out = vec![0; samples];
+        for index in 0..offsets.len().min(counts.len()) {
+            let (Some(offset), Some(length)) = (offsets.get_u32(index), counts.get_u32(index))
+            else {
+                continue;
+            };
+            let src = checked_slice(&self.buffer, offset as usize, length as usize)?;
+            let jpeg = LosslessJpeg::decode(src)?;
+            if jpeg.width * jpeg.components != tile_width {
+                return Err(NefError::InvalidMosaic(format!(
+                    "tile {index} holds rows of {} samples, not {tile_width}",
+                    jpeg.width * jpeg.components
+                )));
             }
-            pred_up1[row & 1] += huff_table
-                .huff_decode(&mut pump)
-                .expect("Error decoding huffman");
-            // println!("pred_up1: {}", pred_up1[row & 1]);
-            pred_up2[row & 1] += huff_table
-                .huff_decode(&mut pump)
-                .expect("Error decoding huffman");
-            // println!("pred_up2: {}", pred_up2[row & 1]);
-            let mut pred_left1 = pred_up1[row & 1];
-            // println!("pred_left1: {}", pred_left1);
-            let mut pred_left2 = pred_up2[row & 1];
-            // println!("pred_left2: {}", pred_left2);
-            for col in (0..width).step_by(2) {
-                if col > 0 {
-                    pred_left1 += huff_table
-                        .huff_decode(&mut pump)
-                        .expect("Error decoding huffman");
-                    // println!("pred_left1: {}", pred_left1);
-                    pred_left2 += huff_table
-                        .huff_decode(&mut pump)
-                        .expect("Error decoding huffman");
-                    // println!("pred_left2: {}", pred_left2);
+            let top = index / across * tile_length;
+            let left = index % across * tile_width;
+            let columns = tile_width.min(width.saturating_sub(left));
+            for (row, line) in jpeg
+                .data
+                .chunks_exact(tile_width)
+                .take(tile_length)
+                .enumerate()
+            {
+                let y = top + row;
+                if y >= height {
+                    break;
                 }
-                out[row * width + col] = curve.dither(clampbits(pred_left1, bps), &mut random);
-                out[row * width + col + 1] = curve.dither(clampbits(pred_left2, bps), &mut random);
+                out[y * width + left..y * width + left + columns].copy_from_slice(&line[..columns]);
             }
         }
-
         Ok(out)
     }
 
-    // fn parse_image_thumbnail(&mut self) -> Result<(), Error> {
-    //     // Extract image thumbnail data from the file.
-    // }
+    fn decode_nikon_compressed(
+        &self,
+        src: &[u8],
+        width: usize,
+        height: usize,
+        tiff_bps: u16,
+    ) -> Result<Vec<u16>, NefError> {
+        // The MakerNote holds the decompression parameters
+        let table = self.linearization_table()?;
+        decode_nikon(src, width, height, tiff_bps, &table)
+    }
+
+    /// Every embedded JPEG preview, in file order. Dimensions are read from
+    /// the JPEG frame header; entries pointing outside the file or at data
+    /// that is not a JPEG are left out.
+    pub fn previews(&self) -> Vec<ImageThumbnail> {
+        let chain = self.ifd_chain();
+        self.ifds
+            .iter()
+            .filter_map(|ifd| {
+                let start = ifd.get_entry(IfdEntryTag::JpgFromRawStart)?;
+                let length = ifd
+                    .get_entry(IfdEntryTag::JpgFromRawLength)?
+                    .get_data_or_offset();
+                let offset = ifd.base_offset + start.get_data_or_offset();
+                let jpeg = checked_slice(&self.buffer, offset, length).ok()?;
+                let (width, height) = jpeg_dimensions(jpeg)?;
+
+                let source = if ifd.base_offset != 0 {
+                    PreviewSource::MakerNote
+                } else if chain.iter().any(|linked| std::ptr::eq(*linked, ifd)) {
+                    PreviewSource::ChainedIfd
+                } else {
+                    PreviewSource::SubIfd
+                };
+                Some(ImageThumbnail {
+                    source,
+                    offset,
+                    length,
+                    width,
+                    height,
+                })
+            })
+            .collect()
+    }
 
-    // Try to read the 2x2 CFA pattern bytes from the data IFD (CFAPattern, tag 0x828E)
+    /// The bytes of the largest embedded JPEG, without decoding the raw data.
+    pub fn extract_largest_preview(&self) -> Result<&[u8], NefError> {
+        let preview = self
+            .previews()
+            .into_iter()
+            .max_by_key(|preview| (preview.width * preview.height, preview.length))
+            .ok_or(NefError::MissingTag(IfdEntryTag::JpgFromRawStart))?;
+        self.preview_data(&preview)
+    }
+
+    pub fn preview_data(&self, preview: &ImageThumbnail) -> Result<&[u8], NefError> {
+        checked_slice(&self.buffer, preview.offset, preview.length)
+    }
+
+    // Try to read the 2x2 CFA pattern bytes from the raw IFD (CFAPattern, tag 0x828E)
     // Returns the first four bytes in row-major order if available.
     pub fn cfa_pattern_2x2(&self) -> Option<[u8; 4]> {
-        // Heuristic: third IFD (index 2) is the image data IFD in this project
-        let data_ifd = self.ifds.get(2)?;
-        if let Some(entry) = data_ifd.get_entry(crate::ifd::IfdEntryTag::CFAPattern) {
+        let data_ifd = self.raw_ifd()?;
+        if let Some(entry) = data_ifd.get_entry(IfdEntryTag::CFAPattern) {
             if entry.offset {
-                let bytes = entry.get_offset_data(&self.buffer);
+                let bytes = entry.get_offset_data(&self.buffer).ok()?;
                 if bytes.len() >= 4 {
                     return Some([bytes[0], bytes[1], bytes[2], bytes[3]]);
                 }
@@ -310,54 +797,188 @@ impl NefFile {
     }
 }
 
-fn create_hufftable(num: usize) -> Result<HuffTable, String> {
-    let mut htable = HuffTable::empty();
+/// Decodes a Nikon compressed stream of `tiff_bps` bit samples with the
+/// parameters of its NEFLinearizationTable.
+pub fn decode_nikon(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    tiff_bps: u16,
+    table: &LinearizationTable,
+) -> Result<Vec<u16>, NefError> {
+    // Rows are decoded in pairs of samples, one for each CFA column
+    if width % 2 == 1 {
+        return Err(NefError::InvalidMosaic(format!(
+            "odd width {width}, Nikon compressed rows hold pairs of samples"
+        )));
+    }
+    // Every code is at least two bits long, and the pump reads at most eight
+    // bytes of padding past the end
+    let samples = sample_count(width, height, src.len().saturating_add(8).saturating_mul(4))?;
 
-    for i in 0..15 {
-        htable.bits[i] = NIKON_TREE[num][0][i] as u32;
-        htable.huffval[i] = NIKON_TREE[num][1][i] as u32;
-        htable.shiftval[i] = NIKON_TREE[num][2][i] as u32;
+    // Determine the Huffman compression type based on the version bytes and BitsPerSample
+    let mut huff_select = if table.is_lossless() { 2 } else { 0 };
+    if tiff_bps == 14 {
+        huff_select += 3;
     }
 
-    htable.initialize()?;
-    println!("Hufftable: {:?}", htable);
-    Ok(htable)
+    // Create the Huffman table
+    let mut huff_table = create_hufftable(huff_select)?;
+
+    let [[up1_even, up1_odd], [up2_even, up2_odd]] = table.predictors;
+    let mut pred_up1 = [up1_even as i32, up1_odd as i32];
+    let mut pred_up2 = [up2_even as i32, up2_odd as i32];
+
+    // Lossless data maps through the identity and is not dithered, so it
+    // decodes to exactly the values that were encoded
+    let curve = (!table.is_lossless()).then(|| LookupTable::new(&table.curve(tiff_bps)));
+    // Row where lossy after split files change tables
+    let split = table.split_row.unwrap_or(0);
+
+    let mut pump = BitPumpMSB::new(src);
+    let mut random = pump.peek_bits(24);
+    let mut linearize = |value| match &curve {
+        Some(curve) => curve.dither(value, &mut random),
+        None => value,
+    };
+
+    let mut out = vec![0; samples];
+    let bps: u32 = tiff_bps as u32;
+    for row in 0..height {
+        if split > 0 && row == split {
+            // The "after split" tree follows each lossy tree in NIKON_TREE
+            huff_table = create_hufftable(huff_select + 1)?;
+        }
+        let corrupt = |col| move |_| NefError::CorruptHuffmanStream { row, col };
+        pred_up1[row & 1] += huff_table.huff_decode(&mut pump).map_err(corrupt(0))?;
+        pred_up2[row & 1] += huff_table.huff_decode(&mut pump).map_err(corrupt(1))?;
+        let mut pred_left1 = pred_up1[row & 1];
+        let mut pred_left2 = pred_up2[row & 1];
+        for col in (0..width).step_by(2) {
+            if col > 0 {
+                pred_left1 += huff_table.huff_decode(&mut pump).map_err(corrupt(col))?;
+                pred_left2 += huff_table
+                    .huff_decode(&mut pump)
+                    .map_err(corrupt(col + 1))?;
+            }
+            if pump.is_exhausted() {
+                return Err(NefError::CorruptHuffmanStream { row, col });
+            }
+            out[row * width + col] = linearize(clampbits(pred_left1, bps));
+            out[row * width + col + 1] = linearize(clampbits(pred_left2, bps));
+        }
+    }
+
+    Ok(out)
 }
 
-fn nikon_read_curve(
-    buffer: &[u8],
-    pointer: &mut usize,
-    tiff_bps: u16,
-    ver0: u8,
-    ver1: u8,
-) -> LookupTable {
-    let mut points = [0_u16; 1 << 16];
-    for i in 0..points.len() {
-        points[i] = i as u16;
-    }
-    let mut max = 1 << tiff_bps;
-    let csize = read_leu16(buffer, pointer, false) as usize;
-    // let mut split = 0 as usize;
-    let step = if csize > 1 { max / (csize - 1) } else { 0 };
-    if ver0 == 0x44 && ver1 == 0x20 && step > 0 {
-        // should not happen
-        // for i in 0..csize {
-        //     points[i * step] = read_u16(buffer.clone(), pointer);
-        // }
-        // for i in 0..max {
-        //     points[i] = ((points[i - i % step] as usize * (step - i % step)
-        //         + points[i - i % step + step] as usize * (i % step))
-        //         / step) as u16;
-        // }
-        // split = endian.ru16(meta, 562) as usize;
-        println!("Should not happen")
-    } else if ver0 != 0x46 && csize <= 0x4001 {
-        for i in 0..csize {
-            points[i] = read_leu16(buffer, pointer, false);
+/// Compresses a `width` x `height` mosaic of `bps` bit samples, 12 or 14, to
+/// a Nikon lossless stream, the inverse of [`decode_nikon`]. Returns the
+/// stream and the NEFLinearizationTable to store with it, whose initial
+/// predictors are the samples of the top left 2x2 block.
+pub fn encode_nikon_lossless(
+    mosaic: &[u16],
+    width: usize,
+    height: usize,
+    bps: u16,
+) -> Result<(Vec<u8>, LinearizationTable), NefError> {
+    let sample = |row: usize, col: usize| {
+        let index = row.min(height.saturating_sub(1)) * width + col;
+        mosaic.get(index).copied().unwrap_or(0)
+    };
+    let predictors = [[sample(0, 0), sample(1, 0)], [sample(0, 1), sample(1, 1)]];
+    let table = LinearizationTable::lossless(predictors);
+    let stream = encode_nikon(mosaic, width, height, bps, &table)?;
+    Ok((stream, table))
+}
+
+/// Compresses a mosaic with the Huffman tree, initial predictors and split
+/// row `table` selects. The values are those before the curve, for lossy
+/// tables the indices into it. The lossy after split trees only encode some
+/// difference lengths exactly, so data after the split must be smooth.
+pub fn encode_nikon(
+    mosaic: &[u16],
+    width: usize,
+    height: usize,
+    bps: u16,
+    table: &LinearizationTable,
+) -> Result<Vec<u8>, NefError> {
+    if bps != 12 && bps != 14 {
+        return Err(NefError::InvalidMosaic(format!(
+            "no Huffman tree for {bps}-bit samples"
+        )));
+    }
+    // The decoder always produces pairs of samples
+    if width == 0 || width % 2 == 1 || height == 0 || mosaic.len() != width * height {
+        return Err(NefError::InvalidMosaic(format!(
+            "{} samples do not make an even width {width} x {height} image",
+            mosaic.len()
+        )));
+    }
+    if mosaic.iter().any(|&value| value >> bps != 0) {
+        return Err(NefError::InvalidMosaic(format!(
+            "samples exceed {bps} bits"
+        )));
+    }
+
+    let mut huff_select = if table.is_lossless() { 2 } else { 0 };
+    if bps == 14 {
+        huff_select += 3;
+    }
+    let mut huff_table = create_hufftable(huff_select)?;
+    let split = table.split_row.unwrap_or(0);
+
+    // Mirror the predictions of the decoder: the first pair of each row
+    // follows the first pair two rows up, the others the pair to the left
+    let [[up1_even, up1_odd], [up2_even, up2_odd]] = table.predictors;
+    let mut pred_up1 = [up1_even as i32, up1_odd as i32];
+    let mut pred_up2 = [up2_even as i32, up2_odd as i32];
+    let mut writer = BitWriter::new();
+    for (row, line) in mosaic.chunks_exact(width).enumerate() {
+        if split > 0 && row == split {
+            huff_table = create_hufftable(huff_select + 1)?;
+        }
+        let mut pred_left1 = pred_up1[row & 1];
+        let mut pred_left2 = pred_up2[row & 1];
+        for pair in line.chunks_exact(2) {
+            let (value1, value2) = (pair[0] as i32, pair[1] as i32);
+            huff_table.huff_encode(&mut writer, value1 - pred_left1)?;
+            huff_table.huff_encode(&mut writer, value2 - pred_left2)?;
+            pred_left1 = value1;
+            pred_left2 = value2;
         }
-        max = csize;
+        pred_up1[row & 1] = line[0] as i32;
+        pred_up2[row & 1] = line[1] as i32;
     }
-    LookupTable::new(&points[0..max])
+    Ok(writer.finish())
+}
+
+/// `width * height`, refused when it overflows or exceeds `max_samples`, the
+/// most the data could hold, so corrupt dimensions cannot cause a huge
+/// allocation.
+fn sample_count(width: usize, height: usize, max_samples: usize) -> Result<usize, NefError> {
+    width
+        .checked_mul(height)
+        .filter(|&samples| samples <= max_samples)
+        .ok_or_else(|| {
+            NefError::InvalidMosaic(format!(
+                "{width} x {height} samples are more than the data can hold"
+            ))
+        })
+}
+
+fn create_hufftable(num: usize) -> Result<HuffTable, NefError> {
+    let mut htable = HuffTable::empty();
+
+    let [bits, huffval, shiftval] = &NIKON_TREE[num];
+    for i in 0..15 {
+        htable.bits[i] = bits[i] as u32;
+        htable.huffval[i] = huffval[i] as u32;
+        htable.shiftval[i] = shiftval[i] as u32;
+    }
+
+    htable.initialize()?;
+    Ok(htable)
 }
 
 pub fn clampbits(val: i32, bits: u32) -> u16 {
@@ -386,12 +1007,13 @@ impl LookupTable {
             } else {
                 center
             };
+            // Curves read from corrupt files need not be monotonic
+            let delta = upper.saturating_sub(lower);
             let base = if center == 0 {
                 0
             } else {
-                center - ((upper - lower + 2) / 4)
+                center.saturating_sub(((delta as u32 + 2) / 4) as u16)
             };
-            let delta = upper - lower;
             tbl[i] = (center, base, delta);
         }
         LookupTable { table: tbl }
@@ -403,7 +1025,7 @@ impl LookupTable {
     //  }
 
     pub fn dither(&self, value: u16, rand: &mut u32) -> u16 {
-        let (_, sbase, sdelta) = self.table[value as usize];
+        let (_, sbase, sdelta) = self.table[(value as usize).min(self.table.len() - 1)];
         let base = sbase as u32;
         let delta = sdelta as u32;
         let pixel = base + ((delta * (*rand & 2047) + 1024) >> 12);
@@ -420,7 +1042,7 @@ pub struct BitPumpMSB<'a> {
 }
 
 impl<'a> BitPumpMSB<'a> {
-    pub fn new(src: &'a [u8]) -> BitPumpMSB {
+    pub fn new(src: &'a [u8]) -> BitPumpMSB<'a> {
         BitPumpMSB {
             buffer: src,
             pos: 0,
@@ -428,13 +1050,28 @@ impl<'a> BitPumpMSB<'a> {
             nbits: 0,
         }
     }
+
+    /// Whether the pump has run past the end of the buffer by more than the
+    /// zero padding a well formed stream can consume while peeking ahead.
+    pub fn is_exhausted(&self) -> bool {
+        self.pos > self.buffer.len() + 8
+    }
 }
 
 impl<'a> BitPump for BitPumpMSB<'a> {
     #[inline(always)]
     fn peek_bits(&mut self, num: u32) -> u32 {
         if num > self.nbits {
-            let inbits: u64 = read_beu32(self.buffer, &mut self.pos, true) as u64;
+            // Past the end of the buffer the stream is padded with zeros
+            let inbits: u64 = match read_beu32(self.buffer, &mut self.pos, true) {
+                Ok(int) => int as u64,
+                Err(_) => {
+                    let mut template = [0_u8; 4];
+                    let rest = self.buffer.get(self.pos..).unwrap_or_default();
+                    template[..rest.len()].copy_from_slice(rest);
+                    u32::from_be_bytes(template) as u64
+                }
+            };
             self.bits = (self.bits << 32) | inbits;
             self.pos += 4;
             self.nbits += 32;
@@ -447,3 +1084,110 @@ impl<'a> BitPump for BitPumpMSB<'a> {
         self.bits &= (1 << self.nbits) - 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 2x2 stream in the 12-bit lossless tree, written out by hand. From
+    /// predictors of 2048 the differences are 3 and -1 on the first row,
+    /// 0 and 5 on the second:
+    /// `1100 11`, `11100 0`, `11110`, `100 101` and one bit of padding.
+    const LOSSLESS_2X2: [u8; 3] = [0b1100_1111, 0b1000_1111, 0b0100_1010];
+
+    fn lossless_table(predictors: [[u16; 2]; 2]) -> LinearizationTable {
+        LinearizationTable {
+            version: (0x46, 0x30),
+            predictors,
+            points: Vec::new(),
+            split_row: None,
+            bit_depth: None,
+        }
+    }
+
+    #[test]
+    fn decodes_lossless_data_exactly() {
+        let table = lossless_table([[2048; 2]; 2]);
+        let decoded = decode_nikon(&LOSSLESS_2X2, 2, 2, 12, &table).unwrap();
+        assert_eq!(decoded, [2051, 2047, 2048, 2053]);
+    }
+
+    #[test]
+    fn truncated_stream_is_an_error() {
+        let table = lossless_table([[2048; 2]; 2]);
+        assert!(matches!(
+            decode_nikon(&LOSSLESS_2X2, 2, 8, 12, &table),
+            Err(NefError::CorruptHuffmanStream { .. })
+        ));
+    }
+
+    #[test]
+    fn rejects_unusable_dimensions() {
+        let table = lossless_table([[2048; 2]; 2]);
+        let invalid = |width, height| {
+            matches!(
+                decode_nikon(&LOSSLESS_2X2, width, height, 12, &table),
+                Err(NefError::InvalidMosaic(_))
+            )
+        };
+        assert!(invalid(3, 2));
+        assert!(invalid(1, 1));
+        // Overflowing, and more samples than three bytes can hold
+        assert!(invalid(usize::MAX - 1, 4));
+        assert!(invalid(1 << 16, 1 << 16));
+        assert!(invalid(46, 1));
+        assert!(
+            decode_nikon(&LOSSLESS_2X2, 2, 0, 12, &table)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn lossless_round_trip() {
+        for bps in [12, 14] {
+            let max = (1 << bps) - 1;
+            let (width, height) = (10, 7);
+            // Both extremes next to each other and values spread over the range
+            let mosaic: Vec<u16> = (0..width * height)
+                .map(|index| match index % 5 {
+                    0 => 0,
+                    1 => max,
+                    _ => (index * 997 % (max as usize + 1)) as u16,
+                })
+                .collect();
+            let (stream, table) = encode_nikon_lossless(&mosaic, width, height, bps).unwrap();
+            assert_eq!(table.predictors, [[0, 0], [max, max]]);
+            let decoded = decode_nikon(&stream, width, height, bps, &table).unwrap();
+            assert_eq!(decoded, mosaic, "{bps} bits");
+        }
+    }
+
+    #[test]
+    fn after_split_trees_encode_with_unshifted_codes() {
+        // Both trees list shifted codes for length 5 (12 bits) or 7 (14 bits)
+        // before the unshifted one. Length 6 of the 12-bit tree only has
+        // shifted codes and lengths 9 to 12 of the 14-bit tree none at all.
+        let cases: [(usize, &[i32], i32); 2] = [
+            (1, &[0, 1, -2, 17, -31, 2047, -4095], 40),
+            (4, &[0, 3, 100, -64, 255, 8191], 300),
+        ];
+        for (tree, diffs, unencodable) in cases {
+            let table = create_hufftable(tree).unwrap();
+            let mut writer = BitWriter::new();
+            for &diff in diffs {
+                table.huff_encode(&mut writer, diff).unwrap();
+            }
+            let stream = writer.finish();
+            let mut pump = BitPumpMSB::new(&stream);
+            for &diff in diffs {
+                assert_eq!(table.huff_decode(&mut pump).unwrap(), diff, "tree {tree}");
+            }
+            assert!(
+                table
+                    .huff_encode(&mut BitWriter::new(), unencodable)
+                    .is_err()
+            );
+        }
+    }
+}
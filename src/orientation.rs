@@ -0,0 +1,124 @@
+use crate::error::NefError;
+use crate::ifd::IfdEntryTag;
+use image::metadata::Orientation;
+
+/// A minimal EXIF block, a little-endian TIFF structure whose IFD0 holds
+/// only the Orientation tag. This is the form image encoders and the JPEG
+/// APP1 segment expect, without the `Exif\0\0` prefix.
+pub fn orientation_exif(orientation: Orientation) -> Vec<u8> {
+    let mut exif = Vec::with_capacity(26);
+    exif.extend_from_slice(b"II");
+    exif.extend_from_slice(&42_u16.to_le_bytes());
+    exif.extend_from_slice(&8_u32.to_le_bytes());
+    // One entry: tag, type SHORT, count 1, value padded to four bytes
+    exif.extend_from_slice(&1_u16.to_le_bytes());
+    exif.extend_from_slice(&IfdEntryTag::Orientation.u16_value().to_le_bytes());
+    exif.extend_from_slice(&3_u16.to_le_bytes());
+    exif.extend_from_slice(&1_u32.to_le_bytes());
+    exif.extend_from_slice(&(orientation.to_exif() as u16).to_le_bytes());
+    exif.extend_from_slice(&[0, 0]);
+    // No next IFD
+    exif.extend_from_slice(&0_u32.to_le_bytes());
+    exif
+}
+
+/// Returns `jpeg` with `exif` stored in an APP1 segment after SOI and any
+/// JFIF APP0 segment. Existing EXIF APP1 segments are dropped, so the new
+/// block is the only one readers see.
+pub fn jpeg_with_exif(jpeg: &[u8], exif: &[u8]) -> Result<Vec<u8>, NefError> {
+    if jpeg.len() < 4 || jpeg[..2] != [0xFF, 0xD8] {
+        return Err(NefError::InvalidJpeg);
+    }
+    let segment_len =
+        u16::try_from(exif.len() + 8).map_err(|_| NefError::ExifTooLarge(exif.len()))?;
+
+    let mut app1 = Vec::with_capacity(segment_len as usize + 2);
+    app1.extend_from_slice(&[0xFF, 0xE1]);
+    app1.extend_from_slice(&segment_len.to_be_bytes());
+    app1.extend_from_slice(b"Exif\0\0");
+    app1.extend_from_slice(exif);
+
+    let mut out = Vec::with_capacity(jpeg.len() + app1.len());
+    out.extend_from_slice(&jpeg[..2]);
+    let mut pointer = 2;
+    let mut inserted = false;
+    // Walk the APPn segments; the first other marker starts the image data
+    while pointer + 4 <= jpeg.len()
+        && jpeg[pointer] == 0xFF
+        && (0xE0..=0xEF).contains(&jpeg[pointer + 1])
+    {
+        let marker = jpeg[pointer + 1];
+        let len = u16::from_be_bytes([jpeg[pointer + 2], jpeg[pointer + 3]]) as usize;
+        let segment = jpeg
+            .get(pointer..pointer + 2 + len)
+            .ok_or(NefError::OutOfBounds {
+                offset: pointer,
+                len: len + 2,
+            })?;
+        if marker != 0xE0 && !inserted {
+            out.extend_from_slice(&app1);
+            inserted = true;
+        }
+        let is_exif = segment
+            .get(4..)
+            .is_some_and(|data| data.starts_with(b"Exif\0\0"));
+        if !(marker == 0xE1 && is_exif) {
+            out.extend_from_slice(segment);
+        }
+        pointer += 2 + len;
+    }
+    if !inserted {
+        out.extend_from_slice(&app1);
+    }
+    out.extend_from_slice(&jpeg[pointer..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOI: [u8; 2] = [0xFF, 0xD8];
+    const JFIF: [u8; 6] = [0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46];
+    const OLD_EXIF: [u8; 10] = [0xFF, 0xE1, 0x00, 0x08, b'E', b'x', b'i', b'f', 0, 0];
+    const IMAGE: [u8; 4] = [0xFF, 0xDB, 0xFF, 0xD9];
+
+    #[test]
+    fn writes_a_single_orientation_entry() {
+        let exif = orientation_exif(Orientation::Rotate90);
+        let expected = [
+            0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, // header
+            0x01, 0x00, // one entry
+            0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, // 6
+            0x00, 0x00, 0x00, 0x00, // no next IFD
+        ];
+        assert_eq!(exif, expected);
+    }
+
+    #[test]
+    fn inserts_after_jfif_and_drops_old_exif() {
+        let jpeg = [&SOI[..], &JFIF, &OLD_EXIF, &IMAGE].concat();
+        let out = jpeg_with_exif(&jpeg, &[1, 2]).unwrap();
+        let app1 = [0xFF, 0xE1, 0x00, 0x0A, b'E', b'x', b'i', b'f', 0, 0, 1, 2];
+        assert_eq!(out, [&SOI[..], &JFIF, &app1, &IMAGE].concat());
+
+        let out = jpeg_with_exif(&[&SOI[..], &IMAGE].concat(), &[1, 2]).unwrap();
+        assert_eq!(out, [&SOI[..], &app1, &IMAGE].concat());
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(matches!(
+            jpeg_with_exif(&[0x89, b'P', b'N', b'G'], &[]),
+            Err(NefError::InvalidJpeg)
+        ));
+        assert!(matches!(
+            jpeg_with_exif(&[&SOI[..], &IMAGE].concat(), &[0; 65530]),
+            Err(NefError::ExifTooLarge(65530))
+        ));
+        assert!(matches!(
+            jpeg_with_exif(&[&SOI[..], &JFIF[..4]].concat(), &[]),
+            Err(NefError::OutOfBounds { offset: 2, .. })
+        ));
+    }
+}
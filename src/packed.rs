@@ -0,0 +1,230 @@
+use crate::error::NefError;
+use crate::utils::Endian;
+
+/// Layout of an uncompressed (Compression = 1) raw strip, derived from the
+/// strip size since the NEF does not describe it directly.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PackedLayout {
+    /// Samples of `bits` bits stored back to back, rows `stride` bytes apart.
+    /// Little-endian files pack the bits LSB first, big-endian files MSB first.
+    Packed { bits: u32, stride: usize },
+    /// Every sample in its own 16-bit word (D1 and most 14-bit bodies), masked
+    /// to `bits` bits.
+    Unpacked { bits: u32, stride: usize },
+    /// 12-bit samples with a zero control byte after every 15 data bytes, as
+    /// written by the D100.
+    Packed12WithControl { stride: usize },
+}
+
+impl PackedLayout {
+    /// Picks the layout whose row size fits the strip. The first bytes of the
+    /// strip tell the D100 control byte layout apart.
+    pub fn detect(strip: &[u8], width: usize, height: usize, bps: u16) -> Option<PackedLayout> {
+        if width == 0 || height == 0 || !(8..=16).contains(&bps) {
+            return None;
+        }
+        let bits = bps as u32;
+        let stride = strip.len() / height;
+        let packed_row = (width * bps as usize).div_ceil(8);
+
+        if stride >= width * 2 {
+            Some(PackedLayout::Unpacked { bits, stride })
+        } else if bps == 12 && stride * 15 >= packed_row * 16 && has_control_bytes(strip) {
+            Some(PackedLayout::Packed12WithControl { stride })
+        } else if stride >= packed_row {
+            Some(PackedLayout::Packed { bits, stride })
+        } else {
+            None
+        }
+    }
+}
+
+/// The D100 leaves every 16th byte zero; Huffman data essentially never does.
+fn has_control_bytes(strip: &[u8]) -> bool {
+    strip.len() >= 256
+        && strip[..256]
+            .iter()
+            .skip(15)
+            .step_by(16)
+            .all(|&byte| byte == 0)
+}
+
+/// Decodes an uncompressed strip into one `u16` per pixel, row by row.
+pub fn decode_uncompressed(
+    src: &[u8],
+    width: usize,
+    height: usize,
+    layout: PackedLayout,
+    endian: Endian,
+) -> Result<Vec<u16>, NefError> {
+    let stride = match layout {
+        PackedLayout::Packed { stride, .. }
+        | PackedLayout::Unpacked { stride, .. }
+        | PackedLayout::Packed12WithControl { stride } => stride,
+    };
+    if width == 0 {
+        return Ok(Vec::new());
+    }
+    let needed = stride * height;
+    if src.len() < needed {
+        return Err(NefError::OutOfBounds {
+            offset: 0,
+            len: needed,
+        });
+    }
+
+    let mut out = vec![0; width * height];
+    for (row, line) in out.chunks_exact_mut(width).enumerate() {
+        let input = &src[row * stride..(row + 1) * stride];
+        match layout {
+            PackedLayout::Packed { bits, .. } => {
+                unpack_bits(input.iter().copied(), line, bits, endian)
+            }
+            PackedLayout::Unpacked { bits, .. } => {
+                let mask = ((1_u32 << bits) - 1) as u16;
+                for (value, bytes) in line.iter_mut().zip(input.chunks_exact(2)) {
+                    *value = endian.u16(bytes) & mask;
+                }
+            }
+            PackedLayout::Packed12WithControl { .. } => {
+                let data = input
+                    .chunks(16)
+                    .flat_map(|chunk| &chunk[..chunk.len().min(15)]);
+                unpack_bits(data.copied(), line, 12, endian)
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn unpack_bits(input: impl Iterator<Item = u8>, out: &mut [u16], bits: u32, endian: Endian) {
+    let mask = (1_u64 << bits) - 1;
+    let mut acc: u64 = 0;
+    let mut nbits = 0;
+    let mut values = out.iter_mut();
+    for byte in input {
+        match endian {
+            Endian::Big => acc = (acc << 8) | byte as u64,
+            Endian::Little => acc |= (byte as u64) << nbits,
+        }
+        nbits += 8;
+        while nbits >= bits {
+            let Some(value) = values.next() else {
+                return;
+            };
+            nbits -= bits;
+            match endian {
+                Endian::Big => *value = ((acc >> nbits) & mask) as u16,
+                Endian::Little => {
+                    *value = (acc & mask) as u16;
+                    acc >>= bits;
+                }
+            }
+        }
+        if endian == Endian::Big {
+            acc &= (1 << nbits) - 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(src: &[u8], width: usize, layout: PackedLayout, endian: Endian) -> Vec<u16> {
+        decode_uncompressed(src, width, 1, layout, endian).unwrap()
+    }
+
+    #[test]
+    fn unpacks_12_bit_samples() {
+        let layout = PackedLayout::Packed {
+            bits: 12,
+            stride: 3,
+        };
+        // MSB first: 123 456, LSB first: 23, 6 and 1, 45
+        assert_eq!(
+            decode(&[0x12, 0x34, 0x56], 2, layout, Endian::Big),
+            [0x123, 0x456]
+        );
+        assert_eq!(
+            decode(&[0x23, 0x61, 0x45], 2, layout, Endian::Little),
+            [0x123, 0x456]
+        );
+    }
+
+    #[test]
+    fn unpacks_14_bit_samples() {
+        let layout = PackedLayout::Packed {
+            bits: 14,
+            stride: 4,
+        };
+        // 01001000110100 00101010111100 and four bits of padding
+        assert_eq!(
+            decode(&[0x48, 0xD0, 0xAB, 0xC0], 2, layout, Endian::Big),
+            [0x1234, 0x0ABC]
+        );
+    }
+
+    #[test]
+    fn masks_unpacked_samples() {
+        let layout = PackedLayout::Unpacked {
+            bits: 12,
+            stride: 4,
+        };
+        assert_eq!(
+            decode(&[0x34, 0xF2, 0xBC, 0x0A], 2, layout, Endian::Little),
+            [0x234, 0xABC]
+        );
+    }
+
+    #[test]
+    fn skips_control_bytes() {
+        let mut row = [0x12, 0x34, 0x56].repeat(5);
+        row.push(0);
+        let layout = PackedLayout::Packed12WithControl { stride: 16 };
+        assert_eq!(
+            decode(&row, 10, layout, Endian::Big),
+            [0x123, 0x456].repeat(5)
+        );
+    }
+
+    #[test]
+    fn detects_the_layout_from_the_strip_size() {
+        let detect = |len, bps| PackedLayout::detect(&vec![0xAA; len], 4, 2, bps);
+        assert_eq!(
+            detect(16, 12),
+            Some(PackedLayout::Unpacked {
+                bits: 12,
+                stride: 8
+            })
+        );
+        assert_eq!(
+            detect(12, 12),
+            Some(PackedLayout::Packed {
+                bits: 12,
+                stride: 6
+            })
+        );
+        assert_eq!(
+            detect(14, 14),
+            Some(PackedLayout::Packed {
+                bits: 14,
+                stride: 7
+            })
+        );
+        assert_eq!(detect(10, 12), None);
+        assert_eq!(detect(16, 18), None);
+    }
+
+    #[test]
+    fn short_strip_is_an_error() {
+        let layout = PackedLayout::Packed {
+            bits: 12,
+            stride: 3,
+        };
+        assert!(matches!(
+            decode_uncompressed(&[0x12, 0x34, 0x56], 2, 2, layout, Endian::Big),
+            Err(NefError::OutOfBounds { .. })
+        ));
+    }
+}
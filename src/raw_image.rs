@@ -0,0 +1,302 @@
+/// A decoded CFA mosaic together with the levels needed to interpret it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawImage {
+    pub width: usize,
+    pub height: usize,
+    /// One sample per pixel, row by row, as stored by the camera.
+    pub data: Vec<u16>,
+    /// Color of each position of the 2x2 CFA tile in row-major order, using
+    /// the TIFF/EP codes 0 red, 1 green and 2 blue.
+    pub cfa: [u8; 4],
+    /// Black level of each position of the 2x2 CFA tile in row-major order.
+    pub black_levels: [u16; 4],
+    /// The sample value at which the sensor saturates.
+    pub white_level: u16,
+    /// The part of the sensor that records the image. Samples outside it are
+    /// masked or unusable, and kept for black level estimation.
+    pub active_area: Area,
+    /// The framing of the finished image inside the active area, e.g. the
+    /// image area selected on the camera.
+    pub default_crop: Area,
+}
+
+/// A rectangle of the raw image, in pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Area {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Area {
+    /// The whole of a `width` x `height` image.
+    pub fn full(width: usize, height: usize) -> Area {
+        Area {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+
+    /// The part of `self` inside `other`; empty if they do not overlap.
+    pub fn intersect(&self, other: Area) -> Area {
+        let left = self.left.max(other.left);
+        let top = self.top.max(other.top);
+        let right = (self.left + self.width).min(other.left + other.width);
+        let bottom = (self.top + self.height).min(other.top + other.height);
+        Area {
+            left,
+            top,
+            width: right.saturating_sub(left),
+            height: bottom.saturating_sub(top),
+        }
+    }
+
+    pub fn contains(&self, row: usize, col: usize) -> bool {
+        (self.top..self.top + self.height).contains(&row)
+            && (self.left..self.left + self.width).contains(&col)
+    }
+}
+
+/// Which part of the sensor an output covers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Crop {
+    /// Every stored sample, masked borders included.
+    Full,
+    /// [`RawImage::active_area`].
+    ActiveArea,
+    /// [`RawImage::default_crop`].
+    #[default]
+    Default,
+}
+
+impl RawImage {
+    /// Position in the 2x2 CFA tile of the pixel at `row`, `col`.
+    pub fn cfa_index(row: usize, col: usize) -> usize {
+        (row & 1) * 2 + (col & 1)
+    }
+
+    /// The color (0 red, 1 green, 2 blue) of the pixel at `row`, `col`.
+    pub fn color_at(&self, row: usize, col: usize) -> u8 {
+        self.cfa[Self::cfa_index(row, col)]
+    }
+
+    /// The area an output with the given `crop` covers.
+    pub fn crop_area(&self, crop: Crop) -> Area {
+        match crop {
+            Crop::Full => Area::full(self.width, self.height),
+            Crop::ActiveArea => self.active_area,
+            Crop::Default => self.default_crop,
+        }
+    }
+
+    /// The part of the mosaic inside `area`. The CFA pattern and black levels
+    /// follow the new origin, and the active area and default crop are moved
+    /// into the new coordinates.
+    pub fn cropped(&self, area: Area) -> RawImage {
+        let area = area.intersect(Area::full(self.width, self.height));
+        let mut data = Vec::with_capacity(area.width * area.height);
+        for line in self
+            .data
+            .chunks_exact(self.width.max(1))
+            .skip(area.top)
+            .take(area.height)
+        {
+            data.extend_from_slice(&line[area.left..area.left + area.width]);
+        }
+
+        // Position i of the new tile is at row i / 2 + top, column i % 2 + left
+        let shifted = |index: usize| Self::cfa_index(index / 2 + area.top, index % 2 + area.left);
+        let relative = |inner: Area| {
+            let inner = inner.intersect(area);
+            Area {
+                left: inner.left - area.left,
+                top: inner.top - area.top,
+                ..inner
+            }
+        };
+
+        RawImage {
+            width: area.width,
+            height: area.height,
+            data,
+            cfa: std::array::from_fn(|index| self.cfa[shifted(index)]),
+            black_levels: std::array::from_fn(|index| self.black_levels[shifted(index)]),
+            white_level: self.white_level,
+            active_area: relative(self.active_area),
+            default_crop: relative(self.default_crop),
+        }
+    }
+
+    /// Per-position black levels measured as the mean of the samples outside
+    /// the active area. `None` unless every CFA position has masked samples.
+    pub fn masked_black_levels(&self) -> Option<[u16; 4]> {
+        let mut sums = [0_u64; 4];
+        let mut counts = [0_u64; 4];
+        for (row, line) in self.data.chunks_exact(self.width.max(1)).enumerate() {
+            for (col, &value) in line.iter().enumerate() {
+                if !self.active_area.contains(row, col) {
+                    let index = Self::cfa_index(row, col);
+                    sums[index] += value as u64;
+                    counts[index] += 1;
+                }
+            }
+        }
+        if counts.contains(&0) {
+            return None;
+        }
+        let mut levels = [0; 4];
+        for (index, level) in levels.iter_mut().enumerate() {
+            *level = ((sums[index] + counts[index] / 2) / counts[index]) as u16;
+        }
+        Some(levels)
+    }
+
+    /// The samples with the per-channel black level removed and scaled so
+    /// that the white level maps to 1.0. Values are clamped to 0.0..=1.0.
+    pub fn subtract_black(&self) -> Vec<f32> {
+        let scales = self
+            .black_levels
+            .map(|black| 1.0 / (self.white_level.saturating_sub(black)).max(1) as f32);
+
+        let mut out = Vec::with_capacity(self.data.len());
+        for (row, line) in self.data.chunks_exact(self.width.max(1)).enumerate() {
+            for (col, &value) in line.iter().enumerate() {
+                let index = Self::cfa_index(row, col);
+                let value = value.saturating_sub(self.black_levels[index]) as f32 * scales[index];
+                out.push(value.min(1.0));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 4x2 RGGB mosaic with distinct black levels and no masked border.
+    fn mosaic() -> RawImage {
+        RawImage {
+            width: 4,
+            height: 2,
+            data: vec![100, 200, 1123, 4200, 300, 50, 2000, 1000],
+            cfa: [0, 1, 1, 2],
+            black_levels: [100, 200, 300, 1000],
+            white_level: 4095,
+            active_area: Area::full(4, 2),
+            default_crop: Area::full(4, 2),
+        }
+    }
+
+    #[test]
+    fn follows_the_cfa_tile() {
+        let raw_image = mosaic();
+        assert_eq!(RawImage::cfa_index(3, 2), 2);
+        assert_eq!(RawImage::cfa_index(4, 5), 1);
+        assert_eq!(raw_image.color_at(0, 0), 0);
+        assert_eq!(raw_image.color_at(1, 0), 1);
+        assert_eq!(raw_image.color_at(3, 3), 2);
+    }
+
+    #[test]
+    fn subtracts_per_channel_black_levels() {
+        let out = mosaic().subtract_black();
+        let expected = [
+            0.0,
+            0.0,
+            1023.0 / 3995.0,
+            // Above the white level
+            1.0,
+            0.0,
+            // Below the black level
+            0.0,
+            1700.0 / 3795.0,
+            0.0,
+        ];
+        assert!(
+            out.iter()
+                .zip(expected)
+                .all(|(value, expected)| (value - expected).abs() < 1e-6),
+            "{out:?}"
+        );
+    }
+
+    #[test]
+    fn subtract_black_survives_a_black_level_at_white() {
+        let raw_image = RawImage {
+            black_levels: [4095; 4],
+            ..mosaic()
+        };
+        let out = raw_image.subtract_black();
+        assert_eq!(out, [0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn intersects_areas() {
+        let area = Area {
+            left: 2,
+            top: 1,
+            width: 4,
+            height: 4,
+        };
+        let other = Area::full(5, 3);
+        let expected = Area {
+            left: 2,
+            top: 1,
+            width: 3,
+            height: 2,
+        };
+        assert_eq!(area.intersect(other), expected);
+        assert!(area.contains(4, 5) && !area.contains(0, 2) && !area.contains(1, 6));
+        let apart = Area { left: 9, ..area };
+        assert_eq!(area.intersect(apart).width, 0);
+    }
+
+    #[test]
+    fn crops_follow_the_cfa_phase() {
+        let raw_image = RawImage {
+            active_area: Area {
+                left: 1,
+                top: 0,
+                width: 3,
+                height: 2,
+            },
+            ..mosaic()
+        };
+        let area = Area {
+            left: 1,
+            top: 1,
+            width: 3,
+            height: 1,
+        };
+        let cropped = raw_image.cropped(area);
+        assert_eq!((cropped.width, cropped.height), (3, 1));
+        assert_eq!(cropped.data, [50, 2000, 1000]);
+        // The new origin is a blue pixel of the RGGB mosaic
+        assert_eq!(cropped.cfa, [2, 1, 1, 0]);
+        assert_eq!(cropped.black_levels, [1000, 300, 200, 100]);
+        assert_eq!(cropped.active_area, Area::full(3, 1));
+        assert_eq!(raw_image.crop_area(Crop::ActiveArea), raw_image.active_area);
+        assert_eq!(raw_image.crop_area(Crop::Full), Area::full(4, 2));
+    }
+
+    #[test]
+    fn measures_black_in_the_masked_border() {
+        // The left two columns are masked
+        let raw_image = RawImage {
+            active_area: Area {
+                left: 2,
+                top: 0,
+                width: 2,
+                height: 2,
+            },
+            ..mosaic()
+        };
+        assert_eq!(raw_image.masked_black_levels(), Some([100, 200, 300, 50]));
+        // Nothing masked
+        assert_eq!(mosaic().masked_black_levels(), None);
+    }
+}
@@ -0,0 +1,523 @@
+use std::collections::BTreeMap;
+
+use image::DynamicImage;
+use image::metadata::Orientation;
+
+use crate::error::NefError;
+use crate::ifd::{Ifd, IfdEntryTag, IfdValue};
+use crate::nef::NefFile;
+use crate::utils::Endian;
+
+/// Rows per strip of the image data; a strip of 16-bit RGB at 6000 pixels
+/// per row stays below 3 MB.
+const ROWS_PER_STRIP: usize = 64;
+
+/// IFD0 tags describing the capture that are copied into new files.
+const DESCRIPTIVE_TAGS: [IfdEntryTag; 6] = [
+    IfdEntryTag::Make,
+    IfdEntryTag::Model,
+    IfdEntryTag::Software,
+    IfdEntryTag::DateTime,
+    IfdEntryTag::Artist,
+    IfdEntryTag::CopyRight,
+];
+
+/// The value of a tag in a [`TiffDirectory`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum TiffField {
+    Value(IfdValue),
+    /// Data blocks written elsewhere in the file; the tag holds their offsets
+    /// as LONGs.
+    Blocks(Vec<Vec<u8>>),
+    /// Blocks of 16-bit samples, like [`TiffField::Blocks`] but written in
+    /// the byte order of the file.
+    Samples(Vec<Vec<u16>>),
+    /// Directories written elsewhere in the file; the tag holds their offsets
+    /// as LONGs, like the EXIF pointer or SubIFDs.
+    Directories(Vec<TiffDirectory>),
+}
+
+/// A directory to write, its fields kept in ascending tag order as TIFF
+/// requires.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TiffDirectory {
+    fields: BTreeMap<u16, TiffField>,
+}
+
+impl TiffDirectory {
+    pub fn new() -> TiffDirectory {
+        TiffDirectory::default()
+    }
+
+    pub fn set(&mut self, tag: IfdEntryTag, value: IfdValue) {
+        self.fields.insert(tag.u16_value(), TiffField::Value(value));
+    }
+
+    /// Stores `blocks` with their offsets in `offsets_tag` and their lengths
+    /// in `counts_tag`, e.g. StripOffsets and StripByteCounts.
+    pub fn set_blocks(
+        &mut self,
+        offsets_tag: IfdEntryTag,
+        counts_tag: IfdEntryTag,
+        blocks: Vec<Vec<u8>>,
+    ) {
+        let counts = blocks.iter().map(|block| block.len() as u32).collect();
+        self.set(counts_tag, IfdValue::Longs(counts));
+        self.fields
+            .insert(offsets_tag.u16_value(), TiffField::Blocks(blocks));
+    }
+
+    /// Like [`TiffDirectory::set_blocks`] for blocks of 16-bit samples.
+    pub fn set_sample_blocks(
+        &mut self,
+        offsets_tag: IfdEntryTag,
+        counts_tag: IfdEntryTag,
+        blocks: Vec<Vec<u16>>,
+    ) {
+        let counts = blocks.iter().map(|block| block.len() as u32 * 2).collect();
+        self.set(counts_tag, IfdValue::Longs(counts));
+        self.fields
+            .insert(offsets_tag.u16_value(), TiffField::Samples(blocks));
+    }
+
+    pub fn set_directories(&mut self, tag: IfdEntryTag, directories: Vec<TiffDirectory>) {
+        self.fields
+            .insert(tag.u16_value(), TiffField::Directories(directories));
+    }
+
+    /// Adds every field of `other`, replacing those with the same tag.
+    pub fn merge(&mut self, other: TiffDirectory) {
+        self.fields.extend(other.fields);
+    }
+
+    pub fn remove(&mut self, tag: IfdEntryTag) {
+        self.fields.remove(&tag.u16_value());
+    }
+
+    pub fn contains(&self, tag: IfdEntryTag) -> bool {
+        self.fields.contains_key(&tag.u16_value())
+    }
+
+    /// Copies `tag` from `ifd` if it is present and its value can be read.
+    pub fn copy_entry(&mut self, ifd: &Ifd, buffer: &[u8], tag: IfdEntryTag) {
+        if let Some(value) = ifd
+            .get_entry(tag)
+            .and_then(|entry| entry.get_value(buffer).ok())
+        {
+            self.set(tag, value);
+        }
+    }
+
+    /// A copy of every entry of `ifd` except `skip`. Entries pointing to
+    /// other directories must be skipped, their offsets are only valid in the
+    /// source file.
+    pub fn copy_ifd(ifd: &Ifd, buffer: &[u8], skip: &[IfdEntryTag]) -> TiffDirectory {
+        let mut directory = TiffDirectory::new();
+        for entry in &ifd.entries {
+            if skip.contains(&entry.tag) {
+                continue;
+            }
+            directory.copy_entry(ifd, buffer, entry.tag);
+        }
+        directory
+    }
+
+    /// The IFD0 metadata of `nef_file` for a derived image: the descriptive
+    /// tags and copies of the EXIF and GPS IFDs. The EXIF copy keeps the
+    /// MakerNote, whose offsets are relative to its own header.
+    pub fn from_nef(nef_file: &NefFile) -> TiffDirectory {
+        let buffer = nef_file.buffer();
+        let mut directory = TiffDirectory::new();
+        if let Some(ifd0) = nef_file.ifds.first() {
+            for tag in DESCRIPTIVE_TAGS {
+                directory.copy_entry(ifd0, buffer, tag);
+            }
+        }
+        if let Some(exif_ifd) = nef_file.exif_ifd() {
+            let skip = [IfdEntryTag::InteropIFDPointer];
+            let exif = TiffDirectory::copy_ifd(exif_ifd, buffer, &skip);
+            directory.set_directories(IfdEntryTag::ExifIFDPointer, vec![exif]);
+        }
+        if let Some(gps_ifd) = nef_file.gps_ifd() {
+            let gps = TiffDirectory::copy_ifd(gps_ifd, buffer, &[]);
+            directory.set_directories(IfdEntryTag::GPSInfo, vec![gps]);
+        }
+        directory
+    }
+
+    /// Adds `image` as uncompressed strips. Gray and RGB images are stored
+    /// as they are, with 16-bit samples in the byte order the file is
+    /// written in; other layouts are converted to 16-bit RGB.
+    pub fn set_image(&mut self, image: &DynamicImage) {
+        let (samples_per_pixel, bits) = match image {
+            DynamicImage::ImageLuma8(_) => (1, 8),
+            DynamicImage::ImageRgb8(_) => (3, 8),
+            DynamicImage::ImageLuma16(_) => (1, 16),
+            _ => (3, 16),
+        };
+        let (width, height) = (image.width(), image.height());
+        let photometric = if samples_per_pixel == 1 { 1 } else { 2 };
+        let strip_len = (width as usize * samples_per_pixel as usize).max(1) * ROWS_PER_STRIP;
+
+        self.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![0]));
+        self.set(IfdEntryTag::ImageWidth, IfdValue::Longs(vec![width]));
+        self.set(IfdEntryTag::ImageLength, IfdValue::Longs(vec![height]));
+        self.set(
+            IfdEntryTag::BitsPerSample,
+            IfdValue::Shorts(vec![bits; samples_per_pixel as usize]),
+        );
+        self.set(IfdEntryTag::Compression, IfdValue::Shorts(vec![1]));
+        self.set(
+            IfdEntryTag::PhotometricInterpretation,
+            IfdValue::Shorts(vec![photometric]),
+        );
+        self.set(
+            IfdEntryTag::SamplesPerPixel,
+            IfdValue::Shorts(vec![samples_per_pixel]),
+        );
+        self.set(
+            IfdEntryTag::RowsPerStrip,
+            IfdValue::Longs(vec![ROWS_PER_STRIP as u32]),
+        );
+        self.set(IfdEntryTag::PlanarConfiguration, IfdValue::Shorts(vec![1]));
+
+        let (offsets, counts) = (IfdEntryTag::StripOffsets, IfdEntryTag::StripByteCounts);
+        match image {
+            DynamicImage::ImageLuma8(img) => {
+                self.set_blocks(offsets, counts, split(img.as_raw(), strip_len))
+            }
+            DynamicImage::ImageRgb8(img) => {
+                self.set_blocks(offsets, counts, split(img.as_raw(), strip_len))
+            }
+            DynamicImage::ImageLuma16(img) => {
+                self.set_sample_blocks(offsets, counts, split(img.as_raw(), strip_len))
+            }
+            DynamicImage::ImageRgb16(img) => {
+                self.set_sample_blocks(offsets, counts, split(img.as_raw(), strip_len))
+            }
+            _ => {
+                self.set_sample_blocks(offsets, counts, split(image.to_rgb16().as_raw(), strip_len))
+            }
+        }
+    }
+
+    /// Sets the Orientation tag, or removes it for `None`.
+    pub fn set_orientation(&mut self, orientation: Option<Orientation>) {
+        match orientation {
+            Some(orientation) => self.set(
+                IfdEntryTag::Orientation,
+                IfdValue::Shorts(vec![orientation.to_exif() as u16]),
+            ),
+            None => self.remove(IfdEntryTag::Orientation),
+        }
+    }
+
+    /// The directory as a little-endian TIFF file with this IFD as IFD0.
+    pub fn to_tiff(&self) -> Result<Vec<u8>, NefError> {
+        self.to_tiff_endian(Endian::Little)
+    }
+
+    /// The directory as a TIFF file in the byte order `endian`, with this
+    /// IFD as IFD0. Offsets are relative to the TIFF header, so the result
+    /// can also be embedded, e.g. as a Nikon MakerNote.
+    pub fn to_tiff_endian(&self, endian: Endian) -> Result<Vec<u8>, NefError> {
+        let mut out = Vec::new();
+        out.extend_from_slice(match endian {
+            Endian::Little => b"II",
+            Endian::Big => b"MM",
+        });
+        out.extend_from_slice(&ordered(42_u16.to_le_bytes(), endian));
+        out.extend_from_slice(&ordered(8_u32.to_le_bytes(), endian));
+        self.write(&mut out, endian)?;
+        Ok(out)
+    }
+
+    /// Appends the directory and everything it points to, and returns the
+    /// directory's offset.
+    fn write(&self, out: &mut Vec<u8>, endian: Endian) -> Result<u32, NefError> {
+        let order = |bytes| ordered(bytes, endian);
+        align(out);
+        let start = out.len();
+        out.resize(start + 2 + self.fields.len() * 12 + 4, 0);
+        out[start..start + 2]
+            .copy_from_slice(&ordered((self.fields.len() as u16).to_le_bytes(), endian));
+
+        for (index, (&tag, field)) in self.fields.iter().enumerate() {
+            let (data_type, count, bytes) = match field {
+                TiffField::Value(value) => {
+                    let (data_type, bytes) = encode_value(value, endian);
+                    let count = match value {
+                        IfdValue::Ascii(_) => bytes.len(),
+                        _ => value.len(),
+                    };
+                    (data_type, count, bytes)
+                }
+                TiffField::Blocks(blocks) => (4, blocks.len(), write_blocks(out, blocks, endian)?),
+                TiffField::Samples(blocks) => {
+                    let blocks: Vec<Vec<u8>> = blocks
+                        .iter()
+                        .map(|block| ordered_all(block, endian, |v| v.to_le_bytes()))
+                        .collect();
+                    (4, blocks.len(), write_blocks(out, &blocks, endian)?)
+                }
+                TiffField::Directories(directories) => {
+                    let mut offsets = Vec::with_capacity(directories.len() * 4);
+                    for directory in directories {
+                        offsets
+                            .extend_from_slice(&order(directory.write(out, endian)?.to_le_bytes()));
+                    }
+                    (4, directories.len(), offsets)
+                }
+            };
+
+            let mut entry = [0_u8; 12];
+            entry[0..2].copy_from_slice(&ordered(tag.to_le_bytes(), endian));
+            entry[2..4].copy_from_slice(&ordered(data_type.to_le_bytes(), endian));
+            entry[4..8].copy_from_slice(&order((count as u32).to_le_bytes()));
+            if bytes.len() <= 4 {
+                entry[8..8 + bytes.len()].copy_from_slice(&bytes);
+            } else {
+                align(out);
+                entry[8..12].copy_from_slice(&order(file_offset(out.len())?.to_le_bytes()));
+                out.extend_from_slice(&bytes);
+            }
+            let position = start + 2 + index * 12;
+            out[position..position + 12].copy_from_slice(&entry);
+        }
+        file_offset(start)
+    }
+}
+
+/// The TIFF type code and bytes in the order `endian` of `value`. ASCII
+/// values get their terminating NUL.
+fn encode_value(value: &IfdValue, endian: Endian) -> (u16, Vec<u8>) {
+    let flat = |bytes: &mut dyn Iterator<Item = [u8; 4]>| -> Vec<u8> {
+        bytes.flat_map(|bytes| ordered(bytes, endian)).collect()
+    };
+    match value {
+        IfdValue::Bytes(values) => (1, values.clone()),
+        IfdValue::Ascii(text) => {
+            let mut bytes = text.as_bytes().to_vec();
+            bytes.push(0);
+            (2, bytes)
+        }
+        IfdValue::Shorts(values) => (3, ordered_all(values, endian, |v| v.to_le_bytes())),
+        IfdValue::Longs(values) => (4, ordered_all(values, endian, |v| v.to_le_bytes())),
+        IfdValue::Rationals(values) => (
+            5,
+            flat(
+                &mut values
+                    .iter()
+                    .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()]),
+            ),
+        ),
+        IfdValue::SBytes(values) => (6, values.iter().map(|&v| v as u8).collect()),
+        IfdValue::Undefined(values) => (7, values.clone()),
+        IfdValue::SShorts(values) => (8, ordered_all(values, endian, |v| v.to_le_bytes())),
+        IfdValue::SLongs(values) => (9, ordered_all(values, endian, |v| v.to_le_bytes())),
+        IfdValue::SRationals(values) => (
+            10,
+            flat(
+                &mut values
+                    .iter()
+                    .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()]),
+            ),
+        ),
+        IfdValue::Floats(values) => (11, ordered_all(values, endian, |v| v.to_le_bytes())),
+        IfdValue::Doubles(values) => (12, ordered_all(values, endian, |v| v.to_le_bytes())),
+    }
+}
+
+/// The little-endian `bytes` of a number in the byte order `endian`.
+fn ordered<const N: usize>(mut bytes: [u8; N], endian: Endian) -> [u8; N] {
+    if endian == Endian::Big {
+        bytes.reverse();
+    }
+    bytes
+}
+
+/// The bytes of every value in the byte order `endian`.
+fn ordered_all<T, const N: usize>(
+    values: &[T],
+    endian: Endian,
+    bytes: impl Fn(&T) -> [u8; N],
+) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| ordered(bytes(value), endian))
+        .collect()
+}
+
+/// Appends each block at a word boundary and returns their offsets in the
+/// byte order `endian`.
+fn write_blocks(
+    out: &mut Vec<u8>,
+    blocks: &[Vec<u8>],
+    endian: Endian,
+) -> Result<Vec<u8>, NefError> {
+    let mut offsets = Vec::with_capacity(blocks.len() * 4);
+    for block in blocks {
+        align(out);
+        offsets.extend_from_slice(&ordered(file_offset(out.len())?.to_le_bytes(), endian));
+        out.extend_from_slice(block);
+    }
+    Ok(offsets)
+}
+
+/// `data` in blocks of `len` values, the last one possibly shorter.
+fn split<T: Clone>(data: &[T], len: usize) -> Vec<Vec<T>> {
+    data.chunks(len).map(<[T]>::to_vec).collect()
+}
+
+/// Pads `out` to the word boundary TIFF offsets should fall on.
+fn align(out: &mut Vec<u8>) {
+    if out.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// `position` as a TIFF offset, which must fit into 32 bits.
+fn file_offset(position: usize) -> Result<u32, NefError> {
+    u32::try_from(position).map_err(|_| NefError::OutOfBounds {
+        offset: position,
+        len: 0,
+    })
+}
+
+/// `image` as a TIFF with the metadata of `nef_file` and the given
+/// Orientation tag. 16-bit samples keep their exact values.
+pub fn nef_tiff(
+    nef_file: &NefFile,
+    image: &DynamicImage,
+    orientation: Option<Orientation>,
+) -> Result<Vec<u8>, NefError> {
+    let mut directory = TiffDirectory::from_nef(nef_file);
+    directory.set_image(image);
+    directory.set_orientation(orientation);
+    directory.to_tiff()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ifd::IfdRole;
+    use image::ImageBuffer;
+
+    /// IFD0 at 8 with ImageWidth LONG 7, Make "NIKON" out of line at 50 and
+    /// Orientation SHORT 6, and no next IFD.
+    const LITTLE_ENDIAN: [u8; 56] = [
+        b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00, // header
+        0x03, 0x00, // entry count
+        0x00, 0x01, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, // width
+        0x0F, 0x01, 0x02, 0x00, 0x06, 0x00, 0x00, 0x00, 0x32, 0x00, 0x00, 0x00, // make
+        0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, // orientation
+        0x00, 0x00, 0x00, 0x00, // next IFD
+        b'N', b'I', b'K', b'O', b'N', 0x00, // make
+    ];
+
+    /// The same file in big-endian order.
+    const BIG_ENDIAN: [u8; 56] = [
+        b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08, // header
+        0x00, 0x03, // entry count
+        0x01, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x07, // width
+        0x01, 0x0F, 0x00, 0x02, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00, 0x32, // make
+        0x01, 0x12, 0x00, 0x03, 0x00, 0x00, 0x00, 0x01, 0x00, 0x06, 0x00, 0x00, // orientation
+        0x00, 0x00, 0x00, 0x00, // next IFD
+        b'N', b'I', b'K', b'O', b'N', 0x00, // make
+    ];
+
+    fn directory() -> TiffDirectory {
+        let mut directory = TiffDirectory::new();
+        // Set out of order, written in ascending tag order
+        directory.set_orientation(Some(Orientation::Rotate90));
+        directory.set(IfdEntryTag::Make, IfdValue::Ascii("NIKON".to_string()));
+        directory.set(IfdEntryTag::ImageWidth, IfdValue::Longs(vec![7]));
+        directory
+    }
+
+    #[test]
+    fn writes_both_byte_orders() {
+        assert_eq!(directory().to_tiff().unwrap(), LITTLE_ENDIAN);
+        assert_eq!(directory().to_tiff_endian(Endian::Big).unwrap(), BIG_ENDIAN);
+    }
+
+    #[test]
+    fn sixteen_bit_samples_follow_the_byte_order() {
+        let samples = vec![0x1234, 0xABCD, 0x0001, 0xFF00];
+        let gray = ImageBuffer::from_raw(2, 2, samples).unwrap();
+        let image = DynamicImage::ImageLuma16(gray);
+        let mut directory = TiffDirectory::new();
+        directory.set_image(&image);
+
+        for (endian, expected) in [
+            (
+                Endian::Big,
+                [0x12, 0x34, 0xAB, 0xCD, 0x00, 0x01, 0xFF, 0x00],
+            ),
+            (
+                Endian::Little,
+                [0x34, 0x12, 0xCD, 0xAB, 0x01, 0x00, 0x00, 0xFF],
+            ),
+        ] {
+            let tiff = directory.to_tiff_endian(endian).unwrap();
+            let ifds = Ifd::parse_ifd(&tiff, 0).unwrap();
+            let offset = ifds[0]
+                .get_entry(IfdEntryTag::StripOffsets)
+                .unwrap()
+                .get_data_or_offset();
+            assert_eq!(tiff[offset..offset + 8], expected, "{endian:?}");
+
+            let decoded = image::load_from_memory(&tiff).unwrap();
+            assert_eq!(decoded, image, "{endian:?}");
+        }
+    }
+
+    #[test]
+    fn blocks_and_sub_directories_read_back() {
+        for endian in [Endian::Big, Endian::Little] {
+            let mut exif = TiffDirectory::new();
+            exif.set(IfdEntryTag::FNumber, IfdValue::Rationals(vec![(56, 10)]));
+            let mut directory = directory();
+            directory.set_blocks(
+                IfdEntryTag::StripOffsets,
+                IfdEntryTag::StripByteCounts,
+                vec![vec![1, 2, 3], vec![4, 5]],
+            );
+            directory.set_directories(IfdEntryTag::ExifIFDPointer, vec![exif]);
+            let tiff = directory.to_tiff_endian(endian).unwrap();
+
+            let ifds = Ifd::parse_ifd(&tiff, 0).unwrap();
+            assert_eq!(ifds.len(), 2, "{endian:?}");
+            assert_eq!(ifds[1].role, IfdRole::Exif);
+            let value = |ifd: &Ifd, tag| ifd.get_entry(tag).unwrap().get_value(&tiff).unwrap();
+            assert_eq!(
+                value(&ifds[1], IfdEntryTag::FNumber),
+                IfdValue::Rationals(vec![(56, 10)])
+            );
+            assert_eq!(
+                value(&ifds[0], IfdEntryTag::Make),
+                IfdValue::Ascii("NIKON".to_string())
+            );
+            let IfdValue::Longs(offsets) = value(&ifds[0], IfdEntryTag::StripOffsets) else {
+                panic!("{endian:?}: StripOffsets is not LONG");
+            };
+            let strip = |index: usize, len: usize| {
+                let start = offsets[index] as usize;
+                assert_eq!(start % 2, 0, "{endian:?}: unaligned strip");
+                &tiff[start..start + len]
+            };
+            assert_eq!((strip(0, 3), strip(1, 2)), (&[1, 2, 3][..], &[4, 5][..]));
+            assert_eq!(
+                value(&ifds[0], IfdEntryTag::StripByteCounts),
+                IfdValue::Longs(vec![3, 2])
+            );
+        }
+    }
+
+    #[test]
+    fn removes_the_orientation() {
+        let mut directory = directory();
+        directory.set_orientation(None);
+        assert!(!directory.contains(IfdEntryTag::Orientation));
+    }
+}
@@ -1,71 +1,175 @@
-use std::sync::Arc;
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
 
-use byteorder::{LittleEndian, ReadBytesExt};
+use crate::error::NefError;
 
-pub fn bytes_to_num(bytes: &[u8]) -> usize {
-    let mut template: [u8; 4] = [0, 0, 0, 0];
-    template[..bytes.len()].copy_from_slice(bytes);
-    let mut cursor = std::io::Cursor::new(&template);
-    let num = cursor.read_u32::<LittleEndian>().unwrap() as usize;
-    num
+/// Byte order of a TIFF structure, as announced by its `II` / `MM` header.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Endian {
+    #[default]
+    Little,
+    Big,
 }
 
-pub fn read_leu8(buffer: &[u8], pointer: &mut usize, peek: bool) -> u8 {
-    let int = buffer[*pointer];
+impl Endian {
+    /// Detects the byte order from a TIFF header (`II*\0` or `MM\0*`).
+    pub fn from_tiff_header(header: &[u8]) -> Option<Endian> {
+        match header.get(0..4)? {
+            [0x49, 0x49, 0x2A, 0x00] => Some(Endian::Little),
+            [0x4D, 0x4D, 0x00, 0x2A] => Some(Endian::Big),
+            _ => None,
+        }
+    }
+
+    pub fn u16(&self, bytes: &[u8]) -> u16 {
+        match self {
+            Endian::Little => LittleEndian::read_u16(bytes),
+            Endian::Big => BigEndian::read_u16(bytes),
+        }
+    }
+
+    pub fn u32(&self, bytes: &[u8]) -> u32 {
+        match self {
+            Endian::Little => LittleEndian::read_u32(bytes),
+            Endian::Big => BigEndian::read_u32(bytes),
+        }
+    }
+
+    pub fn u64(&self, bytes: &[u8]) -> u64 {
+        match self {
+            Endian::Little => LittleEndian::read_u64(bytes),
+            Endian::Big => BigEndian::read_u64(bytes),
+        }
+    }
+
+    /// Appends `value` in this byte order.
+    pub fn write_u16(&self, out: &mut Vec<u8>, value: u16) {
+        match self {
+            Endian::Little => out.extend_from_slice(&value.to_le_bytes()),
+            Endian::Big => out.extend_from_slice(&value.to_be_bytes()),
+        }
+    }
+
+    pub fn read_u16(
+        &self,
+        buffer: &[u8],
+        pointer: &mut usize,
+        peek: bool,
+    ) -> Result<u16, NefError> {
+        match self {
+            Endian::Little => read_leu16(buffer, pointer, peek),
+            Endian::Big => read_beu16(buffer, pointer, peek),
+        }
+    }
+
+    pub fn read_u32(
+        &self,
+        buffer: &[u8],
+        pointer: &mut usize,
+        peek: bool,
+    ) -> Result<u32, NefError> {
+        match self {
+            Endian::Little => read_leu32(buffer, pointer, peek),
+            Endian::Big => read_beu32(buffer, pointer, peek),
+        }
+    }
+}
+
+/// Interprets up to four bytes as an unsigned number in the given byte order.
+pub fn bytes_to_num(bytes: &[u8], endian: Endian) -> usize {
+    match endian {
+        Endian::Little => bytes
+            .iter()
+            .rev()
+            .fold(0, |num, &byte| (num << 8) | byte as usize),
+        Endian::Big => bytes
+            .iter()
+            .fold(0, |num, &byte| (num << 8) | byte as usize),
+    }
+}
+
+/// Reads the width and height from the frame header of a JPEG stream.
+/// Returns `None` if the data does not start with an SOI marker or no frame
+/// header is found.
+pub fn jpeg_dimensions(jpeg: &[u8]) -> Option<(usize, usize)> {
+    if jpeg.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        let marker = jpeg.get(pos..pos + 4)?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+        let segment_length = BigEndian::read_u16(&marker[2..4]) as usize;
+        match marker[1] {
+            // Fill bytes before a marker
+            0xFF => pos += 1,
+            // Start of frame markers, excluding DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if !matches!(marker[1], 0xC4 | 0xC8 | 0xCC) => {
+                let frame = jpeg.get(pos + 5..pos + 9)?;
+                let height = BigEndian::read_u16(&frame[0..2]) as usize;
+                let width = BigEndian::read_u16(&frame[2..4]) as usize;
+                return Some((width, height));
+            }
+            // Start of scan or end of image without a frame header
+            0xDA | 0xD9 => return None,
+            _ => pos += 2 + segment_length,
+        }
+    }
+}
+
+/// Returns `len` bytes at `offset`, or `OutOfBounds` if they are not all in `buffer`.
+pub fn checked_slice(buffer: &[u8], offset: usize, len: usize) -> Result<&[u8], NefError> {
+    offset
+        .checked_add(len)
+        .and_then(|end| buffer.get(offset..end))
+        .ok_or(NefError::OutOfBounds { offset, len })
+}
+
+pub fn read_leu8(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u8, NefError> {
+    let int = checked_slice(buffer, *pointer, 1)?[0];
     if !peek {
         *pointer += 1;
     }
-    int
+    Ok(int)
 }
 
-pub fn read_leu16(buffer: &[u8], pointer: &mut usize, peek: bool) -> u16 {
-    let mut template: [u8; 2] = [0, 0];
-    template.copy_from_slice(&buffer[*pointer..*pointer + 2]);
-    let mut cursor = std::io::Cursor::new(&template);
-    let int = cursor.read_u16::<LittleEndian>().unwrap();
+pub fn read_leu16(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u16, NefError> {
+    let int = LittleEndian::read_u16(checked_slice(buffer, *pointer, 2)?);
     if !peek {
         *pointer += 2;
     }
-    int
+    Ok(int)
 }
 
-pub fn read_leu32(buffer: &[u8], pointer: &mut usize, peek: bool) -> u32 {
-    let mut template: [u8; 4] = [0, 0, 0, 0];
-    template.copy_from_slice(&buffer[*pointer..*pointer + 4]);
-    let mut cursor = std::io::Cursor::new(&template);
-    let int = cursor.read_u32::<LittleEndian>().unwrap();
+pub fn read_leu32(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u32, NefError> {
+    let int = LittleEndian::read_u32(checked_slice(buffer, *pointer, 4)?);
     if !peek {
         *pointer += 4;
     }
-    int
+    Ok(int)
 }
 
-pub fn read_beu8(buffer: &[u8], pointer: &mut usize, peek: bool) -> u8 {
-    let int = buffer[*pointer];
+pub fn read_beu8(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u8, NefError> {
+    let int = checked_slice(buffer, *pointer, 1)?[0];
     if !peek {
         *pointer += 1;
     }
-    int
+    Ok(int)
 }
 
-pub fn read_beu16(buffer: &[u8], pointer: &mut usize, peek: bool) -> u16 {
-    let mut template: [u8; 2] = [0, 0];
-    template.copy_from_slice(&buffer[*pointer..*pointer + 2]);
-    let mut cursor = std::io::Cursor::new(&template);
-    let int = cursor.read_u16::<byteorder::BigEndian>().unwrap();
+pub fn read_beu16(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u16, NefError> {
+    let int = BigEndian::read_u16(checked_slice(buffer, *pointer, 2)?);
     if !peek {
         *pointer += 2;
     }
-    int
+    Ok(int)
 }
 
-pub fn read_beu32(buffer: &[u8], pointer: &mut usize, peek: bool) -> u32 {
-    let mut template: [u8; 4] = [0, 0, 0, 0];
-    template.copy_from_slice(&buffer[*pointer..*pointer + 4]);
-    let mut cursor = std::io::Cursor::new(&template);
-    let int = cursor.read_u32::<byteorder::BigEndian>().unwrap();
+pub fn read_beu32(buffer: &[u8], pointer: &mut usize, peek: bool) -> Result<u32, NefError> {
+    let int = BigEndian::read_u32(checked_slice(buffer, *pointer, 4)?);
     if !peek {
         *pointer += 4;
     }
-    int
+    Ok(int)
 }
@@ -0,0 +1,61 @@
+use crate::raw_image::RawImage;
+
+/// Which multipliers the white balance stage uses.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum WhiteBalance {
+    /// The multipliers the camera recorded for the shot.
+    #[default]
+    AsShot,
+    /// Neutral for D65 daylight, derived from the camera's color matrix.
+    /// [`DAYLIGHT`] for bodies not in the database.
+    Daylight,
+    /// Explicit R, G, B multipliers.
+    Custom([f32; 3]),
+}
+
+/// Daylight multipliers of the D7500, close to those of other current Nikon
+/// bodies.
+pub const DAYLIGHT: [f32; 3] = [2.62, 1.0, 1.39];
+
+/// Multiplies every CFA sample by the multiplier of its color. `cfa` gives
+/// the color (0 red, 1 green, 2 blue) of each position of the 2x2 tile in
+/// row-major order. The multipliers are scaled so the smallest is 1.0.
+/// Results are not clipped: values above 1.0 are left for the highlight
+/// stage, which needs them to recover colors in partly clipped pixels.
+pub fn apply_white_balance(data: &mut [f32], width: usize, cfa: [u8; 4], multipliers: [f32; 3]) {
+    let usable = multipliers.iter().all(|m| m.is_finite() && *m > 0.0);
+    if !usable || width == 0 {
+        return;
+    }
+    let min = multipliers.iter().copied().fold(f32::INFINITY, f32::min);
+    let scales = cfa.map(|color| multipliers[color.min(2) as usize] / min);
+
+    for (row, line) in data.chunks_exact_mut(width).enumerate() {
+        for (col, value) in line.iter_mut().enumerate() {
+            let scale = scales[RawImage::cfa_index(row, col)];
+            *value *= scale;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_each_color_without_clipping() {
+        // RGGB, 4x2
+        let mut data = [0.5, 0.5, 0.25, 0.5, 0.5, 0.8, 0.5, 0.8];
+        apply_white_balance(&mut data, 4, [0, 1, 1, 2], [4.0, 2.0, 3.0]);
+        assert_eq!(data, [1.0, 0.5, 0.5, 0.5, 0.5, 1.2, 0.5, 1.2]);
+    }
+
+    #[test]
+    fn ignores_unusable_multipliers() {
+        for multipliers in [[0.0, 1.0, 1.0], [f32::NAN, 1.0, 1.0], [-1.0, 1.0, 1.0]] {
+            let mut data = [0.5; 4];
+            apply_white_balance(&mut data, 2, [0, 1, 1, 2], multipliers);
+            assert_eq!(data, [0.5; 4], "{multipliers:?}");
+        }
+    }
+}
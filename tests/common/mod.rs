@@ -0,0 +1,486 @@
+//! Small synthetic NEFs with the structure of camera files: IFD0 with an RGB
+//! thumbnail, SubIFDs holding a JPEG preview and the raw CFA image, an EXIF
+//! IFD and a Nikon MakerNote with its own TIFF header and, for compressed
+//! data, a NEFLinearizationTable.
+
+// Each test crate uses a different part of the fixtures
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageBuffer, Rgb};
+use read_nef::ifd::{IfdEntryTag, IfdValue};
+use read_nef::linearization::LinearizationTable;
+use read_nef::makernote::NikonTag;
+use read_nef::nef::{NefFile, encode_nikon};
+use read_nef::tiff_writer::TiffDirectory;
+use read_nef::utils::Endian;
+
+pub const MAKE: &str = "NIKON CORPORATION";
+pub const MODEL: &str = "NIKON D7500";
+pub const SERIAL_NUMBER: &str = "3012345";
+/// Black level of every channel on the 14-bit scale of the MakerNote.
+pub const BLACK_LEVEL: u16 = 400;
+pub const WB_RB_LEVELS: [(u32, u32); 2] = [(1875, 1000), (1375, 1000)];
+pub const PREVIEW_SIZE: (u32, u32) = (32, 24);
+pub const THUMBNAIL_SIZE: (u32, u32) = (16, 12);
+
+/// How the raw strip is stored.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RawCompression {
+    /// Every sample in its own 16-bit word.
+    Uncompressed,
+    /// Samples packed back to back, LSB first in little-endian files.
+    Packed,
+    /// Nikon compressed with the lossless trees (NEFLinearizationTable 0x46).
+    Lossless,
+    /// Nikon compressed with the lossy trees and a full curve (0x44 0x10).
+    Lossy,
+    /// Nikon compressed with a sparse curve and a second tree from the middle
+    /// row on (0x44 0x20).
+    LossyAfterSplit,
+}
+
+impl RawCompression {
+    pub const ALL: [RawCompression; 5] = [
+        RawCompression::Uncompressed,
+        RawCompression::Packed,
+        RawCompression::Lossless,
+        RawCompression::Lossy,
+        RawCompression::LossyAfterSplit,
+    ];
+
+    pub fn is_nikon_compressed(self) -> bool {
+        !matches!(self, RawCompression::Uncompressed | RawCompression::Packed)
+    }
+}
+
+/// The description of a synthetic NEF. `samples` holds the values before
+/// the linearization curve; for lossy files these are indices into it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticNef {
+    pub width: usize,
+    pub height: usize,
+    pub bps: u16,
+    pub compression: RawCompression,
+    pub endian: Endian,
+    pub model: &'static str,
+    pub samples: Vec<u16>,
+}
+
+impl SyntheticNef {
+    /// A 64 x 48 big-endian NEF, as Nikon bodies write them, with
+    /// pseudo-random samples suiting `compression`.
+    pub fn new(bps: u16, compression: RawCompression) -> SyntheticNef {
+        SyntheticNef {
+            width: 0,
+            height: 0,
+            bps,
+            compression,
+            endian: Endian::Big,
+            model: MODEL,
+            samples: Vec::new(),
+        }
+        .size(64, 48)
+    }
+
+    pub fn endian(mut self, endian: Endian) -> SyntheticNef {
+        self.endian = endian;
+        self
+    }
+
+    pub fn model(mut self, model: &'static str) -> SyntheticNef {
+        self.model = model;
+        self
+    }
+
+    /// Changes the size and generates new samples for it.
+    pub fn size(mut self, width: usize, height: usize) -> SyntheticNef {
+        self.width = width;
+        self.height = height;
+        self.samples = self.generate_samples();
+        self
+    }
+
+    pub fn samples(mut self, samples: Vec<u16>) -> SyntheticNef {
+        self.samples = samples;
+        self
+    }
+
+    /// Noise over the whole range the compression can store. After the split
+    /// the lossy trees only encode small differences exactly, so those files
+    /// get a gentle wave with a little noise instead.
+    fn generate_samples(&self) -> Vec<u16> {
+        let mut random = Random(0x2545_F491 ^ (self.bps as u32) << 8 ^ self.width as u32);
+        let limit = match self.compression {
+            RawCompression::Lossy => curve_size(self.bps) as u32,
+            _ => 1 << self.bps,
+        };
+        (0..self.width * self.height)
+            .map(|index| {
+                if self.compression != RawCompression::LossyAfterSplit {
+                    return (random.next() % limit) as u16;
+                }
+                let (row, col) = (index / self.width, index % self.width);
+                let phase = (row * 3 + col * 2) % 256;
+                let wave = if phase < 128 { phase } else { 256 - phase };
+                (limit / 4 + wave as u32 * 4 + random.next() % 8) as u16
+            })
+            .collect()
+    }
+
+    /// The linearization table stored in the MakerNote, `None` for
+    /// uncompressed files. The initial predictors are the top left samples.
+    pub fn linearization_table(&self) -> Option<LinearizationTable> {
+        let below = if self.height > 1 { self.width } else { 0 };
+        let predictors = [
+            [self.samples[0], self.samples[below]],
+            [self.samples[1], self.samples[below + 1]],
+        ];
+        let lossy = |version, points: Vec<u16>, split_row| {
+            let bit_depth = points
+                .iter()
+                .max()
+                .map(|&max| u16::BITS - max.leading_zeros());
+            LinearizationTable {
+                version,
+                predictors,
+                points,
+                split_row,
+                bit_depth,
+            }
+        };
+        match self.compression {
+            RawCompression::Uncompressed | RawCompression::Packed => None,
+            RawCompression::Lossless => Some(LinearizationTable::lossless(predictors)),
+            RawCompression::Lossy => Some(lossy(
+                (0x44, 0x10),
+                curve_points(curve_size(self.bps), self.bps),
+                None,
+            )),
+            // 257 points, one every 2^bps / 256 values
+            RawCompression::LossyAfterSplit => Some(lossy(
+                (0x44, 0x20),
+                curve_points(257, self.bps),
+                Some(self.height / 2),
+            )),
+        }
+    }
+
+    /// The raw strip as stored in the file.
+    pub fn strip(&self) -> Vec<u8> {
+        match self.compression {
+            RawCompression::Uncompressed => {
+                let mut strip = Vec::with_capacity(self.samples.len() * 2);
+                for &sample in &self.samples {
+                    self.endian.write_u16(&mut strip, sample);
+                }
+                strip
+            }
+            RawCompression::Packed => self
+                .samples
+                .chunks_exact(self.width)
+                .flat_map(|line| pack(line, self.bps as u32, self.endian))
+                .collect(),
+            _ => {
+                let table = self
+                    .linearization_table()
+                    .expect("compressed files have a table");
+                encode_nikon(&self.samples, self.width, self.height, self.bps, &table)
+                    .expect("samples suit the compression")
+            }
+        }
+    }
+
+    /// The NEFCompression MakerNote value.
+    fn nef_compression(&self) -> u16 {
+        match self.compression {
+            RawCompression::Lossy => 1,
+            RawCompression::Uncompressed => 2,
+            RawCompression::Lossless => 3,
+            RawCompression::LossyAfterSplit => 4,
+            RawCompression::Packed if self.bps == 12 => 9,
+            RawCompression::Packed => 10,
+        }
+    }
+
+    /// The complete NEF file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut raw = TiffDirectory::new();
+        let compression = if self.compression.is_nikon_compressed() {
+            34713
+        } else {
+            1
+        };
+        raw.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![0]));
+        raw.set(
+            IfdEntryTag::ImageWidth,
+            IfdValue::Longs(vec![self.width as u32]),
+        );
+        raw.set(
+            IfdEntryTag::ImageLength,
+            IfdValue::Longs(vec![self.height as u32]),
+        );
+        raw.set(IfdEntryTag::BitsPerSample, IfdValue::Shorts(vec![self.bps]));
+        raw.set(
+            IfdEntryTag::Compression,
+            IfdValue::Shorts(vec![compression]),
+        );
+        raw.set(
+            IfdEntryTag::PhotometricInterpretation,
+            IfdValue::Shorts(vec![32803]),
+        );
+        raw.set(IfdEntryTag::SamplesPerPixel, IfdValue::Shorts(vec![1]));
+        raw.set(
+            IfdEntryTag::RowsPerStrip,
+            IfdValue::Longs(vec![self.height as u32]),
+        );
+        raw.set(IfdEntryTag::PlanarConfiguration, IfdValue::Shorts(vec![1]));
+        raw.set(
+            IfdEntryTag::CFARepeatPatternDim,
+            IfdValue::Shorts(vec![2, 2]),
+        );
+        raw.set(IfdEntryTag::CFAPattern, IfdValue::Bytes(vec![0, 1, 1, 2]));
+        raw.set_blocks(
+            IfdEntryTag::StripOffsets,
+            IfdEntryTag::StripByteCounts,
+            vec![self.strip()],
+        );
+
+        let mut preview = TiffDirectory::new();
+        preview.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![1]));
+        preview.set(IfdEntryTag::Compression, IfdValue::Shorts(vec![6]));
+        preview.set_blocks(
+            IfdEntryTag::JpgFromRawStart,
+            IfdEntryTag::JpgFromRawLength,
+            vec![preview_jpeg()],
+        );
+
+        let mut exif = TiffDirectory::new();
+        exif.set(
+            IfdEntryTag::ExposureTime,
+            IfdValue::Rationals(vec![(1, 250)]),
+        );
+        exif.set(IfdEntryTag::FNumber, IfdValue::Rationals(vec![(56, 10)]));
+        exif.set(IfdEntryTag::ISOSpeedRatings, IfdValue::Shorts(vec![200]));
+        exif.set(
+            IfdEntryTag::DateTimeOriginal,
+            IfdValue::Ascii("2024:05:01 12:30:45".to_string()),
+        );
+        exif.set(
+            IfdEntryTag::FocalLength,
+            IfdValue::Rationals(vec![(350, 10)]),
+        );
+        exif.set(
+            IfdEntryTag::MakerNote,
+            IfdValue::Undefined(self.maker_note()),
+        );
+
+        let mut ifd0 = TiffDirectory::new();
+        ifd0.set_image(&thumbnail());
+        ifd0.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![1]));
+        ifd0.set(IfdEntryTag::Make, IfdValue::Ascii(MAKE.to_string()));
+        ifd0.set(IfdEntryTag::Model, IfdValue::Ascii(self.model.to_string()));
+        ifd0.set(IfdEntryTag::Orientation, IfdValue::Shorts(vec![1]));
+        ifd0.set(
+            IfdEntryTag::Software,
+            IfdValue::Ascii("Ver.1.00".to_string()),
+        );
+        ifd0.set_directories(IfdEntryTag::SubIFDS, vec![preview, raw]);
+        ifd0.set_directories(IfdEntryTag::ExifIFDPointer, vec![exif]);
+        ifd0.to_tiff_endian(self.endian)
+            .expect("fixtures are small")
+    }
+
+    /// The type 3 MakerNote: `Nikon\0`, version 2.10 and a TIFF structure
+    /// whose offsets are relative to its own header.
+    fn maker_note(&self) -> Vec<u8> {
+        let nikon = |tag: NikonTag| IfdEntryTag::Unknown(tag.u16_value() as usize);
+        let mut directory = TiffDirectory::new();
+        directory.set(
+            nikon(NikonTag::MakerNoteVersion),
+            IfdValue::Undefined(b"0211".to_vec()),
+        );
+        directory.set(nikon(NikonTag::ISO), IfdValue::Shorts(vec![0, 200]));
+        directory.set(nikon(NikonTag::Quality), IfdValue::Ascii("RAW".to_string()));
+        directory.set(
+            nikon(NikonTag::WhiteBalance),
+            IfdValue::Ascii("AUTO1".to_string()),
+        );
+        directory.set(
+            nikon(NikonTag::WBRBLevels),
+            IfdValue::Rationals(WB_RB_LEVELS.to_vec()),
+        );
+        directory.set(
+            nikon(NikonTag::SerialNumber),
+            IfdValue::Ascii(SERIAL_NUMBER.to_string()),
+        );
+        directory.set(
+            nikon(NikonTag::BlackLevel),
+            IfdValue::Shorts(vec![BLACK_LEVEL; 4]),
+        );
+        directory.set(
+            nikon(NikonTag::NEFCompression),
+            IfdValue::Shorts(vec![self.nef_compression()]),
+        );
+        // ContrastCurve, present in camera files and used by other decoders to
+        // find the MakerNote
+        directory.set(IfdEntryTag::Unknown(0x8C), IfdValue::Undefined(vec![0; 16]));
+        if let Some(table) = self.linearization_table() {
+            directory.set(
+                nikon(NikonTag::NEFLinearizationTable),
+                IfdValue::Undefined(table.to_bytes(self.endian)),
+            );
+        }
+        directory.set(nikon(NikonTag::ShutterCount), IfdValue::Longs(vec![12345]));
+
+        let mut maker_note = b"Nikon\0\x02\x10\0\0".to_vec();
+        maker_note.extend(
+            directory
+                .to_tiff_endian(self.endian)
+                .expect("fixtures are small"),
+        );
+        maker_note
+    }
+
+    /// Writes the NEF to a temporary file, removed when the fixture is
+    /// dropped.
+    pub fn write(&self, name: &str) -> Fixture {
+        Fixture::new(name, &self.to_bytes())
+    }
+}
+
+/// A file in the temporary directory that is removed on drop.
+pub struct Fixture {
+    pub path: PathBuf,
+}
+
+impl Fixture {
+    /// Writes `bytes` to a file named after `name` and the process, so test
+    /// binaries running side by side do not collide.
+    pub fn new(name: &str, bytes: &[u8]) -> Fixture {
+        let path = std::env::temp_dir().join(format!("read_nef_{}_{name}", std::process::id()));
+        std::fs::write(&path, bytes).expect("temporary directory is writable");
+        Fixture { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn open(&self) -> NefFile {
+        NefFile::open(&self.path).expect("fixture parses")
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Every combination of bit depth, compression and byte order the decoder
+/// supports.
+pub fn all_variants() -> Vec<SyntheticNef> {
+    let mut variants = Vec::new();
+    for compression in RawCompression::ALL {
+        for bps in [12, 14] {
+            for endian in [Endian::Big, Endian::Little] {
+                variants.push(SyntheticNef::new(bps, compression).endian(endian));
+            }
+        }
+    }
+    for endian in [Endian::Big, Endian::Little] {
+        variants.push(SyntheticNef::new(16, RawCompression::Uncompressed).endian(endian));
+    }
+    variants
+}
+
+/// A short name for file names and assertion messages.
+pub fn variant_name(nef: &SyntheticNef) -> String {
+    format!("{:?}_{}_{:?}", nef.compression, nef.bps, nef.endian)
+}
+
+/// Points of the lossy full curve: one per sample value that the Huffman
+/// stream stores, 1024 for 12-bit and 4096 for 14-bit files.
+fn curve_size(bps: u16) -> usize {
+    1 << (bps - 2)
+}
+
+/// `size` points rising from 0 to the top of `bps` bits, steeper towards the
+/// highlights like a camera curve.
+fn curve_points(size: usize, bps: u16) -> Vec<u16> {
+    let last = size as u64 - 1;
+    let max = (1_u64 << bps) - 1;
+    (0..size as u64)
+        .map(|i| (i + i * i * (max - last) / (last * last)) as u16)
+        .collect()
+}
+
+/// One row of samples packed back to back, MSB first for big-endian and LSB
+/// first for little-endian files, padded to whole bytes.
+fn pack(line: &[u16], bits: u32, endian: Endian) -> Vec<u8> {
+    let mut out = Vec::with_capacity((line.len() * bits as usize).div_ceil(8));
+    let mut acc: u64 = 0;
+    let mut nbits = 0;
+    for &value in line {
+        match endian {
+            Endian::Big => acc = (acc << bits) | value as u64,
+            Endian::Little => acc |= (value as u64) << nbits,
+        }
+        nbits += bits;
+        while nbits >= 8 {
+            nbits -= 8;
+            match endian {
+                Endian::Big => out.push((acc >> nbits) as u8),
+                Endian::Little => {
+                    out.push(acc as u8);
+                    acc >>= 8;
+                }
+            }
+        }
+        if endian == Endian::Big {
+            acc &= (1 << nbits) - 1;
+        }
+    }
+    if nbits > 0 {
+        match endian {
+            Endian::Big => out.push((acc << (8 - nbits)) as u8),
+            Endian::Little => out.push(acc as u8),
+        }
+    }
+    out
+}
+
+fn gradient(width: u32, height: u32) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
+    ImageBuffer::from_fn(width, height, |x, y| {
+        Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 128])
+    })
+}
+
+fn thumbnail() -> DynamicImage {
+    let (width, height) = THUMBNAIL_SIZE;
+    DynamicImage::ImageRgb8(gradient(width, height))
+}
+
+fn preview_jpeg() -> Vec<u8> {
+    let (width, height) = PREVIEW_SIZE;
+    let mut jpeg = Vec::new();
+    DynamicImage::ImageRgb8(gradient(width, height))
+        .write_with_encoder(JpegEncoder::new(&mut jpeg))
+        .expect("JPEG encoding into memory succeeds");
+    jpeg
+}
+
+/// A xorshift generator, so fixtures are the same on every run.
+struct Random(u32);
+
+impl Random {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::{BLACK_LEVEL, Fixture, RawCompression, SyntheticNef, all_variants, variant_name};
+use read_nef::error::NefError;
+use read_nef::huffmanv2::LosslessJpeg;
+use read_nef::ifd::{IfdEntryTag, IfdValue};
+use read_nef::linearization::LinearizationTable;
+use read_nef::nef::{LookupTable, decode_nikon, encode_nikon_lossless};
+use read_nef::tiff_writer::TiffDirectory;
+use read_nef::utils::Endian;
+
+/// Checks that each value lies in the range `LookupTable::dither` spreads
+/// the curve value of its sample over.
+fn assert_dithered(nef: &SyntheticNef, decoded: &[u16]) {
+    let table = nef.linearization_table().unwrap();
+    let curve = table.curve(nef.bps);
+    let lookup = LookupTable::new(&curve);
+    for (index, (&sample, &value)) in nef.samples.iter().zip(decoded).enumerate() {
+        let (center, base, delta) = lookup.table[(sample as usize).min(curve.len() - 1)];
+        assert!(
+            (base..=base + delta.div_ceil(2)).contains(&value),
+            "{} sample {index}: {value} is not a dithered {center}",
+            variant_name(nef)
+        );
+    }
+}
+
+#[test]
+fn decodes_every_variant() {
+    for nef in all_variants() {
+        let name = variant_name(&nef);
+        let fixture = nef.write(&format!("{name}.NEF"));
+        let decoded = fixture
+            .open()
+            .parse_raw_image_data()
+            .unwrap_or_else(|e| panic!("{name}: {e}"));
+        assert_eq!(decoded.len(), nef.width * nef.height, "{name}");
+        match nef.compression {
+            RawCompression::Lossy | RawCompression::LossyAfterSplit => {
+                assert_dithered(&nef, &decoded)
+            }
+            _ => assert!(decoded == nef.samples, "{name}: samples differ"),
+        }
+    }
+}
+
+#[test]
+fn reads_the_linearization_table() {
+    for nef in all_variants() {
+        let name = variant_name(&nef);
+        let nef_file = nef.write(&format!("table_{name}.NEF")).open();
+        match nef.linearization_table() {
+            Some(table) => assert_eq!(nef_file.linearization_table().unwrap(), table, "{name}"),
+            None => assert!(nef_file.linearization_table().is_err(), "{name}"),
+        }
+    }
+}
+
+#[test]
+fn raw_image_carries_levels_and_cfa() {
+    for bps in [12, 14] {
+        let nef = SyntheticNef::new(bps, RawCompression::Lossless);
+        let raw_image = nef
+            .write(&format!("raw_image_{bps}.NEF"))
+            .open()
+            .raw_image()
+            .unwrap();
+        assert_eq!((raw_image.width, raw_image.height), (nef.width, nef.height));
+        assert_eq!(raw_image.cfa, [0, 1, 1, 2]);
+        assert_eq!(raw_image.black_levels, [BLACK_LEVEL >> (14 - bps); 4]);
+        assert_eq!(raw_image.data, nef.samples);
+    }
+}
+
+#[test]
+fn small_sizes_round_trip() {
+    // Odd heights and widths that are not a multiple of the 16 pixels
+    // Nikon bodies use; odd widths cannot be encoded
+    for (width, height) in [(2, 1), (2, 2), (6, 3), (130, 7)] {
+        let nef = SyntheticNef::new(14, RawCompression::Lossless)
+            .endian(Endian::Little)
+            .size(width, height);
+        let name = format!("small_{width}x{height}.NEF");
+        let decoded = nef.write(&name).open().parse_raw_image_data().unwrap();
+        assert_eq!(decoded, nef.samples, "{name}");
+    }
+}
+
+#[test]
+fn decoder_rejects_odd_widths() {
+    let nef = SyntheticNef::new(12, RawCompression::Lossless).size(2, 2);
+    let table = nef.linearization_table().unwrap();
+    let result = decode_nikon(&nef.strip(), 3, 2, 12, &table);
+    assert!(matches!(result, Err(NefError::InvalidMosaic(_))));
+}
+
+#[test]
+fn fixture_strips_match_hand_written_bytes() {
+    let strip = |bps, compression, endian, samples: &[u16]| {
+        SyntheticNef::new(bps, compression)
+            .endian(endian)
+            .size(2, samples.len() / 2)
+            .samples(samples.to_vec())
+            .strip()
+    };
+    let (big, little) = (Endian::Big, Endian::Little);
+    let uncompressed = RawCompression::Uncompressed;
+    let samples = [0x1234, 0x0ABC];
+    assert_eq!(
+        strip(14, uncompressed, big, &samples),
+        [0x12, 0x34, 0x0A, 0xBC]
+    );
+    assert_eq!(
+        strip(14, uncompressed, little, &samples),
+        [0x34, 0x12, 0xBC, 0x0A]
+    );
+
+    // Back to back, most significant bits first; little-endian files fill
+    // each byte from its least significant bit
+    let packed = RawCompression::Packed;
+    assert_eq!(strip(12, packed, big, &[0x123, 0x456]), [0x12, 0x34, 0x56]);
+    assert_eq!(
+        strip(12, packed, little, &[0x123, 0x456]),
+        [0x23, 0x61, 0x45]
+    );
+    assert_eq!(strip(14, packed, big, &samples), [0x48, 0xD0, 0xAB, 0xC0]);
+
+    // The predictors are the samples themselves, so every difference is 0,
+    // `11110` in the 12-bit lossless tree, and the stream is padded with zeros
+    let lossless = strip(12, RawCompression::Lossless, big, &[2051, 2047, 2048, 2053]);
+    assert_eq!(lossless, [0b1111_0111, 0b1011_1101, 0b1110_0000]);
+}
+
+#[test]
+fn extreme_samples_round_trip() {
+    for bps in [12, 14] {
+        let max = (1 << bps) - 1;
+        let samples = (0..64 * 48)
+            .map(|index| if index % 3 == 0 { max } else { 0 })
+            .collect();
+        let nef = SyntheticNef::new(bps, RawCompression::Lossless).samples(samples);
+        let (stream, table) =
+            encode_nikon_lossless(&nef.samples, nef.width, nef.height, bps).unwrap();
+        let decoded = decode_nikon(&stream, nef.width, nef.height, bps, &table).unwrap();
+        assert_eq!(decoded, nef.samples, "{bps} bits");
+    }
+}
+
+#[test]
+fn encoder_rejects_unsuitable_mosaics() {
+    let invalid = |result: Result<(Vec<u8>, LinearizationTable), NefError>| {
+        matches!(result, Err(NefError::InvalidMosaic(_)))
+    };
+    assert!(invalid(encode_nikon_lossless(&[0; 12], 3, 4, 12)));
+    assert!(invalid(encode_nikon_lossless(&[0; 12], 4, 4, 12)));
+    assert!(invalid(encode_nikon_lossless(&[0; 16], 4, 4, 16)));
+    assert!(invalid(encode_nikon_lossless(&[4096; 16], 4, 4, 12)));
+}
+
+#[test]
+fn truncated_stream_is_an_error() {
+    let nef = SyntheticNef::new(12, RawCompression::Lossless);
+    let table = nef.linearization_table().unwrap();
+    let stream = nef.strip();
+    let result = decode_nikon(
+        &stream[..stream.len() / 2],
+        nef.width,
+        nef.height,
+        12,
+        &table,
+    );
+    assert!(matches!(result, Err(NefError::CorruptHuffmanStream { .. })));
+}
+
+/// A DNG style file whose raw IFD claims `width` x `height` samples stored as
+/// one lossless JPEG strip of 2 x 2 samples.
+fn lossless_jpeg_dng(name: &str, width: u32, height: u32) -> Fixture {
+    let jpeg = LosslessJpeg {
+        width: 2,
+        height: 2,
+        components: 1,
+        precision: 12,
+        predictor: 1,
+        data: vec![100, 200, 300, 400],
+    };
+    let mut raw = TiffDirectory::new();
+    raw.set(IfdEntryTag::NewSubfileType, IfdValue::Longs(vec![0]));
+    raw.set(IfdEntryTag::ImageWidth, IfdValue::Longs(vec![width]));
+    raw.set(IfdEntryTag::ImageLength, IfdValue::Longs(vec![height]));
+    raw.set(IfdEntryTag::BitsPerSample, IfdValue::Shorts(vec![12]));
+    raw.set(IfdEntryTag::Compression, IfdValue::Shorts(vec![7]));
+    raw.set(
+        IfdEntryTag::PhotometricInterpretation,
+        IfdValue::Shorts(vec![32803]),
+    );
+    raw.set(IfdEntryTag::RowsPerStrip, IfdValue::Longs(vec![height]));
+    raw.set_blocks(
+        IfdEntryTag::StripOffsets,
+        IfdEntryTag::StripByteCounts,
+        vec![jpeg.encode().unwrap()],
+    );
+    Fixture::new(name, &raw.to_tiff().unwrap())
+}
+
+#[test]
+fn lossless_jpeg_strips_decode() {
+    let decoded = lossless_jpeg_dng("ljpeg_2x2.dng", 2, 2)
+        .open()
+        .parse_raw_image_data()
+        .unwrap();
+    assert_eq!(decoded, [100, 200, 300, 400]);
+}
+
+#[test]
+fn lossless_jpeg_rejects_unusable_sizes() {
+    // No samples at all, and strips narrower than the image
+    for (width, height) in [(0, 2), (4, 2)] {
+        let name = format!("ljpeg_{width}x{height}.dng");
+        let result = lossless_jpeg_dng(&name, width, height)
+            .open()
+            .parse_raw_image_data();
+        assert!(
+            matches!(result, Err(NefError::InvalidMosaic(_))),
+            "{width}x{height}: {result:?}"
+        );
+    }
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{RawCompression, SyntheticNef, WB_RB_LEVELS};
+use read_nef::color::ColorSpace;
+use read_nef::develop::{Develop, Highlights};
+
+/// A 14-bit mosaic of one value per CFA color, RGGB as the fixtures write.
+fn flat(rgb: [u16; 3]) -> SyntheticNef {
+    let nef = SyntheticNef::new(14, RawCompression::Uncompressed);
+    let samples = (0..nef.width * nef.height)
+        .map(|index| {
+            let (row, col) = (index / nef.width, index % nef.width);
+            rgb[(row & 1) + (col & 1)]
+        })
+        .collect();
+    nef.samples(samples)
+}
+
+#[test]
+fn neutral_subjects_develop_to_gray() {
+    // A quarter of the D7500 range of 400..=15520 above black, divided by the
+    // as-shot multipliers of 1.875 and 1.375 for red and blue
+    let [(red, _), (blue, _)] = WB_RB_LEVELS;
+    assert_eq!((red, blue), (1875, 1375));
+    let nef_file = flat([2416, 4180, 3149]).write("develop_gray.NEF").open();
+    let image = Develop::new().develop_rgb8(&nef_file).unwrap();
+    // Linear 0.25 is 137 after the sRGB curve
+    for pixel in image.pixels() {
+        for value in pixel.0 {
+            assert!(value.abs_diff(137) <= 1, "{pixel:?}");
+        }
+    }
+}
+
+#[test]
+fn saturated_pixels_blend_to_white() {
+    let nef_file = flat([16383; 3]).write("develop_saturated.NEF").open();
+    let develop = Develop::new().highlights(Highlights::Blend);
+    let image = develop.develop_rgb8(&nef_file).unwrap();
+    assert!(image.pixels().all(|pixel| pixel.0 == [255; 3]));
+}
+
+#[test]
+fn unknown_bodies_fall_back_to_a_generic_matrix() {
+    let known = flat([2416, 4180, 3149]).write("develop_known.NEF").open();
+    let unknown = flat([2416, 4180, 3149])
+        .model("NIKON Z 99")
+        .write("develop_unknown.NEF")
+        .open();
+    assert!(unknown.camera().is_none());
+    assert_eq!(
+        unknown.color_matrix(ColorSpace::Srgb),
+        known.color_matrix(ColorSpace::Srgb)
+    );
+    let image = Develop::new().develop_rgb8(&unknown).unwrap();
+    assert_eq!((image.width(), image.height()), (64, 48));
+}
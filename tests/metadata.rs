@@ -0,0 +1,211 @@
+mod common;
+
+use common::{
+    BLACK_LEVEL, Fixture, MAKE, MODEL, PREVIEW_SIZE, RawCompression, SERIAL_NUMBER, SyntheticNef,
+    WB_RB_LEVELS, variant_name,
+};
+use read_nef::dng::{DngCompression, DngOptions, dng};
+use read_nef::ifd::{Ifd, IfdEntryTag, IfdRole, IfdValue};
+use read_nef::makernote::NefCompression;
+use read_nef::nef::PreviewSource;
+use read_nef::utils::Endian;
+
+#[test]
+fn finds_every_directory() {
+    for endian in [Endian::Big, Endian::Little] {
+        let nef = SyntheticNef::new(14, RawCompression::Lossless).endian(endian);
+        let nef_file = nef.write(&format!("ifds_{endian:?}.NEF")).open();
+
+        let raw_ifd = nef_file.raw_ifd().expect("raw IFD");
+        assert_eq!(raw_ifd.endian, endian);
+        assert!(nef_file.exif_ifd().is_some());
+        let makernote_ifd = nef_file.makernote_ifd().expect("MakerNote IFD");
+        assert_eq!(makernote_ifd.endian, endian);
+        assert_ne!(makernote_ifd.base_offset, 0);
+        let previews = nef_file
+            .ifds
+            .iter()
+            .filter(|ifd| ifd.role == IfdRole::Preview)
+            .count();
+        // The IFD0 thumbnail and the JPEG preview
+        assert_eq!(previews, 2);
+        assert_eq!(
+            (nef_file.image_data.width, nef_file.image_data.height),
+            (nef.width, nef.height)
+        );
+    }
+}
+
+#[test]
+fn reads_capture_metadata() {
+    let nef_file = SyntheticNef::new(12, RawCompression::Packed)
+        .write("capture.NEF")
+        .open();
+    let meta = &nef_file.meta_data;
+    assert_eq!(meta.make.as_deref(), Some(MAKE));
+    assert_eq!(meta.model.as_deref(), Some(MODEL));
+    assert_eq!(meta.iso_speed_ratings, Some(200));
+    assert_eq!(meta.exposure_time, Some(1.0 / 250.0));
+    assert_eq!(meta.f_number, Some(5.6));
+    assert_eq!(meta.focal_length, Some(35.0));
+    assert_eq!(
+        meta.date_time_original.as_deref(),
+        Some("2024:05:01 12:30:45")
+    );
+    assert!(nef_file.camera().is_some());
+}
+
+#[test]
+fn reads_the_maker_note() {
+    for compression in RawCompression::ALL {
+        let nef = SyntheticNef::new(14, compression);
+        let name = variant_name(&nef);
+        let nef_file = nef.write(&format!("makernote_{name}.NEF")).open();
+        let maker_note = nef_file.maker_note().expect("MakerNote");
+        assert_eq!(maker_note.version.as_deref(), Some("0211"), "{name}");
+        assert_eq!(maker_note.quality.as_deref(), Some("RAW"), "{name}");
+        assert_eq!(maker_note.serial_number.as_deref(), Some(SERIAL_NUMBER));
+        assert_eq!(maker_note.shutter_count, Some(12345));
+        let expected = match compression {
+            RawCompression::Uncompressed => NefCompression::Uncompressed,
+            RawCompression::Packed => NefCompression::Packed14Bit,
+            RawCompression::Lossless => NefCompression::Lossless,
+            RawCompression::Lossy => NefCompression::LossyType1,
+            RawCompression::LossyAfterSplit => NefCompression::LossyType2,
+        };
+        assert_eq!(maker_note.nef_compression, Some(expected), "{name}");
+
+        let [(red, red_scale), (blue, blue_scale)] = WB_RB_LEVELS;
+        let wb = [
+            red as f32 / red_scale as f32,
+            1.0,
+            blue as f32 / blue_scale as f32,
+        ];
+        assert_eq!(nef_file.as_shot_wb(), Some(wb), "{name}");
+    }
+}
+
+#[test]
+fn extracts_the_preview() {
+    let nef_file = SyntheticNef::new(12, RawCompression::Lossless)
+        .write("preview.NEF")
+        .open();
+    let previews = nef_file.previews();
+    assert_eq!(previews.len(), 1);
+    assert_eq!(previews[0].source, PreviewSource::SubIfd);
+    let (width, height) = PREVIEW_SIZE;
+    assert_eq!(
+        (previews[0].width, previews[0].height),
+        (width as usize, height as usize)
+    );
+
+    let jpeg = nef_file.extract_largest_preview().unwrap();
+    let image = image::load_from_memory(jpeg).unwrap();
+    assert_eq!((image.width(), image.height()), PREVIEW_SIZE);
+}
+
+#[test]
+fn dng_keeps_the_raw_data() {
+    for compression in [DngCompression::Uncompressed, DngCompression::LosslessJpeg] {
+        for bps in [12, 14] {
+            let nef = SyntheticNef::new(bps, RawCompression::Lossless);
+            let name = format!("dng_{compression:?}_{bps}");
+            let nef_file = nef.write(&format!("{name}.NEF")).open();
+            let options = DngOptions::new().compression(compression);
+            let dng_file = Fixture::new(&format!("{name}.dng"), &dng(&nef_file, &options).unwrap());
+            let reread = dng_file.open();
+            assert_eq!(
+                reread.parse_raw_image_data().unwrap(),
+                nef.samples,
+                "{name}"
+            );
+            assert_eq!(reread.meta_data.model.as_deref(), Some(MODEL), "{name}");
+        }
+    }
+}
+
+#[test]
+fn dng_carries_the_required_tags() {
+    // D7500 XYZ to camera matrix, scaled by 10000
+    let d7500 = [8813, -3210, -1036, -4703, 12868, 2021, -1054, 1940, 6129];
+    let color_matrix = IfdValue::SRationals(d7500.map(|value| (value, 10000)).to_vec());
+
+    for (model, white_level) in [(MODEL, 15520), ("NIKON Z 99", 16383)] {
+        let nef = SyntheticNef::new(14, RawCompression::Lossless).model(model);
+        let nef_file = nef.write(&format!("tags_{model}.NEF")).open();
+        let options = DngOptions::new().preview(false);
+        let dng_file = Fixture::new(
+            &format!("tags_{model}.dng"),
+            &dng(&nef_file, &options).unwrap(),
+        );
+        let reread = dng_file.open();
+        let buffer = reread.buffer();
+        let value = |ifd: &Ifd, tag| {
+            ifd.get_entry(tag)
+                .unwrap_or_else(|| panic!("{model}: {tag:?} missing"))
+                .get_value(buffer)
+                .unwrap()
+        };
+
+        // Without a preview the raw data is in IFD0
+        let ifd0 = &reread.ifds[0];
+        assert_eq!(
+            value(ifd0, IfdEntryTag::DNGVersion),
+            IfdValue::Bytes(vec![1, 4, 0, 0])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::UniqueCameraModel),
+            IfdValue::Ascii(format!("{MAKE} {model}"))
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::ColorMatrix1),
+            color_matrix,
+            "{model}"
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::CalibrationIlluminant1),
+            IfdValue::Shorts(vec![21])
+        );
+        // 1 / 1.875 and 1 / 1.375, the inverse of the as-shot multipliers
+        assert_eq!(WB_RB_LEVELS, [(1875, 1000), (1375, 1000)]);
+        assert_eq!(
+            value(ifd0, IfdEntryTag::AsShotNeutral),
+            IfdValue::Rationals(vec![
+                (533333, 1_000_000),
+                (1_000_000, 1_000_000),
+                (727273, 1_000_000),
+            ])
+        );
+
+        assert_eq!(
+            value(ifd0, IfdEntryTag::PhotometricInterpretation),
+            IfdValue::Shorts(vec![32803])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::CFARepeatPatternDim),
+            IfdValue::Shorts(vec![2, 2])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::CFAPattern),
+            IfdValue::Bytes(vec![0, 1, 1, 2])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::BlackLevel),
+            IfdValue::Shorts(vec![BLACK_LEVEL; 4])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::WhiteLevel),
+            IfdValue::Longs(vec![white_level]),
+            "{model}"
+        );
+        let (width, height) = (nef.width as u32, nef.height as u32);
+        assert_eq!(
+            value(ifd0, IfdEntryTag::ActiveArea),
+            IfdValue::Longs(vec![0, 0, height, width])
+        );
+        assert_eq!(
+            value(ifd0, IfdEntryTag::DefaultCropSize),
+            IfdValue::Longs(vec![width, height])
+        );
+    }
+}